@@ -17,16 +17,24 @@ use engine::{
     error::{SgleamError, show_error},
     format,
     gleam::{Project, find_imports, get_module},
+    lint::LintOptions,
     quickjs::QuickJsEngine,
-    repl::{DEBUG, HELP, QUIT, Repl, ReplOutput, STEPPER, THEME, TIME, TYPE, welcome_message},
-    run::{copy_files_and_build, run_check, run_main, run_test},
+    repl::{
+        BENCH, BROWSE, DEBUG, DOC, ENV, FORGET, HELP, INFO, LOAD, PROFILE, QUIT, RELOAD, RESET,
+        Repl, ReplOutput, SAVE, SEARCH, STEPPER, THEME, TIME, TRACE, TYPE, UNDO, UNTRACE,
+        welcome_message,
+    },
+    run::{
+        copy_files_and_build_all, make_relative_to_current_dir, run_check, run_lint, run_main,
+        run_test,
+    },
     stepper::build_stepper,
-    substitution::SubstitutionModule,
-};
-use gleam_core::{
-    error::{FileIoAction, FileKind},
-    javascript::set_bigint_enabled,
+    substitution::{
+        StepperOptions, SubstitutionModule,
+        export::{TraceFormat, export_trace},
+    },
 };
+use gleam_core::javascript::set_bigint_enabled;
 
 /// Use Number instead of BigInt for integers
 fn number_arg() -> impl bpaf::Parser<bool> {
@@ -54,6 +62,12 @@ enum Command {
     Run {
         #[bpaf(external(number_arg))]
         number: bool,
+        /// Print the calls to these functions (comma separated).
+        #[bpaf(long, argument("NAMES"))]
+        trace: Vec<String>,
+        /// Report the calls and times of the functions.
+        #[bpaf(long)]
+        profile: bool,
         /// Gleam file to run.
         #[bpaf(positional("FILE"))]
         file: String,
@@ -63,6 +77,12 @@ enum Command {
     Test {
         #[bpaf(external(number_arg))]
         number: bool,
+        /// Show the lines, `case` clauses and functions executed by the tests.
+        #[bpaf(long)]
+        coverage: bool,
+        /// Write the coverage in the lcov format to this file.
+        #[bpaf(long, argument("FILE"))]
+        lcov: Option<String>,
         /// Gleam file to test.
         #[bpaf(positional("FILE"))]
         file: String,
@@ -86,9 +106,41 @@ enum Command {
         #[bpaf(positional("FILE"))]
         file: String,
     },
+    /// Check a program against the design recipe.
+    #[bpaf(command)]
+    Lint {
+        #[bpaf(external(number_arg))]
+        number: bool,
+        /// Print the problems as json.
+        #[bpaf(long)]
+        json: bool,
+        /// Maximum number of lines of a function (default 30).
+        #[bpaf(long, argument("N"))]
+        max_lines: Option<usize>,
+        /// Gleam file to check.
+        #[bpaf(positional("FILE"))]
+        file: String,
+    },
     /// Show substitution steps for a program.
     #[bpaf(command)]
     Stepper {
+        #[bpaf(external(number_arg))]
+        number: bool,
+        /// Expression to step through instead of main, in the scope of the file.
+        #[bpaf(long, argument("EXPR"))]
+        expr: Option<String>,
+        /// Print the steps as json, markdown or html instead of showing them.
+        #[bpaf(long, argument("FORMAT"))]
+        format: Option<TraceFormat>,
+        /// Maximum number of steps (default 1000).
+        #[bpaf(long, argument("N"))]
+        max_steps: Option<usize>,
+        /// Show calls to this function as a single step (can be repeated).
+        #[bpaf(long, argument("NAME"))]
+        collapse: Vec<String>,
+        /// Show calls deeper than N as a single step.
+        #[bpaf(long, argument("N"))]
+        max_depth: Option<usize>,
         /// Gleam file to step through.
         #[bpaf(positional("FILE"))]
         file: String,
@@ -101,7 +153,14 @@ enum Command {
 fn cli() -> bpaf::OptionParser<Option<Command>> {
     let number = number_arg();
     let file = bpaf::positional::<String>("FILE");
-    let file_as_run = bpaf::construct!(Command::Run { number, file });
+    let trace = bpaf::pure(vec![]);
+    let profile = bpaf::pure(false);
+    let file_as_run = bpaf::construct!(Command::Run {
+        number,
+        trace,
+        profile,
+        file
+    });
     let cmd = bpaf::construct!([command(), file_as_run]).optional();
     bpaf::construct!(cmd)
         .to_options()
@@ -143,6 +202,8 @@ fn run() -> Result<(), SgleamError> {
             | Command::Run { number: true, .. }
             | Command::Test { number: true, .. }
             | Command::Check { number: true, .. }
+            | Command::Lint { number: true, .. }
+            | Command::Stepper { number: true, .. }
     );
     set_bigint_enabled(!number);
 
@@ -160,16 +221,33 @@ fn run() -> Result<(), SgleamError> {
             let paths = paths.as_slice();
             run_interactive(paths, quiet)
         }
-        Command::Run { file, .. } => {
+        Command::Run {
+            file,
+            trace,
+            profile,
+            ..
+        } => {
             let file = make_relative_to_current_dir(file.into())?;
             let files = find_imports(vec![file])?;
-            run_main(&files)
+            let trace: Vec<_> = trace
+                .iter()
+                .flat_map(|names| names.split(','))
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect();
+            run_main(&files, &trace, profile)
         }
-        Command::Test { file, .. } => {
+        Command::Test {
+            file,
+            coverage,
+            lcov,
+            ..
+        } => {
             let file = make_relative_to_current_dir(file.into())?;
             let user_files = vec![file];
             let files = find_imports(user_files.clone())?;
-            run_test(&user_files, &files)
+            let lcov = lcov.map(Utf8PathBuf::from);
+            run_test(&user_files, &files, coverage, lcov.as_deref())
         }
         Command::Format { check, files } => {
             let paths = files
@@ -183,10 +261,44 @@ fn run() -> Result<(), SgleamError> {
             let files = find_imports(vec![file])?;
             run_check(&files)
         }
-        Command::Stepper { file } => {
+        Command::Lint {
+            json,
+            max_lines,
+            file,
+            ..
+        } => {
             let file = make_relative_to_current_dir(file.into())?;
-            let steps = build_stepper(file)?;
-            if std::io::stdout().is_terminal() {
+            let user_files = vec![file];
+            let files = find_imports(user_files.clone())?;
+            let mut options = LintOptions::default();
+            if let Some(max_lines) = max_lines {
+                options.max_lines = max_lines;
+            }
+            run_lint(&user_files, &files, &options, json)
+        }
+        Command::Stepper {
+            expr,
+            format,
+            max_steps,
+            collapse,
+            max_depth,
+            file,
+            ..
+        } => {
+            let file = make_relative_to_current_dir(file.into())?;
+            let mut options = StepperOptions {
+                collapse: collapse.into_iter().map(Into::into).collect(),
+                max_depth,
+                ..StepperOptions::default()
+            };
+            if let Some(max_steps) = max_steps {
+                options.max_steps = max_steps;
+            }
+            let steps = build_stepper(file, expr.as_deref(), options)?;
+            if let Some(format) = format {
+                print!("{}", export_trace(&steps, format));
+                Ok(())
+            } else if std::io::stdout().is_terminal() {
                 Ok(stepper_display::display_stepper(&steps)?)
             } else {
                 for (index, step) in steps.iter().enumerate() {
@@ -201,38 +313,10 @@ fn run() -> Result<(), SgleamError> {
     }
 }
 
-fn make_relative_to_current_dir(path: Utf8PathBuf) -> Result<Utf8PathBuf, SgleamError> {
-    let current_dir = canonicalise(get_current_dir()?)?;
-    canonicalise(path.clone())?
-        .strip_prefix(&current_dir)
-        .map(|p| Utf8PathBuf::from(p.as_str().replace('\\', "/")))
-        .map_err(|_| SgleamError::PathNotInCurrentDir { current_dir, path })
-}
-
-fn canonicalise(path: Utf8PathBuf) -> Result<Utf8PathBuf, gleam_core::Error> {
-    path.canonicalize_utf8()
-        .map_err(|e| gleam_core::Error::FileIo {
-            kind: FileKind::File,
-            action: FileIoAction::Canonicalise,
-            path,
-            err: Some(e.to_string()),
-        })
-}
-
-fn get_current_dir() -> Result<Utf8PathBuf, gleam_core::Error> {
-    let curr_dir = std::env::current_dir().map_err(|e| gleam_core::Error::FileIo {
-        kind: FileKind::Directory,
-        action: FileIoAction::Open,
-        path: ".".into(),
-        err: Some(e.to_string()),
-    })?;
-    Utf8PathBuf::from_path_buf(curr_dir.clone())
-        .map_err(|_| gleam_core::Error::NonUtf8Path { path: curr_dir })
-}
-
 const COMPLETION_EXTRAS: &[&str] = &[
     // REPL commands
-    QUIT, TYPE, TIME, STEPPER, DEBUG, HELP, THEME, // Keywords and builtins
+    QUIT, TYPE, TIME, STEPPER, LOAD, RELOAD, SAVE, BROWSE, INFO, DOC, SEARCH, ENV, FORGET, RESET,
+    UNDO, BENCH, TRACE, UNTRACE, PROFILE, DEBUG, HELP, THEME, // Keywords and builtins
     "let", "fn", "type", "import", "case", "pub", "const", "assert", "use", "if", "else", "True",
     "False", "Nil", "Ok", "Error", "panic", "todo",
 ];
@@ -252,7 +336,7 @@ fn run_interactive(paths: &[Utf8PathBuf], quiet: bool) -> Result<(), SgleamError
     };
 
     let mut project = Project::default();
-    let modules = copy_files_and_build(&mut project, &paths)?;
+    let modules = copy_files_and_build_all(&mut project, &paths)?;
     let module = paths.first().and_then(|input| {
         let name = input.with_extension("");
         let name = name.as_str().replace('\\', "/");
@@ -261,7 +345,7 @@ fn run_interactive(paths: &[Utf8PathBuf], quiet: bool) -> Result<(), SgleamError
     let substitution_module = build_substitution_module(&modules);
 
     let mut repl = Repl::<QuickJsEngine>::new(project, module)?;
-    repl.set_substitution_module(substitution_module);
+    repl.merge_substitution_module(substitution_module);
     let completions = repl_reader::Completions::default();
     update_completions(&repl, &completions);
     let reader = repl_reader::ReplReader::new(completions.clone())
@@ -274,7 +358,28 @@ fn run_interactive(paths: &[Utf8PathBuf], quiet: bool) -> Result<(), SgleamError
             println!("  :help          Show this help");
             println!("  :quit          Exit the REPL");
             println!("  :type <expr>   Show the type of an expression");
+            println!("  :time [-n N] <expr>  Show the time to evaluate an expression");
+            println!("  :bench [-n N] <exprs>  Compare the times to evaluate expressions");
             println!("  :stepper <expr>  Show substitution steps for an expression");
+            println!("                 (options: --max-steps N, --collapse NAME, --max-depth N)");
+            println!(
+                "  :trace <names> Print the calls to functions with their arguments and results"
+            );
+            println!("  :untrace <names>  Stop printing the calls to functions");
+            println!(
+                "  :profile <expr>  Show the calls and times of the functions used by an expression"
+            );
+            println!("  :load <file>   Load a file, keeping the definitions that are still valid");
+            println!("  :reload        Load the last loaded file again");
+            println!("  :save <file>   Save the session definitions to a file");
+            println!("  :browse <module>  List the public types and values of a module");
+            println!("  :info <name>   Show the type, origin and definition of a name");
+            println!("  :doc <name>    Show the documentation of a name");
+            println!("  :search <type> Find the values that have a type");
+            println!("  :env           List the bindings of the session");
+            println!("  :forget <name> Remove a binding");
+            println!("  :reset         Remove all bindings");
+            println!("  :undo          Undo the last change to the bindings");
             println!("  :theme         Show the current theme");
             println!("  :theme light   Switch to One Light theme");
             println!("  :theme dark    Switch to One Dark theme");
@@ -324,12 +429,12 @@ fn run_interactive(paths: &[Utf8PathBuf], quiet: bool) -> Result<(), SgleamError
     Ok(())
 }
 
-fn build_substitution_module(modules: &[gleam_core::build::Module]) -> Option<SubstitutionModule> {
+fn build_substitution_module(modules: &[gleam_core::build::Module]) -> SubstitutionModule {
     let mut result = SubstitutionModule::default();
     for module in modules {
         result.merge(SubstitutionModule::from_module(module));
     }
-    Some(result)
+    result
 }
 
 fn update_completions(repl: &Repl<QuickJsEngine>, completions: &repl_reader::Completions) {
@@ -1,11 +1,14 @@
-use std::io::{self, Read, Write};
+use std::{
+    io::{self, Read, Write},
+    ops::Range,
+};
 
 use crossterm::{
     cursor, execute, queue,
     style::{self, Stylize},
     terminal::{self, ClearType},
 };
-use engine::substitution::SubstitutionStep;
+use engine::substitution::{SubstitutionStep, called_functions, lines_with_span, next_call};
 
 use crate::repl_reader::highlight_gleam;
 
@@ -38,12 +41,28 @@ fn run_stepper(
     steps: &[SubstitutionStep],
     current: &mut usize,
 ) -> Result<(), io::Error> {
+    let functions = called_functions(steps);
+    let mut chosen = 0usize;
     loop {
-        render_frame(stdout, steps, *current)?;
+        render_frame(stdout, steps, *current, functions.get(chosen).copied())?;
         stdout.flush()?;
 
         let key = read_key()?;
         match key {
+            KeyCode::Home | KeyCode::Char('g') => *current = 0,
+            KeyCode::End | KeyCode::Char('e') | KeyCode::Char('G') => {
+                *current = steps.len() - 1;
+            }
+            KeyCode::Char('f') if !functions.is_empty() => {
+                chosen = (chosen + 1) % functions.len();
+            }
+            KeyCode::Char('n') => {
+                if let Some(function) = functions.get(chosen)
+                    && let Some(next) = next_call(steps, *current, function)
+                {
+                    *current = next;
+                }
+            }
             KeyCode::Right | KeyCode::Down | KeyCode::Char('l') | KeyCode::Char('j')
                 if *current < steps.len() =>
             {
@@ -70,6 +89,8 @@ enum KeyCode {
     Down,
     Left,
     Right,
+    Home,
+    End,
     Char(char),
     Esc,
     CtrlC,
@@ -95,6 +116,8 @@ fn read_key() -> Result<KeyCode, io::Error> {
                     66 => Ok(KeyCode::Down),
                     67 => Ok(KeyCode::Right),
                     68 => Ok(KeyCode::Left),
+                    70 => Ok(KeyCode::End),
+                    72 => Ok(KeyCode::Home),
                     _ => Ok(KeyCode::Other),
                 }
             } else {
@@ -110,6 +133,7 @@ fn render_frame(
     stdout: &mut io::Stdout,
     steps: &[SubstitutionStep],
     current: usize,
+    chosen: Option<&str>,
 ) -> Result<(), io::Error> {
     let (cols, rows) = terminal::size().unwrap_or((80, 24));
     let cols = cols as usize;
@@ -138,7 +162,12 @@ fn render_frame(
     } else {
         " Stepper - Finished ".to_string()
     };
-    let help_str = " q: quit, arrows: navigate ";
+    let help_str = match chosen {
+        Some(function) => {
+            format!(" q: quit, arrows: navigate, e: end, n: next {function}, f: next function ")
+        }
+        None => " q: quit, arrows: navigate, e: end ".to_string(),
+    };
 
     let available_width = cols.saturating_sub(7);
     let col_left_w = available_width / 2;
@@ -148,7 +177,7 @@ fn render_frame(
     let right_fill = col_right_w + 1;
 
     let title_trunc = truncate_str(&title_str, left_fill);
-    let help_trunc = truncate_str(help_str, right_fill);
+    let help_trunc = truncate_str(&help_str, right_fill);
 
     let title_len = title_trunc.chars().count();
     let left_pad = left_fill.saturating_sub(title_len);
@@ -220,11 +249,15 @@ fn render_frame(
         style::PrintStyledContent("─┤\r\n".cyan())
     )?;
 
+    // The left side shows the redex of the transition to the right side, and
+    // the right side its result.
+    let redex = right_step.and_then(|s| s.redex.as_ref());
     let left_lines: Vec<String> = left_step
-        .map(|s| s.formatted.lines().map(highlight_gleam).collect())
+        .map(|s| highlight_step(&s.formatted, redex, REDEX_BG))
         .unwrap_or_default();
+    let result = right_step.and_then(|s| s.result.as_ref());
     let right_lines: Vec<String> = right_step
-        .map(|s| s.formatted.lines().map(highlight_gleam).collect())
+        .map(|s| highlight_step(&s.formatted, result, RESULT_BG))
         .unwrap_or_default();
 
     let context_lines: Vec<String> = right_step
@@ -303,6 +336,32 @@ fn render_frame(
     Ok(())
 }
 
+const RESET: &str = "\x1b[0m";
+const REDEX_BG: &str = "\x1b[48;5;58m";
+const RESULT_BG: &str = "\x1b[48;5;22m";
+
+/// Highlights the lines of `code`, with the bytes in `span` on the
+/// background `bg`.
+fn highlight_step(code: &str, span: Option<&Range<usize>>, bg: &str) -> Vec<String> {
+    lines_with_span(code, span)
+        .into_iter()
+        .map(|(line, part)| match part {
+            Some(part) => {
+                // The syntax highlighting resets the style after each token,
+                // so the background is set again after each reset.
+                let marked =
+                    highlight_gleam(&line[part.clone()]).replace(RESET, &format!("{RESET}{bg}"));
+                format!(
+                    "{}{bg}{marked}{RESET}{}",
+                    highlight_gleam(&line[..part.start]),
+                    highlight_gleam(&line[part.end..])
+                )
+            }
+            None => highlight_gleam(line),
+        })
+        .collect()
+}
+
 fn truncate_str(s: &str, max_len: usize) -> String {
     if s.chars().count() <= max_len {
         return s.to_string();
@@ -1,5 +1,8 @@
-use engine::repl::{QUIT, STEPPER, TYPE, welcome_message};
-use indoc::formatdoc;
+use engine::repl::{
+    BENCH, BROWSE, DOC, ENV, FORGET, INFO, LOAD, PROFILE, QUIT, RELOAD, RESET, SAVE, SEARCH,
+    STEPPER, TIME, TRACE, TYPE, UNDO, UNTRACE, welcome_message,
+};
+use indoc::{formatdoc, indoc};
 use insta::assert_snapshot;
 
 /// Strip the random 8-hex suffix from internal REPL names so snapshot tests
@@ -385,15 +388,15 @@ fn repl_type_redefine() {
         }),
         "Red"
     );
-    // Cannot redefine type while variables of that type exist
+    // Redefining a type removes the variables of that type
     assert_eq!(
         repl_exec(&formatdoc! {"
             type Val {{ A(Int) }}
             let x = A(42)
             type Val {{ B(String) }}
-            x"
+            B(\"x\")"
         }),
-        "A(42)\nCannot redefine type `Val` while variables of that type exist.\nA(42)"
+        "A(42)\nRemoved the bindings that depend on the previous definition of `Val`: `x`.\nB(\"x\")"
     );
     // Type with name that is substring of another type (e.g. In vs Int)
     // should NOT be blocked by variables of the longer type
@@ -407,6 +410,46 @@ fn repl_type_redefine() {
     );
 }
 
+#[test]
+fn repl_type_redefine_updates_functions() {
+    assert_eq!(
+        repl_exec(&formatdoc! {r#"
+            type Shape {{ Circle(r: Int) }}
+            fn size(s: Shape) {{ s.r }}
+            fn double(s) {{ size(s) * 2 }}
+            fn name(s) {{ case s {{ Circle(_) -> "circle" }} }}
+            let c = Circle(2)
+            type Shape {{ Circle(r: Int) Square(r: Int) }}
+            double(Square(3))"#
+        }),
+        formatdoc! {"
+            Circle(2)
+            Removed the bindings that depend on the previous definition of `Shape`: `c`, `name`.
+            Updated the definitions that use `Shape`: `size`, `double`.
+            6"
+        }
+    );
+}
+
+#[test]
+fn repl_type_redefine_keeps_parameter_named_as_removed_variable() {
+    assert_eq!(
+        repl_exec(&formatdoc! {"
+            type Shape {{ Circle(Int) }}
+            let p = Circle(1)
+            fn area(p: Shape) {{ case p {{ Circle(r) -> 3 * r * r _ -> 0 }} }}
+            type Shape {{ Circle(Int) Square(Int) }}
+            area(Circle(2))"
+        }),
+        formatdoc! {"
+            Circle(1)
+            Removed the bindings that depend on the previous definition of `Shape`: `p`.
+            Updated the definitions that use `Shape`: `area`.
+            12"
+        }
+    );
+}
+
 #[test]
 fn repl_const_replace_let() {
     assert_eq!(
@@ -664,6 +707,669 @@ fn repl_user_module_import() {
     );
 }
 
+#[test]
+fn repl_load_keeps_valid_bindings() {
+    let (out, err) = run_sgleam_cmd_native_only(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            {LOAD}tests/inputs/load_v1.gleam
+            let n = area(Square(3))
+            let s = Square(2)
+            fn twice(x) {{ x + x }}
+            {LOAD}tests/inputs/load_v2.gleam
+            twice(n)
+            area(Square(1.5))
+            s
+            "
+        }),
+    );
+    assert!(
+        out.contains("Loaded `tests/inputs/load_v1.gleam`.\n"),
+        "got: {out}"
+    );
+    assert!(
+        out.contains(
+            "Loaded `tests/inputs/load_v2.gleam`.\n\
+             Removed the definitions that are no longer valid: s.\n\
+             18\n\
+             2.25\n"
+        ),
+        "got: {out}"
+    );
+    assert!(err.contains("Unknown variable"), "got: {err}");
+}
+
+#[test]
+fn repl_reload() {
+    let (out, _) = run_sgleam_cmd_native_only(
+        &["repl", "-q", "tests/inputs/load_v1.gleam"],
+        Some(&formatdoc! {"
+            let n = area(Square(3))
+            {RELOAD}
+            n + 1
+            "
+        }),
+    );
+    assert_eq!(out, "9\nLoaded `tests/inputs/load_v1.gleam`.\n10\n");
+}
+
+#[test]
+fn repl_reload_keeps_values() {
+    let (out, _) = run_sgleam_cmd_native_only(
+        &["repl", "-q", "tests/inputs/load_v1.gleam"],
+        Some(&formatdoc! {"
+            let inc = fn(x) {{ x + 1 }}
+            let s = Square(4)
+            {RELOAD}
+            inc(1)
+            area(s)
+            "
+        }),
+    );
+    assert!(
+        out.ends_with("Loaded `tests/inputs/load_v1.gleam`.\n2\n16\n"),
+        "got: {out}"
+    );
+}
+
+#[test]
+fn repl_load_error_keeps_session() {
+    let (out, err) = run_sgleam_cmd_native_only(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            {RELOAD}
+            let n = 1
+            {LOAD}tests/inputs/missing.gleam
+            n
+            "
+        }),
+    );
+    assert_eq!(out, "No file loaded. Use `:load FILE` first.\n1\n1\n");
+    assert!(err.contains("missing.gleam"), "got: {err}");
+}
+
+#[test]
+fn repl_save() {
+    let dir = temp_dir("repl_save");
+    let path = dir.join("saved_session.gleam");
+    let path = path.to_str().unwrap();
+    let (out, _) = run_sgleam_cmd_native_only(
+        &["repl", "-q"],
+        Some(&formatdoc! {r#"
+            import gleam/int.{{to_string}}
+            const base = 10
+            type Color {{ Red Green }}
+            fn scale(x) {{ x * base }}
+            let n = scale(4)
+            let s = to_string(n) <> string.repeat("!", 2)
+            {SAVE}{path}
+            "#
+        }),
+    );
+    let saved = std::fs::read_to_string(path).unwrap_or_default();
+    let output = assert_cmd::cargo::cargo_bin_cmd!()
+        .current_dir(&dir)
+        .args(["check", "saved_session.gleam"])
+        .output()
+        .expect("run sgleam check");
+    let err = String::from_utf8_lossy(&output.stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let path = path.replace('\\', "/");
+    assert!(
+        out.ends_with(&format!("Saved the session to `{path}`.\n")),
+        "got: {out}"
+    );
+    assert_eq!(
+        saved,
+        indoc! {r#"
+            import gleam/int.{to_string}
+            import gleam/string
+
+            const base = 10
+
+            type Color {
+              Red
+              Green
+            }
+
+            fn scale(x) {
+              x * base
+            }
+
+            pub fn main() {
+              let n = scale(4)
+              let s = to_string(n) <> string.repeat("!", 2)
+            }
+        "#}
+    );
+    assert!(!err.contains("error"), "got: {err}");
+}
+
+#[test]
+fn repl_browse_info_doc() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            {BROWSE}gleam/option
+            let x = 1
+            {INFO}x
+            {INFO}list.map
+            {DOC}list.map
+            {DOC}nothing
+            "
+        }),
+    );
+    assert!(out.contains("type Option(a)\n"), "got: {out}");
+    assert!(out.contains("\nunwrap: fn("), "got: {out}");
+    assert!(
+        out.contains("x: Int\nDefined in the REPL\nlet x = 1\n"),
+        "got: {out}"
+    );
+    assert!(
+        out.contains(
+            "list.map: fn(List(a), fn(a) -> b) -> List(b)\nDefined in gleam/list\npub fn map("
+        ),
+        "got: {out}"
+    );
+    assert!(
+        out.contains("list.map: fn(List(a), fn(a) -> b) -> List(b)\n\n"),
+        "got: {out}"
+    );
+    assert!(out.contains("```gleam"), "got: {out}");
+    assert!(out.ends_with("Unknown name `nothing`.\n"), "got: {out}");
+}
+
+#[test]
+fn repl_search() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            {SEARCH}fn(List(a)) -> Int
+            {SEARCH}fn(List(Int)) -> Int
+            {SEARCH}fn(Nil, Nil, Nil, Nil, Nil) -> Nil
+            "
+        }),
+    );
+    let sum = out.find("int.sum: fn(List(Int)) -> Int\n");
+    let length = out.rfind("list.length: fn(List(a)) -> Int\n");
+    assert!(
+        out.contains("list.length: fn(List(a)) -> Int\n"),
+        "got: {out}"
+    );
+    // In the second search `int.sum` is an exact match and `list.length`
+    // a generalized one.
+    assert!(
+        sum.is_some() && length.is_some() && sum < length,
+        "expected the exact match first, got: {out}"
+    );
+    assert!(
+        out.ends_with("No values found with type `fn(Nil, Nil, Nil, Nil, Nil) -> Nil`.\n"),
+        "got: {out}"
+    );
+}
+
+#[test]
+fn repl_env_forget_reset() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            import gleam/int.{{to_string}}
+            const base = 10
+            type Color {{ Red Green }}
+            fn scale(x) {{ x * base }}
+            let n = scale(4)
+            {ENV}
+            {FORGET}base
+            {FORGET}n
+            {FORGET}n
+            {RESET}
+            {ENV}
+            let m = 1
+            m
+            "
+        }),
+    );
+    assert_eq!(
+        out,
+        indoc! {"
+            40
+            type Color
+            const base: Int
+            let n: Int
+            fn scale: fn(Int) -> Int
+            import to_string: fn(Int) -> String from gleam/int
+            Cannot forget `base` because other definitions depend on it.
+            Forgot `n`.
+            Unknown name `n`.
+            The session was reset.
+            No bindings.
+            1
+            1
+        "}
+    );
+}
+
+#[test]
+fn repl_forget_keeps_the_other_names_of_a_let() {
+    let dir = temp_dir("repl_forget");
+    let path = dir.join("session.gleam");
+    let path = path.to_str().unwrap();
+    let (out, _) = run_sgleam_cmd_native_only(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            let #(a, b) = #(1, 2)
+            {FORGET}a
+            b
+            {SAVE}{path}
+            "
+        }),
+    );
+    let saved = std::fs::read_to_string(path).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(out.contains("Forgot `a`.\n2\n"), "got: {out}");
+    assert!(saved.contains("let #(a, b) = #(1, 2)"), "got: {saved}");
+}
+
+#[test]
+fn repl_undo() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            fn f(x) {{ x + 1 }}
+            fn f(x) {{ x - 1 }}
+            f(10)
+            1 + 1
+            {UNDO}
+            f(10)
+            let f = 5
+            {FORGET}f
+            {UNDO}
+            {UNDO}
+            f(10)
+            {UNDO}
+            {UNDO}
+            "
+        }),
+    );
+    assert_eq!(
+        out,
+        indoc! {"
+            9
+            2
+            Undid the last change.
+            11
+            5
+            Forgot `f`.
+            Undid the last change.
+            Undid the last change.
+            11
+            Undid the last change.
+            Nothing to undo.
+        "}
+    );
+}
+
+#[test]
+fn repl_undo_type_redefinition() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            type Shape {{ Square(Int) }}
+            let s = Square(3)
+            type Shape {{ Circle(Int) }}
+            {UNDO}
+            case s {{ Square(side) -> side * side }}
+            "
+        }),
+    );
+    assert_eq!(
+        out,
+        indoc! {"
+            Square(3)
+            Removed the bindings that depend on the previous definition of `Shape`: `s`.
+            Undid the last change.
+            9
+        "}
+    );
+}
+
+#[test]
+fn repl_time_single_run() {
+    let out = run_sgleam_cmd_stdout(&["repl", "-q"], Some(&format!("{TIME}1 + 2")));
+    let (value, time) = out.split_once('\n').unwrap_or_default();
+    assert_eq!(value, "3", "got: {out}");
+    let (amount, unit) = time
+        .trim_end()
+        .strip_prefix("Time: ")
+        .and_then(|time| time.split_once(' '))
+        .unwrap_or_default();
+    assert!(
+        ["s", "ms", "µs", "ns"].contains(&unit) && amount.parse::<f64>().is_ok(),
+        "got: {out}"
+    );
+}
+
+#[test]
+fn repl_time_runs_and_bench() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            {TIME}-n 5 1 + 2
+            {TIME}-n x 1
+            {BENCH}-n 3 list.range(1, 100) list.range(1, 10)
+            "
+        }),
+    );
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines[0], "3", "got: {out}");
+    assert!(
+        lines[1].starts_with("Time (5 runs): min ") && lines[1].contains(", std dev "),
+        "got: {out}"
+    );
+    assert_eq!(
+        lines[2], ":time -n expects a positive number of runs, got `x`.",
+        "got: {out}"
+    );
+    assert!(
+        lines[3].starts_with("Expression") && lines[3].ends_with("Relative"),
+        "got: {out}"
+    );
+    assert!(lines[4].starts_with("list.range(1, 100)  "), "got: {out}");
+    assert!(lines[5].starts_with("list.range(1, 10)   "), "got: {out}");
+    assert_eq!(lines[6], "3 runs of each expression.", "got: {out}");
+}
+
+#[test]
+fn repl_trace() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            fn sum(l) {{ case l {{ [] -> 0 [x, ..r] -> x + sum(r) }} }}
+            {TRACE}sum
+            sum([1, 2])
+            {UNTRACE}sum
+            sum([1])
+            {TRACE}nope
+            "
+        }),
+    );
+    assert_eq!(
+        out,
+        indoc! {"
+            Tracing `sum`.
+            > sum([1, 2])
+            | > sum([2])
+            | | > sum([])
+            | | < 0
+            | < 2
+            < 3
+            3
+            Stopped tracing `sum`.
+            1
+            `nope` is not a function defined in the REPL or in the loaded file.
+        "}
+    );
+}
+
+#[test]
+fn repl_reset_stops_tracing() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q", "tests/inputs/load_v1.gleam"],
+        Some(&formatdoc! {"
+            fn f(x) {{ x + 1 }}
+            {TRACE}f
+            {TRACE}area
+            {RESET}
+            fn f(x) {{ x * 2 }}
+            f(3)
+            area(Square(2))
+            "
+        }),
+    );
+    assert_eq!(
+        out,
+        indoc! {"
+            Tracing `f`.
+            Tracing `area`.
+            The session was reset.
+            6
+            4
+        "}
+    );
+}
+
+#[test]
+fn repl_trace_file_function_keeps_values() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q", "tests/inputs/load_v1.gleam"],
+        Some(&formatdoc! {"
+            let s = Square(3)
+            {TRACE}area
+            area(s)
+            {UNTRACE}area
+            area(s)
+            "
+        }),
+    );
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines[1], "Tracing `area`.", "got: {out}");
+    assert!(lines[2].starts_with("> area(Square("), "got: {out}");
+    assert_eq!(
+        lines[3..],
+        ["< 9", "9", "Stopped tracing `area`.", "9"],
+        "got: {out}"
+    );
+}
+
+#[test]
+fn run_trace() {
+    let trace = indoc! {"
+        > fact(2)
+        | > fact(1)
+        | | > fact(0)
+        | | < 1
+        | < 1
+        < 2
+    "};
+    let (out, err) = run_sgleam_cmd_native_only(
+        &["run", "--trace", "fact,missing", "tests/inputs/trace.gleam"],
+        None,
+    );
+    assert_eq!(out, format!("{trace}2\n"));
+    assert_eq!(
+        err,
+        "Ignoring `missing`: is not a function of the program.\n"
+    );
+
+    let (out, _) = run_sgleam_cmd_native_only(
+        &["repl", "-q", "tests/inputs/trace.gleam"],
+        Some(&formatdoc! {"
+            {TRACE}fact
+            fact(2)
+            "
+        }),
+    );
+    assert_eq!(out, format!("Tracing `fact`.\n{trace}2\n"));
+}
+
+/// Returns the cells of the row of `function` in a profile report, without
+/// the times.
+fn profile_row(report: &str, function: &str) -> Vec<String> {
+    let line = report
+        .lines()
+        .find(|line| line.starts_with(&format!("{function} ")))
+        .unwrap_or_else(|| panic!("no row for {function} in: {report}"));
+    let cells: Vec<_> = line
+        .split("  ")
+        .map(str::trim)
+        .filter(|cell| !cell.is_empty())
+        .collect();
+    assert_eq!(cells.len(), 6, "got: {line}");
+    [cells[0], cells[1], cells[2], cells[5]]
+        .map(String::from)
+        .to_vec()
+}
+
+#[test]
+fn repl_profile() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {"
+            fn fib(n) {{ case n {{ 0 | 1 -> n _ -> fib(n - 1) + fib(n - 2) }} }}
+            {PROFILE}fib(4)
+            "
+        }),
+    );
+    assert!(
+        out.starts_with("3\nFunction  Location  Calls  Total"),
+        "got: {out}"
+    );
+    assert_eq!(profile_row(&out, "fib"), ["fib", "REPL", "9", "4"]);
+}
+
+#[test]
+fn run_profile() {
+    let (out, err) =
+        run_sgleam_cmd_native_only(&["run", "--profile", "tests/inputs/trace.gleam"], None);
+    assert_eq!(out, "2\n");
+    assert_eq!(
+        profile_row(&err, "fact"),
+        ["fact", "tests/inputs/trace.gleam:8", "3", "3"]
+    );
+    assert_eq!(
+        profile_row(&err, "main"),
+        ["main", "tests/inputs/trace.gleam:4", "1", "1"]
+    );
+}
+
+#[test]
+fn test_coverage() {
+    let dir = temp_dir("test_coverage");
+    let lcov = dir.join("coverage.lcov");
+    let lcov = lcov.to_str().unwrap();
+    let (out, _) = run_sgleam_cmd_native_only(
+        &[
+            "test",
+            "--coverage",
+            "--lcov",
+            lcov,
+            "tests/inputs/coverage.gleam",
+        ],
+        None,
+    );
+    let tracefile = std::fs::read_to_string(lcov).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let listing = indoc! {"
+        Coverage of `tests/inputs/coverage.gleam`:
+                -:    1:import sgleam/check
+                -:    2:
+                -:    3:pub fn sign(n: Int) -> Int {
+                2:    4:  case n {
+                1:    5:    0 -> 0
+                1:    6:    _ if n > 0 -> 1
+            #####:    7:    _ -> -1
+                -:    8:  }
+                -:    9:}
+                -:   10:
+                -:   11:pub fn double(n: Int) -> Int {
+            #####:   12:  n * 2
+                -:   13:}
+    "};
+    assert!(out.contains(listing), "got: {out}");
+    assert!(
+        out.ends_with(indoc! {"
+            Function `double` (line 11) was not called.
+            The `case` clause at line 7 was not executed.
+            Functions: 1/2 (50.0%), lines: 3/5 (60.0%), clauses: 2/3 (66.7%).
+        "}),
+        "got: {out}"
+    );
+    assert!(
+        tracefile.starts_with("TN:\nSF:tests/inputs/coverage.gleam\nFN:3,sign\nFN:11,double\n"),
+        "got: {tracefile}"
+    );
+    assert!(tracefile.contains("BRDA:7,0,2,-\nBRF:3\nBRH:2\n"));
+    assert!(tracefile.ends_with("LF:5\nLH:3\nend_of_record\n"));
+}
+
+#[test]
+fn test_coverage_error_shows_original_source() {
+    let (_, err) = run_sgleam_cmd_native_only(
+        &["test", "--coverage", "tests/inputs/coverage_error.gleam"],
+        None,
+    );
+    for text in [
+        "error: Pattern match failed",
+        "tests/inputs/coverage_error.gleam:4:14",
+        "4 │   let assert 0 = n % 2",
+        "half (tests/inputs/coverage_error.gleam:4)",
+    ] {
+        assert!(err.contains(text), "missing {text:?} in: {err}");
+    }
+    assert!(!err.contains("sgleam_coverage_hit"), "got: {err}");
+}
+
+#[test]
+fn lint() {
+    let (out, err) = run_sgleam_cmd_native_only(&["lint", "tests/inputs/lint.gleam"], None);
+    assert_eq!(out, "");
+    for text in [
+        "warning: Missing type annotation",
+        "The return type of `area` is not annotated.",
+        "The parameter `width` of `area` has no type annotation.",
+        "warning: Missing purpose statement",
+        "`area` has no purpose statement.",
+        "warning: Unused parameter",
+        "The parameter `height` of `area` is not used.",
+        "warning: Examples without checks",
+        "`area_examples` does not check any example of `area`.",
+    ] {
+        assert!(err.contains(text), "missing {text:?} in: {err}");
+    }
+    assert!(!err.contains("`double`"), "got: {err}");
+
+    let (out, _) = run_sgleam_cmd_native_only(
+        &[
+            "lint",
+            "--json",
+            "--max-lines",
+            "2",
+            "tests/inputs/lint.gleam",
+        ],
+        None,
+    );
+    let rules: Vec<_> = out
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("{\"rule\": \""))
+        .filter_map(|line| line.split_once('"'))
+        .map(|(rule, _)| rule)
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            "long-function",
+            "long-function",
+            "missing-annotation",
+            "missing-purpose",
+            "long-function",
+            "missing-annotation",
+            "unused-parameter",
+            "empty-examples",
+            "long-function",
+        ],
+        "got: {out}"
+    );
+    assert!(out.contains(
+        "\"path\": \"tests/inputs/lint.gleam\", \"line\": 12, \"column\": 20, \"title\": \"Unused parameter\""
+    ));
+
+    let (out, _) = run_sgleam_cmd_native_only(&["lint", "tests/inputs/check_eq.gleam"], None);
+    assert_eq!(out, "No problems found.\n");
+}
+
 #[test]
 fn format_stdin() {
     assert_eq!(
@@ -772,8 +1478,8 @@ fn repl_subst_uses_function_from_imported_local_module() {
 }
 
 #[test]
-fn repl_subst_rejects_runtime_only_variable() {
-    let (out, _) = run_sgleam_cmd(
+fn repl_subst_runtime_variable() {
+    let out = run_sgleam_cmd_stdout(
         &["repl", "-q"],
         Some(&formatdoc! {
             "
@@ -782,7 +1488,47 @@ fn repl_subst_rejects_runtime_only_variable() {
         }),
     );
     assert!(
-        out.contains(":stepper does not support REPL variable `x`"),
+        out.contains("3 + 1"),
+        "expected the value of `x` to be substituted, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("4"),
+        "expected final value 4, got: {out}"
+    );
+}
+
+#[test]
+fn repl_subst_runtime_variable_structured_values() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {
+            r#"
+            let xs = [1, 2]
+            let pair = #("a", 1.5)
+            let r = Ok(option.Some(xs))
+            {STEPPER}#(xs, pair, r)"#
+        }),
+    );
+    assert!(
+        out.contains(r#"#([1, 2], #("a", 1.5), Ok(Some([1, 2])))"#),
+        "expected the reified values, got: {out}"
+    );
+}
+
+#[test]
+fn repl_subst_rejects_runtime_function_variable() {
+    let (out, _) = run_sgleam_cmd(
+        &["repl", "-q"],
+        Some(&formatdoc! {
+            "
+            let f = fn(x) {{ x + 1 }}
+            {STEPPER}f(1)"
+        }),
+    );
+    assert!(
+        out.contains(
+            ":stepper does not support REPL variable `f` because its value is a function."
+        ),
         "expected explicit runtime-variable error, got: {out}"
     );
 }
@@ -820,6 +1566,504 @@ fn repl_subst_allows_pattern_shadowing_runtime_variable() {
     );
 }
 
+#[test]
+fn repl_subst_case_guard() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {
+            "
+            pub fn sign(x) {{
+              case x {{
+                n if n > 0 -> 1
+                n if n < 0 -> -1
+                _ -> 0
+              }}
+            }}
+            {STEPPER}sign(0)"
+        }),
+    );
+    assert!(
+        out.contains("case 0 {\n  n if n > 0 -> 1\n  n if n < 0 -> -1\n  _ -> 0\n}"),
+        "expected guarded case after substitution, got: {out}"
+    );
+    assert!(
+        out.contains("case 0 {\n  n if n < 0 -> -1\n  _ -> 0\n}"),
+        "expected first clause removed after its guard failed, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("0"),
+        "expected final value 0, got: {out}"
+    );
+}
+
+#[test]
+fn repl_subst_case_guard_list_values() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {
+            "
+            pub fn same(xs, ys) {{
+              case xs {{
+                _ if xs == ys -> True
+                _ -> False
+              }}
+            }}
+            {STEPPER}same([1], [1])
+            {STEPPER}same([1], [2])"
+        }),
+    );
+    assert!(
+        out.contains("case [1] {\n  _ if [1] == [1] -> True\n  _ -> False\n}"),
+        "expected the list values substituted in the guard, got: {out}"
+    );
+    let (first, second) = out.split_once("same([1], [2])").unwrap_or_default();
+    assert!(
+        first.trim_end().ends_with("True"),
+        "expected the first call to be True, got: {out}"
+    );
+    assert!(
+        second.trim_end().ends_with("False"),
+        "expected the second call to be False, got: {out}"
+    );
+}
+
+#[test]
+fn repl_subst_let_assert() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&format!("{STEPPER}{{ let assert Ok(x) = Ok(1) x + 1 }}")),
+    );
+    assert!(
+        out.trim_end().ends_with("2"),
+        "expected matching let assert to bind, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&format!("{STEPPER}{{ let assert Ok(x) = Error(1) x + 1 }}")),
+    );
+    assert!(
+        out.trim_end()
+            .ends_with(r#"panic as "Pattern match failed, no pattern matched the value.""#),
+        "expected failed let assert to end with a runtime error, got: {out}"
+    );
+}
+
+#[test]
+fn repl_subst_stdlib_calls() {
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {
+            "
+            import gleam/list
+            {STEPPER}list.length([1, 2])"
+        }),
+    );
+    assert!(
+        out.contains("list.length_loop([1, 2], 0)"),
+        "expected substitution into the list module, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("2"),
+        "expected final value 2, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q"],
+        Some(&formatdoc! {
+            "
+            import gleam/int
+            {STEPPER}int.to_string(1 + 2)"
+        }),
+    );
+    assert!(
+        out.contains("int.to_string(3)"),
+        "expected the argument to be reduced first, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with(r#""3""#),
+        "expected the external to be evaluated, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_block_main() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_block_main.gleam"
+    );
+    let out = run_sgleam_cmd_native_only(&["stepper", input], None).0;
+    assert!(
+        out.contains("let x = 3"),
+        "expected the let statement to be reduced, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("6"),
+        "expected final value 6, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_qualified_call_into_user_module() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_qualified.gleam"
+    );
+    let out = run_sgleam_cmd_native_only(&["stepper", input], None).0;
+    assert!(
+        out.starts_with("helper.inc(3)"),
+        "expected the qualified call first, got: {out}"
+    );
+    assert!(
+        out.contains("3 + 1"),
+        "expected the function of the helper module, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("4"),
+        "expected final value 4, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_expr() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    let out = run_sgleam_cmd_native_only(&["stepper", "--expr", "fat(3)", input], None).0;
+    assert!(
+        out.starts_with("fat(3)"),
+        "expected the entry expression first, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("6"),
+        "expected final value 6, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_use_echo() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_use_echo.gleam"
+    );
+    let out = run_sgleam_cmd_native_only(&["stepper", input], None).0;
+    assert!(
+        out.contains("use y <- with_double(3)\n"),
+        "expected the use statement, got: {out}"
+    );
+    assert!(
+        out.contains("with_double(3, fn(y) { echo y + 1 })"),
+        "expected the desugared use, got: {out}"
+    );
+    assert!(
+        out.trim_end().ends_with("7"),
+        "expected final value 7, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_native_only(&["stepper", "--format", "json", input], None).0;
+    assert!(
+        out.contains(r#""note": "echo prints 7""#),
+        "expected the echoed value in the note, got: {out}"
+    );
+
+    let (_, err) = run_sgleam_cmd_native_only(
+        &[
+            "stepper",
+            "--expr",
+            "with_double(3, fn(x) { x + 1 })",
+            input,
+        ],
+        None,
+    );
+    assert!(
+        err.contains("The stepper does not support anonymous functions yet."),
+        "expected anonymous functions outside use to be rejected, got: {err}"
+    );
+}
+
+#[test]
+fn stepper_format() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    let args = |format| ["stepper", "--expr", "fat(1)", "--format", format, input];
+
+    let out = run_sgleam_cmd_native_only(&args("json"), None).0;
+    assert!(
+        out.starts_with(r#"{"steps": ["#),
+        "expected a json object, got: {out}"
+    );
+    assert!(
+        out.contains(r#""index": 0, "code": "fat(1)", "note": null"#),
+        "expected the first step, got: {out}"
+    );
+    assert!(
+        out.contains(r#""redex": {"start": 0, "end": 6}"#),
+        "expected the whole call as the first redex, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_native_only(&args("markdown"), None).0;
+    assert!(
+        out.starts_with("### Step 1\n\n```gleam\nfat(1)\n```"),
+        "expected a markdown step, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_native_only(&args("html"), None).0;
+    assert!(
+        out.contains(r#"<mark class="redex">fat(1)</mark>"#),
+        "expected the highlighted redex, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_redex_spans() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    let out = run_sgleam_cmd_native_only(
+        &["stepper", "--expr", "1 + 2 * 3", "--format", "json", input],
+        None,
+    )
+    .0;
+    assert!(
+        out.contains(r#""code": "1 + 2 * 3", "note": null, "context": null, "result": null, "redex": {"start": 4, "end": 9}"#),
+        "expected `2 * 3` as the first redex, got: {out}"
+    );
+    assert!(
+        out.contains(r#""code": "1 + 6", "note": "reduce primitive operator", "context": null, "result": {"start": 4, "end": 5}"#),
+        "expected `6` as the first result, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_collapse() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    let out = run_sgleam_cmd_native_only(
+        &[
+            "stepper",
+            "--expr",
+            "fat(3)",
+            "--collapse",
+            "fat",
+            "--format",
+            "json",
+            input,
+        ],
+        None,
+    )
+    .0;
+    assert!(
+        out.contains(r#""code": "6", "note": "fat(3) evaluates to 6""#),
+        "expected a single step for the collapsed call, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_native_only(
+        &["stepper", "--expr", "fat(3)", "--max-depth", "0", input],
+        None,
+    )
+    .0;
+    assert!(
+        out.contains("3 * 2"),
+        "expected the nested call to be collapsed, got: {out}"
+    );
+    assert!(
+        !out.contains("fat(1)"),
+        "expected no steps inside the nested call, got: {out}"
+    );
+
+    let out = run_sgleam_cmd_stdout(
+        &["repl", "-q", input],
+        Some(&format!("{STEPPER}--collapse fat fat(3) + 1")),
+    );
+    assert!(
+        out.contains("6 + 1"),
+        "expected the collapsed call in the repl, got: {out}"
+    );
+}
+
+#[test]
+fn stepper_max_depth_in_echo_and_use() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_depth.gleam"
+    );
+    for (expr, collapsed) in [
+        ("shout(3)", "fat(3) evaluates to 6"),
+        ("use_fat(3)", "evaluates to 6"),
+    ] {
+        let out = run_sgleam_cmd_native_only(
+            &["stepper", "--expr", expr, "--max-depth", "0", input],
+            None,
+        )
+        .0;
+        assert!(
+            out.contains(collapsed),
+            "expected the call in the body of {expr} to be collapsed, got: {out}"
+        );
+        assert!(
+            !out.contains("fat(2)"),
+            "expected no steps inside the collapsed call, got: {out}"
+        );
+    }
+}
+
+#[test]
+fn stepper_collapse_shares_the_step_limit() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    let (_, err) = run_sgleam_cmd_native_only(
+        &[
+            "stepper",
+            "--expr",
+            "fat(10)",
+            "--collapse",
+            "fat",
+            "--max-steps",
+            "20",
+            input,
+        ],
+        None,
+    );
+    assert!(
+        err.contains("The substitution exceeded the limit of 20 steps."),
+        "expected the step limit error, got: {err}"
+    );
+}
+
+#[test]
+fn stepper_max_steps() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    let (_, err) = run_sgleam_cmd_native_only(
+        &["stepper", "--expr", "fat(10)", "--max-steps", "5", input],
+        None,
+    );
+    assert!(
+        err.contains("The substitution exceeded the limit of 5 steps."),
+        "expected the step limit error, got: {err}"
+    );
+}
+
+#[test]
+fn stepper_arithmetic_matches_runtime() {
+    for number in [false, true] {
+        let mut rng = 0x5eed_u64 + number as u64;
+        let exprs: Vec<String> = (0..40).map(|_| random_int_expr(&mut rng, 2)).collect();
+        let expr = format!("[{}]", exprs.join(", "));
+        let mut stepper_args = vec!["stepper", "--expr", &expr];
+        let mut repl_args = vec!["repl", "-q"];
+        if number {
+            stepper_args.push("-n");
+            repl_args.push("-n");
+        }
+        let input = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/inputs/stepper_factorial.gleam"
+        );
+        stepper_args.push(input);
+        let (out, err) = run_sgleam_cmd_native_only(&stepper_args, None);
+        let stepper = out.trim_end().rsplit("\n\n").next().unwrap_or("");
+        let (runtime, _) = run_sgleam_cmd_native_only(&repl_args, Some(&expr));
+        assert_eq!(
+            normalize_list(stepper),
+            normalize_list(&runtime),
+            "number mode: {number}\nexpression: {expr}\nstderr: {err}"
+        );
+    }
+}
+
+#[test]
+fn stepper_int_overflow_matches_runtime() {
+    let mut rng = 0x0f10_u64;
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/inputs/stepper_factorial.gleam"
+    );
+    for _ in 0..10 {
+        // Literals of 100 to 308 digits, so some results pass the largest
+        // double and others do not.
+        let expr = random_big_int_expr(&mut rng, 2);
+        let (out, err) =
+            run_sgleam_cmd_native_only(&["stepper", "-n", "--expr", &expr, input], None);
+        let (runtime, _) = run_sgleam_cmd_native_only(&["repl", "-q", "-n"], Some(&expr));
+        let runtime = runtime.trim();
+        if runtime.contains("Infinity") {
+            let sign = if runtime.starts_with('-') { "-" } else { "" };
+            assert!(
+                err.contains("error: Int overflow"),
+                "expression: {expr}\nstderr: {err}"
+            );
+            assert!(
+                err.contains(&format!("`{sign}Infinity`")),
+                "expression: {expr}\nstderr: {err}"
+            );
+        } else {
+            // The runtime shows large Numbers with an exponent.
+            let stepper = out.trim_end().rsplit("\n\n").next().unwrap_or("");
+            assert_eq!(
+                stepper.trim().parse::<f64>().ok(),
+                runtime.parse::<f64>().ok(),
+                "expression: {expr}\nstderr: {err}"
+            );
+        }
+    }
+}
+
+/// Removes the layout differences between the formatter and `string.inspect`.
+fn normalize_list(s: &str) -> String {
+    s.split_whitespace().collect::<String>().replace(",]", "]")
+}
+
+fn random_int_expr(rng: &mut u64, depth: usize) -> String {
+    *rng = rng
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    let r = *rng >> 33;
+    if depth == 0 {
+        // Zero is common so division and remainder by zero are exercised.
+        return match r % 4 {
+            0 => "0".into(),
+            _ => ((r % 200_001) as i64 - 100_000).to_string(),
+        };
+    }
+    let op = ["+", "-", "*", "/", "%"][(r % 5) as usize];
+    let left = random_int_expr(rng, depth - 1);
+    let right = random_int_expr(rng, depth - 1);
+    let expr = format!("{{ {left} {op} {right} }}");
+    // Beyond 2^53, Number mode loses precision.
+    if depth == 2 && r % 3 == 0 {
+        format!("{expr} + 9_007_199_254_740_993")
+    } else {
+        expr
+    }
+}
+
+fn random_big_int_expr(rng: &mut u64, depth: usize) -> String {
+    *rng = rng
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    let r = *rng >> 33;
+    if depth == 0 {
+        let digits = 100 + (r % 209) as usize;
+        let sign = if r % 2 == 0 { "" } else { "-" };
+        return format!("{sign}{}{}", 1 + r % 9, "7".repeat(digits - 1));
+    }
+    let op = ["+", "-", "*"][(r % 3) as usize];
+    let left = random_big_int_expr(rng, depth - 1);
+    let right = random_big_int_expr(rng, depth - 1);
+    format!("{{ {left} {op} {right} }}")
+}
+
 fn repl_exec(s: &str) -> String {
     run_sgleam_cmd_stdout(&["repl", "-q"], Some(s))
         .strip_suffix('\n')
@@ -932,6 +2176,105 @@ fn examples_compile() {
     }
 }
 
+#[test]
+fn runtime_error_in_gleam_source() {
+    let (out, err) = run_sgleam_cmd_native_only(&["run", "tests/inputs/js_error.gleam"], None);
+    assert_eq!(out, "");
+    for text in [
+        "error: Runtime error",
+        "TypeError",
+        "tests/inputs/js_error.gleam:4:",
+        "4 │   io.println(show(Nil))",
+        "the error happened here, in `main`",
+        "Call stack, most recent call first:\n  main (tests/inputs/js_error.gleam:4)",
+    ] {
+        assert!(err.contains(text), "missing {text:?} in: {err}");
+    }
+    assert!(!err.contains("/build/"), "got: {err}");
+}
+
+#[test]
+fn gleam_errors_as_diagnostics() {
+    let (out, err) = run_sgleam_cmd_native_only(&["run", "tests/inputs/runtime_panic.gleam"], None);
+    assert_eq!(out, "");
+    for text in [
+        "error: Pattern match failed",
+        "tests/inputs/runtime_panic.gleam:2:",
+        "2 │   let assert Ok(x) = Error(\"boom\")",
+        "The value was: Error(\"boom\")",
+        "main (tests/inputs/runtime_panic.gleam:2)",
+    ] {
+        assert!(err.contains(text), "missing {text:?} in: {err}");
+    }
+
+    let (_, err) = run_sgleam_cmd_native_only(&["run", "tests/inputs/main_panic.gleam"], None);
+    for text in [
+        "error: Panic",
+        "2 │   panic",
+        "the program panicked here",
+        "`panic` expression evaluated.",
+    ] {
+        assert!(err.contains(text), "missing {text:?} in: {err}");
+    }
+
+    let (out, err) = run_sgleam_cmd_native_only(
+        &["test", "tests/inputs/check_todo_panic_stackoverflow.gleam"],
+        None,
+    );
+    assert_eq!(
+        out,
+        "Running tests...\n6 tests, 1 success(es), 0 failure(s) and 5 error(s).\n"
+    );
+    assert_eq!(err.matches("error: Todo").count(), 2, "got: {err}");
+    assert_eq!(err.matches("error: Panic").count(), 2, "got: {err}");
+    assert_eq!(
+        err.matches("error: Stack overflow").count(),
+        1,
+        "got: {err}"
+    );
+    assert!(err.contains("6 │     1 -> todo as \"not implemented\""));
+    assert!(err.contains("f_examples (tests/inputs/check_todo_panic_stackoverflow.gleam:"));
+
+    let (_, err) = run_sgleam_cmd_native_only(&["repl", "-q"], Some("panic as \"oops\"\n"));
+    assert!(err.contains("error: Panic"), "got: {err}");
+    assert!(err.contains("oops"), "got: {err}");
+}
+
+#[test]
+fn stack_overflow_summary() {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    let (_, err) =
+        run_sgleam_cmd_native_only(&["run", "tests/inputs/main_stackoverflow.gleam"], None);
+    for text in [
+        "error: Stack overflow",
+        "6 │   1 + f()",
+        "`f` calls itself here",
+        "\n  `f` called itself ",
+        " times (tests/inputs/main_stackoverflow.gleam:6)\n  main (tests/inputs/main_stackoverflow.gleam:2)",
+        "Check that `f` has a base case",
+    ] {
+        assert!(err.contains(text), "missing {text:?} in: {err}");
+    }
+    // The smallest frames give the deepest recursion, which must stay well
+    // under the `Error.stackTraceLimit` of sgleam_ffi.mjs.
+    let calls: usize = err
+        .split_once("`f` called itself ")
+        .and_then(|(_, rest)| rest.split_once(" times"))
+        .map(|(count, _)| count.replace(',', ""))
+        .and_then(|count| count.parse().ok())
+        .unwrap_or_default();
+    assert!(calls > 0 && calls < 500_000, "got: {err}");
+
+    let (_, err) = run_sgleam_cmd_native_only(
+        &["run", "tests/inputs/main_stackoverflow_mutual.gleam"],
+        None,
+    );
+    assert!(err.contains(" called each other "), "got: {err}");
+    assert!(err.contains("Check that `"), "got: {err}");
+}
+
 #[test]
 fn runtime_error_exits_with_nonzero() {
     let input = concat!(
@@ -1107,6 +2450,14 @@ fn normalize_warning_locations(s: &str) -> String {
     joined
 }
 
+/// Creates an empty directory for the files written by the test `name`.
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("sgleam-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create a temporary directory");
+    dir
+}
+
 #[allow(dead_code)]
 fn run_sgleam_cmd_native_only(args: &[&str], input: Option<&str>) -> (String, String) {
     run_native(args, input)
@@ -0,0 +1,499 @@
+use std::fmt::Write as _;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ecow::EcoString;
+use gleam_core::{
+    ast::{
+        FunctionLiteralKind, SrcSpan, Statement, TypedExpr, TypedStatement,
+        visit::{Visit, visit_typed_expr, visit_typed_statement},
+    },
+    build::Module,
+    io::{FileSystemReader, FileSystemWriter, memory::InMemoryFileSystem},
+};
+
+use crate::{
+    engine::Engine, error::SgleamError, gleam::Project, sourcemap::line_span, swrite, swriteln,
+};
+
+// Called by the instrumented code, the name cannot clash with the user code
+// because it is added to the module as a private function.
+const HIT_FUNCTION: &str = "sgleam_coverage_hit";
+
+/// A point of a module whose executions are counted.
+#[derive(Debug, Clone)]
+struct Probe {
+    line: usize,
+    /// The `case` expression and the index of the clause, for the probes at
+    /// the start of the clauses.
+    clause: Option<(usize, usize)>,
+}
+
+/// The probes of a user module and the functions they belong to.
+#[derive(Debug, Clone)]
+pub struct ModuleCoverage {
+    /// The path of the module source, relative to the current directory.
+    pub path: Utf8PathBuf,
+    src: EcoString,
+    insertions: Insertions,
+    /// The index of the first probe of the module in the counts.
+    first: usize,
+    probes: Vec<Probe>,
+    /// The name, line and first probe (relative to the module) of the
+    /// functions.
+    functions: Vec<(String, usize, usize)>,
+}
+
+/// Adds calls to count the execution of the statements and `case` clauses of
+/// the user `modules` to their sources in `project`. The project must be
+/// compiled again to use the instrumented sources, and then the original
+/// sources restored with `restore_sources`.
+pub fn instrument(project: &mut Project, modules: &[Module]) -> Vec<ModuleCoverage> {
+    let mut coverage = vec![];
+    let mut first = 0;
+    for module in modules {
+        let Ok(path) = module.input_path.strip_prefix(Project::source()) else {
+            continue;
+        };
+        let mut collector = ProbeCollector::new(&module.code, first);
+        let mut functions = vec![];
+        for function in &module.ast.definitions.functions {
+            let Some((_, name)) = &function.name else {
+                continue;
+            };
+            if name.ends_with("_examples") || function.external_javascript.is_some() {
+                continue;
+            }
+            let probe = collector.probes.len();
+            for statement in &function.body {
+                collector.visit_typed_statement(statement);
+            }
+            if probe < collector.probes.len() {
+                let line = collector.line(function.location.start);
+                functions.push((name.to_string(), line, probe));
+            }
+        }
+        if collector.probes.is_empty() {
+            continue;
+        }
+
+        let depth = module.name.matches('/').count();
+        let ffi = if depth == 0 {
+            "./".to_string()
+        } else {
+            "../".repeat(depth)
+        };
+        let mut src = collector.instrumented_source();
+        swrite!(
+            src,
+            "\n@external(javascript, \"{ffi}sgleam/sgleam_ffi.mjs\", \"coverage_hit\")\n\
+             fn {HIT_FUNCTION}(probe: Int) -> Nil\n"
+        );
+        project.write_source(path.as_str(), &src);
+
+        let insertions = collector.insertions();
+        let probes = collector.probes;
+        coverage.push(ModuleCoverage {
+            path: path.into(),
+            src: module.code.clone(),
+            insertions,
+            first,
+            probes,
+            functions,
+        });
+        first += coverage.last().map_or(0, |module| module.probes.len());
+    }
+    coverage
+}
+
+/// Writes back the original sources of the modules instrumented in `project`,
+/// after it is compiled, so the errors show the code of the user. The errors
+/// and source maps of the compiled code refer to the instrumented sources,
+/// the text inserted in each one is written to the build directory to map
+/// their positions with `Insertions::read`.
+pub fn restore_sources(project: &mut Project, coverage: &[ModuleCoverage]) {
+    for module in coverage {
+        project.write_source(module.path.as_str(), &module.src);
+        project
+            .fs
+            .write(
+                &Insertions::path(&Project::source().join(&module.path)),
+                &module.insertions.to_string(),
+            )
+            .expect("Write a file in memory");
+    }
+}
+
+/// The text inserted in a source by `instrument`, as the position in the
+/// original source and the length of each insertion, sorted by position.
+/// The insertions have no new lines, so the lines of the source are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insertions(Vec<(u32, u32)>);
+
+impl Insertions {
+    /// The file with the insertions of the `source` in the build directory.
+    fn path(source: &Utf8Path) -> Utf8PathBuf {
+        let relative = source.strip_prefix(Project::source()).unwrap_or(source);
+        Project::out().join(relative).with_extension("insertions")
+    }
+
+    /// Reads the insertions of the `source` of `fs`, if it was instrumented.
+    pub fn read(fs: &InMemoryFileSystem, source: &Utf8Path) -> Option<Insertions> {
+        let text = fs.read(&Self::path(source)).ok()?;
+        let insertions = text
+            .lines()
+            .map(|line| {
+                let (position, length) = line.split_once(' ')?;
+                Some((position.parse().ok()?, length.parse().ok()?))
+            })
+            .collect::<Option<_>>()?;
+        Some(Insertions(insertions))
+    }
+
+    /// Maps a `position` of the instrumented source to the original source.
+    /// The positions in an inserted text are mapped to the position of the
+    /// insertion.
+    pub fn original_position(&self, position: u32) -> u32 {
+        let mut shift = 0;
+        for &(at, length) in &self.0 {
+            if position < at + shift {
+                break;
+            }
+            if position < at + shift + length {
+                return at;
+            }
+            shift += length;
+        }
+        position - shift
+    }
+
+    pub fn original_span(&self, span: SrcSpan) -> SrcSpan {
+        SrcSpan {
+            start: self.original_position(span.start),
+            end: self.original_position(span.end),
+        }
+    }
+
+    /// Maps the `column` (in characters) of the `line` of the instrumented
+    /// source to the column of the original `src`, both starting at 0.
+    pub fn original_column(&self, src: &str, line: usize, column: usize) -> usize {
+        let start = line_span(src, line, Some(0)).start;
+        let (mut position, mut columns, mut remaining) = (start, 0, column);
+        for &(at, length) in self.0.iter().filter(|(at, _)| *at >= start) {
+            let chars = src[position as usize..at as usize].chars().count();
+            if remaining < chars {
+                break;
+            }
+            remaining -= chars;
+            columns += chars;
+            position = at;
+            if remaining < length as usize {
+                return columns;
+            }
+            remaining -= length as usize;
+        }
+        columns + remaining
+    }
+}
+
+impl std::fmt::Display for Insertions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (position, length) in &self.0 {
+            writeln!(f, "{position} {length}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the number of executions of each probe, in the order of the
+/// modules returned by `instrument`.
+pub fn coverage_counts<E: Engine>(engine: &E) -> Result<Vec<u64>, SgleamError> {
+    let counts = engine.call_function("sgleam/sgleam_ffi.mjs", "coverage_counts", &[])?;
+    Ok(counts
+        .trim_matches('"')
+        .split(',')
+        .filter_map(|count| count.parse().ok())
+        .collect())
+}
+
+impl ModuleCoverage {
+    fn count(&self, counts: &[u64], probe: usize) -> u64 {
+        counts.get(self.first + probe).copied().unwrap_or(0)
+    }
+
+    /// The largest number of executions of the probes of each line.
+    fn line_counts(&self, counts: &[u64]) -> Vec<(usize, u64)> {
+        let mut lines: Vec<(usize, u64)> = vec![];
+        for (index, probe) in self.probes.iter().enumerate() {
+            let count = self.count(counts, index);
+            match lines.iter_mut().find(|(line, _)| *line == probe.line) {
+                Some((_, max)) => *max = (*max).max(count),
+                None => lines.push((probe.line, count)),
+            }
+        }
+        lines.sort();
+        lines
+    }
+
+    /// The number of executions of each clause, with its line, `case` and
+    /// index.
+    fn clause_counts(&self, counts: &[u64]) -> Vec<(usize, usize, usize, u64)> {
+        self.probes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, probe)| {
+                let (case, clause) = probe.clause?;
+                Some((probe.line, case, clause, self.count(counts, index)))
+            })
+            .collect()
+    }
+
+    fn function_counts(&self, counts: &[u64]) -> Vec<(&str, usize, u64)> {
+        self.functions
+            .iter()
+            .map(|(name, line, probe)| (name.as_str(), *line, self.count(counts, *probe)))
+            .collect()
+    }
+}
+
+/// Formats the sources of the modules with the number of executions of each
+/// line, `#####` for the lines that were not executed and `-` for the lines
+/// without code, followed by a summary.
+pub fn format_coverage(coverage: &[ModuleCoverage], counts: &[u64]) -> String {
+    let mut out = String::new();
+    for module in coverage {
+        let lines = module.line_counts(counts);
+        let clauses = module.clause_counts(counts);
+        let functions = module.function_counts(counts);
+        swriteln!(out, "Coverage of `{}`:", module.path);
+        for (index, line) in module.src.lines().enumerate() {
+            let count = match lines.iter().find(|(number, _)| *number == index + 1) {
+                Some((_, 0)) => "#####".to_string(),
+                Some((_, count)) => count.to_string(),
+                None => "-".into(),
+            };
+            swriteln!(out, "{count:>9}:{:>5}:{line}", index + 1);
+        }
+        for (name, line, _) in functions.iter().filter(|(_, _, count)| *count == 0) {
+            swriteln!(out, "Function `{name}` (line {line}) was not called.");
+        }
+        for (line, ..) in clauses.iter().filter(|(.., count)| *count == 0) {
+            swriteln!(out, "The `case` clause at line {line} was not executed.");
+        }
+        swriteln!(
+            out,
+            "Functions: {}, lines: {}, clauses: {}.",
+            ratio(
+                functions.iter().filter(|f| f.2 > 0).count(),
+                functions.len()
+            ),
+            ratio(lines.iter().filter(|l| l.1 > 0).count(), lines.len()),
+            ratio(clauses.iter().filter(|c| c.3 > 0).count(), clauses.len()),
+        );
+    }
+    out
+}
+
+fn ratio(covered: usize, total: usize) -> String {
+    if total == 0 {
+        return "0/0".into();
+    }
+    format!(
+        "{covered}/{total} ({:.1}%)",
+        covered as f64 * 100.0 / total as f64
+    )
+}
+
+/// Formats the coverage as an lcov tracefile. The clauses of each `case` are
+/// reported as branches.
+pub fn format_lcov(coverage: &[ModuleCoverage], counts: &[u64]) -> String {
+    let mut out = String::new();
+    for module in coverage {
+        let functions = module.function_counts(counts);
+        let clauses = module.clause_counts(counts);
+        let lines = module.line_counts(counts);
+        swriteln!(out, "TN:");
+        swriteln!(out, "SF:{}", module.path);
+        for (name, line, _) in &functions {
+            swriteln!(out, "FN:{line},{name}");
+        }
+        for (name, _, count) in &functions {
+            swriteln!(out, "FNDA:{count},{name}");
+        }
+        swriteln!(out, "FNF:{}", functions.len());
+        swriteln!(out, "FNH:{}", functions.iter().filter(|f| f.2 > 0).count());
+        for (line, case, clause, count) in &clauses {
+            let taken = if *count == 0 {
+                "-".to_string()
+            } else {
+                count.to_string()
+            };
+            swriteln!(out, "BRDA:{line},{case},{clause},{taken}");
+        }
+        swriteln!(out, "BRF:{}", clauses.len());
+        swriteln!(out, "BRH:{}", clauses.iter().filter(|c| c.3 > 0).count());
+        for (line, count) in &lines {
+            swriteln!(out, "DA:{line},{count}");
+        }
+        swriteln!(out, "LF:{}", lines.len());
+        swriteln!(out, "LH:{}", lines.iter().filter(|l| l.1 > 0).count());
+        swriteln!(out, "end_of_record");
+    }
+    out
+}
+
+/// Collects the probes of the statements and `case` clauses and the text to
+/// insert in the source to count their executions.
+struct ProbeCollector<'a> {
+    src: &'a str,
+    // The global index of the first probe.
+    first: usize,
+    probes: Vec<Probe>,
+    // The position, the order among the insertions at the same position and
+    // the text. The insertions that close a block come before the ones that
+    // start at the same position.
+    insertions: Vec<(u32, u8, String)>,
+    cases: usize,
+}
+
+impl<'a> ProbeCollector<'a> {
+    fn new(src: &'a str, first: usize) -> Self {
+        ProbeCollector {
+            src,
+            first,
+            probes: vec![],
+            insertions: vec![],
+            cases: 0,
+        }
+    }
+
+    fn line(&self, position: u32) -> usize {
+        self.src[..position as usize].matches('\n').count() + 1
+    }
+
+    fn add_probe(&mut self, position: u32, clause: Option<(usize, usize)>) -> usize {
+        let id = self.first + self.probes.len();
+        self.probes.push(Probe {
+            line: self.line(position),
+            clause,
+        });
+        id
+    }
+
+    /// Inserts the call to count the executions of the probe `id` before the
+    /// code at `location`. Code that starts with `-` is put in a block, as
+    /// the call before it would be parsed as a subtraction.
+    fn insert_hit(&mut self, location: SrcSpan, id: usize, prefix: &str, suffix: &str) {
+        if self.src[location.start as usize..].starts_with('-') {
+            self.insertions.push((
+                location.start,
+                1,
+                format!("{prefix}{HIT_FUNCTION}({id}) {{ "),
+            ));
+            self.insertions
+                .push((location.end, 0, format!(" }}{suffix}")));
+        } else {
+            self.insertions
+                .push((location.start, 1, format!("{prefix}{HIT_FUNCTION}({id}) ")));
+            if !suffix.is_empty() {
+                self.insertions.push((location.end, 0, suffix.into()));
+            }
+        }
+    }
+
+    fn sorted_insertions(&self) -> Vec<(u32, u8, String)> {
+        let mut insertions = self.insertions.clone();
+        insertions.sort_by_key(|(position, order, _)| (*position, *order));
+        insertions
+    }
+
+    fn insertions(&self) -> Insertions {
+        Insertions(
+            self.sorted_insertions()
+                .into_iter()
+                .map(|(position, _, text)| (position, text.len() as u32))
+                .collect(),
+        )
+    }
+
+    fn instrumented_source(&self) -> String {
+        let insertions = self.sorted_insertions();
+        let mut src = String::new();
+        let mut last = 0;
+        for (position, _, text) in insertions {
+            src.push_str(&self.src[last..position as usize]);
+            src.push_str(&text);
+            last = position as usize;
+        }
+        src.push_str(&self.src[last..]);
+        src
+    }
+}
+
+impl<'ast> Visit<'ast> for ProbeCollector<'_> {
+    fn visit_typed_statement(&mut self, statement: &'ast TypedStatement) {
+        let location = statement.location();
+        let id = self.add_probe(location.start, None);
+        self.insert_hit(location, id, "", "");
+        visit_typed_statement(self, statement);
+    }
+
+    fn visit_typed_expr(&mut self, expr: &'ast TypedExpr) {
+        match expr {
+            // The body of a function capture (`f(_, 1)`) is not a statement
+            // of the source.
+            TypedExpr::Fn {
+                kind: FunctionLiteralKind::Capture { .. },
+                body,
+                ..
+            } => {
+                for statement in body {
+                    if let Statement::Expression(expr) = statement {
+                        self.visit_typed_expr(expr);
+                    }
+                }
+            }
+            TypedExpr::Case {
+                subjects, clauses, ..
+            } => {
+                let case = self.cases;
+                self.cases += 1;
+                for subject in subjects {
+                    self.visit_typed_expr(subject);
+                }
+                for (index, clause) in clauses.iter().enumerate() {
+                    let location = clause.then.location();
+                    let id = self.add_probe(location.start, Some((case, index)));
+                    self.insert_hit(location, id, "{ ", " }");
+                    self.visit_typed_expr(&clause.then);
+                }
+            }
+            _ => visit_typed_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insertions_map_to_original_positions() {
+        // "ab\n  héj" instrumented as "ab\n  XXXhé Y j".
+        let src = "ab\n  h\u{e9}j";
+        let insertions = Insertions(vec![(5, 3), (8, 3)]);
+        assert_eq!(insertions.original_position(2), 2);
+        assert_eq!(insertions.original_position(6), 5);
+        assert_eq!(insertions.original_position(8), 5);
+        assert_eq!(insertions.original_position(11), 8);
+        assert_eq!(insertions.original_position(14), 8);
+        assert_eq!(insertions.original_column(src, 1, 1), 1);
+        assert_eq!(insertions.original_column(src, 1, 3), 2);
+        assert_eq!(insertions.original_column(src, 1, 6), 3);
+        assert_eq!(insertions.original_column(src, 1, 8), 4);
+        assert_eq!(insertions.original_column(src, 1, 10), 4);
+        assert_eq!(
+            Insertions::read(&InMemoryFileSystem::new(), Utf8Path::new("/src/a.gleam")),
+            None
+        );
+    }
+}
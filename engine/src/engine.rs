@@ -21,7 +21,7 @@ impl MainFunction {
     }
 }
 
-pub trait Engine: Clone {
+pub trait Engine: Clone + 'static {
     fn new(fs: InMemoryFileSystem) -> Self;
 
     fn run_main(
@@ -35,5 +35,20 @@ pub trait Engine: Clone {
 
     fn run_tests(&self, modules: &[&str]) -> Result<(), SgleamError>;
 
+    /// Calls `function` exported by the JavaScript `module` (relative to the
+    /// build directory) with `arguments` (JavaScript source) and returns the
+    /// result formatted by `string.inspect`.
+    fn call_function(
+        &self,
+        module: &str,
+        function: &str,
+        arguments: &[String],
+    ) -> Result<String, SgleamError>;
+
     fn interrupt(&self);
+
+    /// Makes the next imports load the user modules again from the file
+    /// system, instead of using the ones already evaluated. The library
+    /// modules are kept.
+    fn reload_user_modules(&self);
 }
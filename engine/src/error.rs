@@ -2,12 +2,18 @@ use std::io::{IsTerminal as _, Write as _};
 
 use camino::Utf8PathBuf;
 use ecow::EcoString;
-use gleam_core::diagnostic::{Diagnostic, Label, Level, Location};
+use gleam_core::{
+    ast::SrcSpan,
+    diagnostic::{Diagnostic, Label, Level, Location},
+};
 use indoc::formatdoc;
 use termcolor::{BufferWriter, ColorChoice};
 use thiserror::Error;
 
-use crate::substitution::SubstitutionError;
+use crate::{
+    sourcemap::{GleamFrame, Recursion, collapse_recursion, line_span},
+    substitution::SubstitutionError,
+};
 
 #[derive(Debug, Error)]
 pub enum SgleamError {
@@ -68,12 +74,258 @@ impl From<SubstitutionError> for SgleamError {
 }
 
 pub fn show_error(err: &SgleamError) {
+    // Already displayed by the JS runtime.
+    if matches!(err, SgleamError::UserProgramRuntimeError) {
+        return;
+    }
+    let buffer_writer = stderr_buffer_writer();
+    let mut buffer = buffer_writer.buffer();
+    write_error(err, &mut buffer);
+    flush_buffer(&buffer_writer, &buffer);
+}
+
+/// Shows a JavaScript error of the user program, like a `TypeError` of an
+/// external function, in the Gleam sources of the `frames` of its stack (the
+/// most recent first).
+pub fn show_runtime_error(message: &str, frames: &[GleamFrame]) {
+    let buffer_writer = stderr_buffer_writer();
+    let mut buffer = buffer_writer.buffer();
+    runtime_error_diagnostic(message, frames).write(&mut buffer);
+    writeln!(buffer).expect("write to buffer");
+    flush_buffer(&buffer_writer, &buffer);
+}
+
+fn runtime_error_diagnostic(message: &str, frames: &[GleamFrame]) -> Diagnostic {
+    let mut text = message.to_string();
+    text.push_str(&call_stack(&collapse_recursion(frames)));
+    Diagnostic {
+        title: "Runtime error".into(),
+        text,
+        hint: None,
+        level: Level::Error,
+        location: frames.first().map(|frame| Location {
+            src: frame.src.clone(),
+            path: frame.path.clone(),
+            label: Label {
+                span: frame.location,
+                text: Some(format!("the error happened here, in `{}`", frame.function)),
+            },
+            extra_labels: vec![],
+        }),
+    }
+}
+
+/// A `panic`, `todo` or failed assertion of the user program, with the
+/// fields of the error thrown by the generated JavaScript.
+#[derive(Debug, Clone, Default)]
+pub struct GleamRuntimeError {
+    /// `panic`, `todo`, `let_assert` or `assert`.
+    pub kind: String,
+    pub message: String,
+    /// The path of the module source in the project (`src/...`).
+    pub file: String,
+    /// The line of the error, starting at 1.
+    pub line: usize,
+    /// The failing expression, when the compiler records it.
+    pub location: Option<SrcSpan>,
+    /// The pattern of a failed `let assert`.
+    pub pattern: Option<SrcSpan>,
+    /// The value that did not match the pattern, formatted as Gleam code.
+    pub value: Option<String>,
+}
+
+/// Shows `error` in `src`, the source of its module, with the `frames` of
+/// its stack (the most recent first).
+pub fn show_gleam_error(error: &GleamRuntimeError, src: Option<EcoString>, frames: &[GleamFrame]) {
+    let buffer_writer = stderr_buffer_writer();
+    let mut buffer = buffer_writer.buffer();
+    gleam_error_diagnostic(error, src, frames).write(&mut buffer);
+    writeln!(buffer).expect("write to buffer");
+    flush_buffer(&buffer_writer, &buffer);
+}
+
+fn gleam_error_diagnostic(
+    error: &GleamRuntimeError,
+    src: Option<EcoString>,
+    frames: &[GleamFrame],
+) -> Diagnostic {
+    let (title, label) = match (error.kind.as_str(), error.pattern) {
+        ("todo", _) => ("Todo", "this code is not implemented yet"),
+        ("panic", _) => ("Panic", "the program panicked here"),
+        ("let_assert", Some(_)) => (
+            "Pattern match failed",
+            "the value does not match this pattern",
+        ),
+        ("let_assert", None) => ("Pattern match failed", "this `let assert` failed"),
+        ("assert", _) => ("Assertion failed", "this assertion is `False`"),
+        _ => ("Runtime error", "the error happened here"),
+    };
+    let mut text = error.message.clone();
+    if let Some(value) = &error.value {
+        text.push_str(&format!("\n\nThe value was: {value}"));
+    }
+    text.push_str(&call_stack(&collapse_recursion(frames)));
+    let location = src.map(|src| {
+        let span = error
+            .pattern
+            .or(error.location)
+            .unwrap_or_else(|| line_span(&src, error.line.saturating_sub(1), None));
+        Location {
+            path: error
+                .file
+                .strip_prefix("src/")
+                .unwrap_or(&error.file)
+                .into(),
+            src,
+            label: Label {
+                span,
+                text: Some(label.into()),
+            },
+            extra_labels: vec![],
+        }
+    });
+    Diagnostic {
+        title: title.into(),
+        text,
+        hint: None,
+        level: Level::Error,
+        location,
+    }
+}
+
+/// Shows a stack overflow with the recursive calls in the `frames` of the
+/// stack (the most recent first) summarized, pointing at the call of the
+/// longest recursion.
+pub fn show_stack_overflow(frames: &[GleamFrame]) {
     let buffer_writer = stderr_buffer_writer();
     let mut buffer = buffer_writer.buffer();
+    stack_overflow_diagnostic(frames).write(&mut buffer);
+    writeln!(buffer).expect("write to buffer");
+    flush_buffer(&buffer_writer, &buffer);
+}
+
+fn stack_overflow_diagnostic(frames: &[GleamFrame]) -> Diagnostic {
+    let groups = collapse_recursion(frames);
+    let mut text = "The program made too many nested function calls. This usually happens \
+                    when a recursion does not stop."
+        .to_string();
+    text.push_str(&call_stack(&groups));
+    let recursion = groups
+        .iter()
+        .filter(|group| group.count > 1)
+        .max_by_key(|group| group.count * group.frames.len());
+    let Some(recursion) = recursion else {
+        return Diagnostic {
+            title: "Stack overflow".into(),
+            text,
+            hint: None,
+            level: Level::Error,
+            location: None,
+        };
+    };
+    let frame = &recursion.frames[0];
+    // Each frame calls the one before it, the first calls the last of the
+    // next repetition.
+    let callee = &recursion.frames[recursion.frames.len() - 1].function;
+    let (label, hint) = if recursion.frames.len() == 1 {
+        (
+            format!("`{callee}` calls itself here"),
+            format!(
+                "Check that `{callee}` has a base case, a `case` clause that does not call \
+                 `{callee}`, and that each recursive call gets closer to it."
+            ),
+        )
+    } else {
+        (
+            format!("`{}` calls `{callee}` here", frame.function),
+            format!(
+                "Check that {} have a base case that stops the recursion.",
+                function_names(&recursion.frames)
+            ),
+        )
+    };
+    Diagnostic {
+        title: "Stack overflow".into(),
+        text,
+        hint: Some(hint),
+        level: Level::Error,
+        location: Some(Location {
+            src: frame.src.clone(),
+            path: frame.path.clone(),
+            label: Label {
+                span: frame.location,
+                text: Some(label),
+            },
+            extra_labels: vec![],
+        }),
+    }
+}
+
+/// Lists the calls of the stack, with the repeated ones summarized.
+fn call_stack(groups: &[Recursion]) -> String {
+    let mut text = String::new();
+    if !groups.is_empty() {
+        text.push_str("\n\nCall stack, most recent call first:");
+    }
+    for group in groups {
+        let frame = &group.frames[0];
+        let location = format!("({}:{})", frame.path, frame.line);
+        let line = match (group.frames.len(), group.count) {
+            (_, 1) => format!("{} {location}", frame.function),
+            (1, count) => format!(
+                "`{}` called itself {} times {location}",
+                frame.function,
+                format_count(count)
+            ),
+            (_, count) => format!(
+                "{} called each other {} times {location}",
+                function_names(&group.frames),
+                format_count(count)
+            ),
+        };
+        text.push_str("\n  ");
+        text.push_str(&line);
+    }
+    text
+}
+
+/// Returns "`a`, `b` and `c`" for the functions of `frames`.
+fn function_names(frames: &[GleamFrame]) -> String {
+    let names: Vec<_> = frames
+        .iter()
+        .map(|frame| format!("`{}`", frame.function))
+        .collect();
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {last}", rest.join(", ")),
+        None => String::new(),
+    }
+}
 
+/// Formats `count` with commas separating the thousands.
+fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut out = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Renders `err` as plain text, for front ends that display it themselves.
+pub fn error_to_string(err: &SgleamError) -> String {
+    let mut buffer = termcolor::Buffer::no_color();
+    write_error(err, &mut buffer);
+    String::from_utf8_lossy(buffer.as_slice()).into_owned()
+}
+
+fn write_error(err: &SgleamError, buffer: &mut termcolor::Buffer) {
     match err {
         SgleamError::Gleam(err) => {
-            err.pretty(&mut buffer);
+            err.pretty(buffer);
         }
         SgleamError::InvalidSMain { module, signature } => Diagnostic {
             title: "smain function has an invalid signature".into(),
@@ -91,7 +343,7 @@ pub fn show_error(err: &SgleamError) {
             level: Level::Error,
             location: None,
         }
-        .write(&mut buffer),
+        .write(buffer),
 
         SgleamError::PathNotInCurrentDir { current_dir, path } => Diagnostic {
             title: "path is not within the current directory".into(),
@@ -100,9 +352,9 @@ pub fn show_error(err: &SgleamError) {
             level: Level::Error,
             location: None,
         }
-        .write(&mut buffer),
+        .write(buffer),
         // Already displayed by the JS runtime.
-        SgleamError::UserProgramRuntimeError => return,
+        SgleamError::UserProgramRuntimeError => {}
         SgleamError::Interrupted => {
             writeln!(buffer, "Interrupted.").expect("write to buffer");
         }
@@ -136,7 +388,31 @@ pub fn show_error(err: &SgleamError) {
                         extra_labels: vec![],
                     }),
                 }
-                .write(&mut buffer);
+                .write(buffer);
+            }
+            SubstitutionError::UnsupportedGuardValue { value } => {
+                Diagnostic {
+                    title: "Unsupported feature in stepper".into(),
+                    text: format!("The stepper cannot use the value {value} in a case guard."),
+                    hint: Some("Try matching the value in the case pattern instead.".into()),
+                    level: Level::Error,
+                    location: None,
+                }
+                .write(buffer);
+            }
+            SubstitutionError::IntOverflow { negative } => {
+                let sign = if *negative { "-" } else { "" };
+                Diagnostic {
+                    title: "Int overflow".into(),
+                    text: format!(
+                        "The result is beyond the range of a Number, which the runtime shows as \
+                         `{sign}Infinity`. The stepper cannot represent it as an Int."
+                    ),
+                    hint: Some("Run without `-n` to use Ints of any size.".into()),
+                    level: Level::Error,
+                    location: None,
+                }
+                .write(buffer);
             }
             SubstitutionError::StepLimitExceeded(limit) => {
                 Diagnostic {
@@ -148,7 +424,17 @@ pub fn show_error(err: &SgleamError) {
                     level: Level::Error,
                     location: None,
                 }
-                .write(&mut buffer);
+                .write(buffer);
+            }
+            SubstitutionError::External { function, message } => {
+                Diagnostic {
+                    title: "External function failed".into(),
+                    text: format!("The stepper could not evaluate {function}: {message}."),
+                    hint: None,
+                    level: Level::Error,
+                    location: None,
+                }
+                .write(buffer);
             }
             SubstitutionError::FormattingError => {
                 Diagnostic {
@@ -158,13 +444,11 @@ pub fn show_error(err: &SgleamError) {
                     level: Level::Error,
                     location: None,
                 }
-                .write(&mut buffer);
+                .write(buffer);
             }
-            SubstitutionError::Format(e) => e.pretty(&mut buffer),
+            SubstitutionError::Format(e) => e.pretty(buffer),
         },
-    };
-
-    flush_buffer(&buffer_writer, &buffer);
+    }
 }
 
 pub fn flush_buffer(_buffer_writer: &BufferWriter, buffer: &termcolor::Buffer) {
@@ -134,7 +134,7 @@ fn write_output(file: &OutputFile) -> Result<(), Error> {
     }
 }
 
-fn write(path: &Utf8Path, text: &str) -> Result<(), Error> {
+pub(crate) fn write(path: &Utf8Path, text: &str) -> Result<(), Error> {
     write_bytes(path, text.as_bytes())
 }
 
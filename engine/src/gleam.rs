@@ -115,11 +115,34 @@ impl Project {
         self.compile_with_modules(repl, &mut im::HashMap::new(), &mut im::HashMap::new())
     }
 
+    /// Compiles without showing the warnings, for sources that were changed
+    /// after being compiled (and warned about).
+    pub fn compile_quietly(&mut self) -> Result<Vec<Module>, Error> {
+        self.compile_with_warnings(
+            &WarningEmitter::new(Rc::new(VectorWarningEmitterIO::new())),
+            &mut im::HashMap::new(),
+            &mut im::HashMap::new(),
+        )
+    }
+
     pub fn compile_with_modules(
         &mut self,
         repl: bool,
         existing_modules: &mut im::HashMap<EcoString, gleam_core::type_::ModuleInterface>,
         defined_modules: &mut im::HashMap<EcoString, DefinedModuleOrigin>,
+    ) -> Result<Vec<Module>, Error> {
+        self.compile_with_warnings(
+            &WarningEmitter::new(Rc::new(ConsoleWarningEmitter::with_repl(repl))),
+            existing_modules,
+            defined_modules,
+        )
+    }
+
+    fn compile_with_warnings(
+        &mut self,
+        warnings: &WarningEmitter,
+        existing_modules: &mut im::HashMap<EcoString, gleam_core::type_::ModuleInterface>,
+        defined_modules: &mut im::HashMap<EcoString, DefinedModuleOrigin>,
     ) -> Result<Vec<Module>, Error> {
         let config = PackageConfig {
             target: Target::JavaScript,
@@ -128,7 +151,7 @@ impl Project {
 
         let target = TargetCodegenConfiguration::JavaScript {
             emit_typescript_definitions: false,
-            emit_source_maps: false,
+            emit_source_maps: true,
             prelude_location: Project::prelude().into(),
         };
 
@@ -147,7 +170,7 @@ impl Project {
 
         compiler
             .compile(
-                &WarningEmitter::new(Rc::new(ConsoleWarningEmitter::with_repl(repl))),
+                warnings,
                 existing_modules,
                 defined_modules,
                 &mut StaleTracker::default(),
@@ -159,6 +182,12 @@ impl Project {
     }
 }
 
+/// Returns true for the modules embedded in sgleam (the Gleam standard library
+/// and the sgleam library), as opposed to user modules.
+pub fn is_library_module(name: &str) -> bool {
+    name.starts_with("gleam/") || name.starts_with("sgleam/")
+}
+
 pub fn get_module<'a>(modules: &'a [Module], name: &str) -> Option<&'a Module> {
     modules.iter().find(|m| m.name == name)
 }
@@ -190,6 +219,69 @@ pub fn get_definition_src<'a>(def: &UntypedDefinition, src: &'a str) -> &'a str
     &src[start..end]
 }
 
+/// Finds the definition of `name` in the module source `src` and returns its
+/// source and its `///` documentation. The name of a constructor finds the
+/// definition of its type.
+pub fn find_definition(src: &str, name: &str) -> Option<(String, String)> {
+    let warning_emitter = WarningEmitter::new(Rc::new(VectorWarningEmitterIO::new()));
+    let parsed = parse_module("definition.gleam".into(), src, &warning_emitter).ok()?;
+    let definition = parsed
+        .module
+        .definitions
+        .iter()
+        .map(|targeted| &targeted.definition)
+        .find(|definition| match definition {
+            Definition::Function(f) => f.name.as_ref().is_some_and(|(_, n)| n == name),
+            Definition::ModuleConstant(c) => c.name == name,
+            Definition::TypeAlias(t) => t.alias == name,
+            Definition::CustomType(t) => {
+                t.name == name || t.constructors.iter().any(|c| c.name == name)
+            }
+            Definition::Import(_) => false,
+        })?;
+    let start = definition.location().start as usize;
+    Some((
+        get_definition_src(definition, src).into(),
+        doc_comment(src, start),
+    ))
+}
+
+/// Returns the line (starting at 1) of the definition of the function `name`
+/// in the module source `src`.
+pub fn function_line(src: &str, name: &str) -> Option<usize> {
+    let warning_emitter = WarningEmitter::new(Rc::new(VectorWarningEmitterIO::new()));
+    let parsed = parse_module("definition.gleam".into(), src, &warning_emitter).ok()?;
+    let start =
+        parsed
+            .module
+            .definitions
+            .iter()
+            .find_map(|targeted| match &targeted.definition {
+                Definition::Function(f) if f.name.as_ref().is_some_and(|(_, n)| n == name) => {
+                    Some(f.location.start as usize)
+                }
+                _ => None,
+            })?;
+    Some(src[..start].matches('\n').count() + 1)
+}
+
+/// Returns the `///` comment before the line of `position`, skipping
+/// attributes such as `@external`.
+pub fn doc_comment(src: &str, position: usize) -> String {
+    let line_start = src[..position].rfind('\n').unwrap_or(0);
+    let mut lines = vec![];
+    for line in src[..line_start].lines().rev() {
+        let line = line.trim();
+        if let Some(doc) = line.strip_prefix("///") {
+            lines.push(doc.strip_prefix(' ').unwrap_or(doc));
+        } else if !line.starts_with('@') {
+            break;
+        }
+    }
+    lines.reverse();
+    lines.join("\n")
+}
+
 pub fn get_args_names(fun: &Function<(), UntypedExpr>) -> Vec<String> {
     fun.arguments
         .iter()
@@ -6,23 +6,30 @@
 
 #[macro_use]
 pub mod output;
+pub mod coverage;
 pub mod engine;
 pub mod error;
 #[cfg(all(not(target_arch = "wasm32"), feature = "resvg"))]
 pub mod fonts;
 pub mod format;
 pub mod gleam;
+pub mod lint;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod logger;
 pub mod panic;
 pub mod parser;
+pub mod profile;
 pub mod quickjs;
 pub mod repl;
+pub mod report;
 pub mod run;
+pub mod search;
+pub mod sourcemap;
 pub mod stepper;
 pub mod substitution;
 #[cfg(all(not(target_arch = "wasm32"), feature = "resvg"))]
 pub mod text_metrics;
+pub mod trace;
 
 use rust_embed::Embed;
 
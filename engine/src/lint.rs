@@ -0,0 +1,343 @@
+use std::{collections::HashSet, fmt::Write as _};
+
+use camino::Utf8PathBuf;
+use ecow::EcoString;
+use gleam_core::{
+    ast::{
+        SrcSpan, TypedExpr, TypedFunction,
+        visit::{Visit, visit_typed_expr},
+    },
+    build::Module,
+    diagnostic::{Diagnostic, Label, Level, Location},
+    type_::{ModuleValueConstructor, ValueConstructorVariant},
+};
+
+use crate::{gleam::doc_comment, substitution::export::json_string, swrite};
+
+const CHECK_MODULE: &str = "sgleam/check";
+const EXAMPLES_SUFFIX: &str = "_examples";
+
+#[derive(Debug, Clone)]
+pub struct LintOptions {
+    /// The maximum number of lines of a function, including its header.
+    pub max_lines: usize,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        LintOptions { max_lines: 30 }
+    }
+}
+
+/// The design recipe checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintRule {
+    MissingAnnotation,
+    MissingExamples,
+    EmptyExamples,
+    MissingPurpose,
+    LongFunction,
+    UnusedParameter,
+}
+
+impl LintRule {
+    /// The name of the rule in the JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            LintRule::MissingAnnotation => "missing-annotation",
+            LintRule::MissingExamples => "missing-examples",
+            LintRule::EmptyExamples => "empty-examples",
+            LintRule::MissingPurpose => "missing-purpose",
+            LintRule::LongFunction => "long-function",
+            LintRule::UnusedParameter => "unused-parameter",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            LintRule::MissingAnnotation => "Missing type annotation",
+            LintRule::MissingExamples => "Missing examples",
+            LintRule::EmptyExamples => "Examples without checks",
+            LintRule::MissingPurpose => "Missing purpose statement",
+            LintRule::LongFunction => "Long function",
+            LintRule::UnusedParameter => "Unused parameter",
+        }
+    }
+}
+
+/// A problem found in a user module.
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub rule: LintRule,
+    /// The path of the module source, relative to the current directory.
+    pub path: Utf8PathBuf,
+    pub src: EcoString,
+    pub location: SrcSpan,
+    pub label: String,
+    pub text: String,
+    pub hint: String,
+}
+
+impl Lint {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            title: self.rule.title().into(),
+            text: self.text.clone(),
+            hint: Some(self.hint.clone()),
+            level: Level::Warning,
+            location: Some(Location {
+                src: self.src.clone(),
+                path: self.path.clone(),
+                label: Label {
+                    span: self.location,
+                    text: Some(self.label.clone()),
+                },
+                extra_labels: vec![],
+            }),
+        }
+    }
+
+    /// The line and column (starting at 1) of the start of the location.
+    pub fn line_column(&self) -> (usize, usize) {
+        let before = &self.src[..self.location.start as usize];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+/// Checks the functions of `module`, with source in `path`, against the
+/// design recipe.
+pub fn lint_module(module: &Module, path: Utf8PathBuf, options: &LintOptions) -> Vec<Lint> {
+    let functions: Vec<_> = module
+        .ast
+        .definitions
+        .functions
+        .iter()
+        .filter(|function| function.external_javascript.is_none())
+        .filter_map(|function| Some((function.name.as_ref()?.1.as_str(), function)))
+        .collect();
+    let mut linter = Linter {
+        path,
+        src: &module.code,
+        lints: vec![],
+    };
+    for &(name, function) in &functions {
+        if let Some(tested) = name.strip_suffix(EXAMPLES_SUFFIX) {
+            if !has_checks(function) {
+                linter.add(
+                    LintRule::EmptyExamples,
+                    function.location,
+                    "no `check` calls",
+                    format!("`{name}` does not check any example of `{tested}`."),
+                    format!(
+                        "Use the functions of `{CHECK_MODULE}`, as in `check.eq({tested}(...), ...)`."
+                    ),
+                );
+            }
+        } else if name != "main" && name != "smain" {
+            linter.check_recipe(name, function, &functions);
+        }
+        linter.check_length(name, function, options.max_lines);
+        linter.check_parameters(name, function);
+    }
+    linter
+        .lints
+        .sort_by_key(|lint| (lint.location.start, lint.rule));
+    linter.lints
+}
+
+/// Formats `lints` as a JSON array, for tools that process the results.
+pub fn lints_to_json(lints: &[Lint]) -> String {
+    let mut out = String::from("[");
+    for (index, lint) in lints.iter().enumerate() {
+        let (line, column) = lint.line_column();
+        if index > 0 {
+            out.push(',');
+        }
+        swrite!(
+            out,
+            "\n  {{\"rule\": {}, \"path\": {}, \"line\": {line}, \"column\": {column}, \"title\": {}, \"message\": {}, \"hint\": {}}}",
+            json_string(lint.rule.name()),
+            json_string(lint.path.as_str()),
+            json_string(lint.rule.title()),
+            json_string(&lint.text),
+            json_string(&lint.hint)
+        );
+    }
+    if !lints.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("]\n");
+    out
+}
+
+struct Linter<'a> {
+    path: Utf8PathBuf,
+    src: &'a str,
+    lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+    fn add(&mut self, rule: LintRule, location: SrcSpan, label: &str, text: String, hint: String) {
+        self.lints.push(Lint {
+            rule,
+            path: self.path.clone(),
+            src: self.src.into(),
+            location,
+            label: label.into(),
+            text,
+            hint,
+        });
+    }
+
+    /// Checks the purpose statement of the functions and the annotations
+    /// and examples of the public ones.
+    fn check_recipe(
+        &mut self,
+        name: &str,
+        function: &TypedFunction,
+        functions: &[(&str, &TypedFunction)],
+    ) {
+        if doc_comment(self.src, function.location.start as usize).is_empty() {
+            self.add(
+                LintRule::MissingPurpose,
+                function.location,
+                "no `///` comment",
+                format!("`{name}` has no purpose statement."),
+                format!("Describe what `{name}` computes in a `///` comment before it."),
+            );
+        }
+        if !function.publicity.is_public() {
+            return;
+        }
+        for argument in &function.arguments {
+            if argument.annotation.is_none() {
+                let parameter = argument
+                    .names
+                    .get_variable_name()
+                    .map_or("_", EcoString::as_str);
+                self.add(
+                    LintRule::MissingAnnotation,
+                    argument.location,
+                    "no type",
+                    format!("The parameter `{parameter}` of `{name}` has no type annotation."),
+                    format!("Write the type of the parameter, as in `{parameter}: Int`."),
+                );
+            }
+        }
+        if function.return_annotation.is_none() {
+            self.add(
+                LintRule::MissingAnnotation,
+                function.location,
+                "no return type",
+                format!("The return type of `{name}` is not annotated."),
+                "Write the type of the result after the parameters, as in `fn double(n: Int) -> Int`."
+                    .into(),
+            );
+        }
+        let examples = format!("{name}{EXAMPLES_SUFFIX}");
+        if !functions.iter().any(|(other, _)| *other == examples) {
+            self.add(
+                LintRule::MissingExamples,
+                function.location,
+                "no examples",
+                format!("`{name}` has no examples."),
+                format!(
+                    "Add a `{examples}` function with `check` calls for the examples of `{name}`."
+                ),
+            );
+        }
+    }
+
+    fn check_length(&mut self, name: &str, function: &TypedFunction, max_lines: usize) {
+        let lines = self.src[function.location.start as usize..function.end_position as usize]
+            .lines()
+            .count();
+        if lines > max_lines {
+            self.add(
+                LintRule::LongFunction,
+                function.location,
+                "too long",
+                format!("`{name}` has {lines} lines, more than the limit of {max_lines}."),
+                "Split the function into smaller helper functions.".into(),
+            );
+        }
+    }
+
+    /// Checks that the parameters are used in the body.
+    fn check_parameters(&mut self, name: &str, function: &TypedFunction) {
+        let mut finder = LocalVariableFinder {
+            used: HashSet::new(),
+        };
+        for statement in &function.body {
+            finder.visit_typed_statement(statement);
+        }
+        for argument in &function.arguments {
+            let Some(parameter) = argument.names.get_variable_name() else {
+                continue;
+            };
+            if !finder.used.contains(&argument.location) {
+                self.add(
+                    LintRule::UnusedParameter,
+                    argument.location,
+                    "not used",
+                    format!("The parameter `{parameter}` of `{name}` is not used."),
+                    format!(
+                        "Remove the parameter or, if it is required, rename it to `_{parameter}`."
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn has_checks(function: &TypedFunction) -> bool {
+    let mut finder = CheckFinder { found: false };
+    for statement in &function.body {
+        finder.visit_typed_statement(statement);
+    }
+    finder.found
+}
+
+/// Finds the uses of the functions of the `check` module.
+struct CheckFinder {
+    found: bool,
+}
+
+impl<'ast> Visit<'ast> for CheckFinder {
+    fn visit_typed_expr(&mut self, expr: &'ast TypedExpr) {
+        match expr {
+            TypedExpr::Var { constructor, .. } => {
+                if let ValueConstructorVariant::ModuleFn { module, .. } = &constructor.variant {
+                    self.found |= module == CHECK_MODULE;
+                }
+            }
+            TypedExpr::ModuleSelect {
+                constructor: ModuleValueConstructor::Fn { module, .. },
+                ..
+            } => {
+                self.found |= module == CHECK_MODULE;
+            }
+            _ => visit_typed_expr(self, expr),
+        }
+    }
+}
+
+/// Finds the definition locations of the local variables that are used.
+struct LocalVariableFinder {
+    used: HashSet<SrcSpan>,
+}
+
+impl<'ast> Visit<'ast> for LocalVariableFinder {
+    fn visit_typed_expr(&mut self, expr: &'ast TypedExpr) {
+        if let TypedExpr::Var { constructor, .. } = expr
+            && let ValueConstructorVariant::LocalVariable { location, .. } = &constructor.variant
+        {
+            self.used.insert(*location);
+        }
+        visit_typed_expr(self, expr);
+    }
+}
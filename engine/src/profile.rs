@@ -0,0 +1,102 @@
+use gleam_core::io::{FileSystemReader, memory::InMemoryFileSystem};
+
+use crate::{
+    engine::Engine,
+    error::SgleamError,
+    gleam::{Project, function_line},
+    report::{format_duration, format_table},
+};
+
+/// The calls to a function while profiling. Times are in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    /// The module of the function, empty for functions defined in the REPL.
+    pub module: String,
+    pub name: String,
+    pub calls: u64,
+    /// The time spent in the function, including the functions it called.
+    pub total: f64,
+    /// The time spent in the function itself.
+    pub self_: f64,
+    /// The largest number of simultaneous calls (the recursion depth).
+    pub max_depth: u64,
+}
+
+/// Starts counting the calls to the instrumented functions.
+pub fn start_profile<E: Engine>(engine: &E) -> Result<(), SgleamError> {
+    engine.call_function("sgleam/sgleam_ffi.mjs", "profile_start", &[])?;
+    Ok(())
+}
+
+/// Stops counting the calls and returns the profiles of the called
+/// functions, the ones with more time first.
+pub fn stop_profile<E: Engine>(engine: &E) -> Result<Vec<FunctionProfile>, SgleamError> {
+    let report = engine.call_function("sgleam/sgleam_ffi.mjs", "profile_stop", &[])?;
+    Ok(parse_profile(report.trim_matches('"')))
+}
+
+/// Parses the `module:name:calls:total:self:depth` entries, separated by
+/// `;`, returned by `profile_stop`.
+fn parse_profile(report: &str) -> Vec<FunctionProfile> {
+    let mut profiles: Vec<_> = report
+        .split(';')
+        .filter_map(|entry| {
+            let fields: Vec<_> = entry.split(':').collect();
+            let [module, name, calls, total, self_, max_depth] = fields[..] else {
+                return None;
+            };
+            Some(FunctionProfile {
+                module: module.into(),
+                name: name.into(),
+                calls: calls.parse().ok()?,
+                total: total.parse().ok()?,
+                self_: self_.parse().ok()?,
+                max_depth: max_depth.parse().ok()?,
+            })
+        })
+        .collect();
+    profiles.sort_by(|a, b| b.self_.total_cmp(&a.self_).then(a.name.cmp(&b.name)));
+    profiles
+}
+
+/// Formats `profiles` as a table, with the location of the functions in the
+/// sources of `fs`.
+pub fn format_profile(profiles: &[FunctionProfile], fs: &InMemoryFileSystem) -> String {
+    if profiles.is_empty() {
+        return "No function calls were profiled.\n".into();
+    }
+    let mut rows = vec![vec![
+        "Function".to_string(),
+        "Location".into(),
+        "Calls".into(),
+        "Total".into(),
+        "Self".into(),
+        "Max depth".into(),
+    ]];
+    for profile in profiles {
+        rows.push(vec![
+            profile.name.clone(),
+            location(profile, fs),
+            profile.calls.to_string(),
+            format_duration(profile.total),
+            format_duration(profile.self_),
+            profile.max_depth.to_string(),
+        ]);
+    }
+    format_table(&rows)
+}
+
+fn location(profile: &FunctionProfile, fs: &InMemoryFileSystem) -> String {
+    if profile.module.is_empty() {
+        return "REPL".into();
+    }
+    let file = format!("{}.gleam", profile.module);
+    let line = fs
+        .read(&Project::source().join(&file))
+        .ok()
+        .and_then(|src| function_line(&src, &profile.name));
+    match line {
+        Some(line) => format!("{file}:{line}"),
+        None => file,
+    }
+}
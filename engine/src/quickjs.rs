@@ -1,13 +1,25 @@
 use camino::Utf8Path;
-use gleam_core::io::{FileSystemReader, memory::InMemoryFileSystem};
+use gleam_core::{
+    ast::SrcSpan,
+    io::{FileSystemReader, memory::InMemoryFileSystem},
+};
 use indoc::formatdoc;
 
-use crate::error::SgleamError;
+use crate::{
+    error::{
+        GleamRuntimeError, SgleamError, show_gleam_error, show_runtime_error, show_stack_overflow,
+    },
+    sourcemap::gleam_frames,
+};
 
 use std::{
     fmt::Write as _,
     path::{Component, Path, PathBuf},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Instant,
 };
 
 use rquickjs::{
@@ -21,8 +33,9 @@ use rquickjs::{
 
 use crate::{
     STACK_SIZE,
+    coverage::Insertions,
     engine::{Engine, MainFunction},
-    gleam::Project,
+    gleam::{Project, is_library_module},
     swriteln,
 };
 
@@ -30,6 +43,8 @@ use crate::{
 pub struct QuickJsEngine {
     context: Context,
     fs: InMemoryFileSystem,
+    // Incremented to load the user modules again, see `reload_user_modules`.
+    generation: Arc<AtomicUsize>,
 }
 
 impl Engine for QuickJsEngine {
@@ -45,9 +60,11 @@ impl Engine for QuickJsEngine {
             });
         }
 
+        let generation = Arc::new(AtomicUsize::new(0));
         QuickJsEngine {
-            context: create_context(fs.clone(), Project::out().into()).unwrap(),
+            context: create_context(fs.clone(), Project::out().into(), generation.clone()).unwrap(),
             fs,
+            generation,
         }
     }
 
@@ -73,9 +90,22 @@ impl Engine for QuickJsEngine {
         run_tests(&self.context, modules)
     }
 
+    fn call_function(
+        &self,
+        module: &str,
+        function: &str,
+        arguments: &[String],
+    ) -> std::result::Result<String, SgleamError> {
+        call_function(&self.context, module, function, arguments)
+    }
+
     fn interrupt(&self) {
         interrupt();
     }
+
+    fn reload_user_modules(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl QuickJsEngine {
@@ -428,17 +458,28 @@ fn load_bitmap(path: String) -> (f64, f64, String) {
     wasm::load_bitmap(path)
 }
 
-pub fn create_context(fs: InMemoryFileSystem, base: PathBuf) -> Result<Context> {
+pub fn create_context(
+    fs: InMemoryFileSystem,
+    base: PathBuf,
+    generation: Arc<AtomicUsize>,
+) -> Result<Context> {
     let runtime = Runtime::new()?;
     runtime.set_max_stack_size(STACK_SIZE - 1024 * 1024);
     runtime.set_interrupt_handler(Some(Box::new(check_interrupt)));
     let context = Context::full(&runtime)?;
-    runtime.set_loader(FileResolver { base }, ScriptLoader { fs });
+    runtime.set_loader(
+        FileResolver {
+            base,
+            fs: fs.clone(),
+            generation,
+        },
+        ScriptLoader { fs: fs.clone() },
+    );
     context
         .with(|ctx| {
             seed_bigint_flag(&ctx)?;
             add_console(&ctx)?;
-            add_sgleam(&ctx)
+            add_sgleam(&ctx, fs)
         })
         .map(|_| context)
 }
@@ -488,6 +529,30 @@ pub fn run_tests(context: &Context, modules: &[&str]) -> std::result::Result<(),
     run_script(context, src)
 }
 
+pub fn call_function(
+    context: &Context,
+    module: &str,
+    function: &str,
+    arguments: &[String],
+) -> std::result::Result<String, SgleamError> {
+    let arguments = arguments.join(", ");
+    let code = formatdoc! {r#"
+        import {{ toList, Ok, Error }} from "./gleam.mjs";
+        import {{ inspect }} from "./gleam/string.mjs";
+        import {{ {function} as external }} from "./{module}";
+        globalThis.__sgleam_result = inspect(external({arguments}));
+        "#
+    };
+    run_script(context, code)?;
+    context
+        .with(|ctx| {
+            let result = ctx.globals().get::<_, String>("__sgleam_result")?;
+            ctx.globals().remove("__sgleam_result")?;
+            Ok(result)
+        })
+        .map_err(|err: Error| err.into())
+}
+
 pub fn run_script(context: &Context, source: String) -> std::result::Result<(), SgleamError> {
     context.with(|ctx| {
         let mut options = EvalOptions::default();
@@ -512,9 +577,49 @@ fn add_console(ctx: &Ctx) -> Result<()> {
     Ok(())
 }
 
-fn add_sgleam(ctx: &Ctx) -> Result<()> {
+fn add_sgleam(ctx: &Ctx, fs: InMemoryFileSystem) -> Result<()> {
     let global = ctx.globals();
     let sgleam = Object::new(ctx.clone())?;
+    let errors_fs = fs.clone();
+    sgleam.set(
+        "show_gleam_error",
+        Function::new(
+            ctx.clone(),
+            move |error: Object, value: Option<String>, stack: String| -> Result<()> {
+                let mut error = gleam_runtime_error(&error, value)?;
+                let path = Project::root().join(&error.file);
+                // The positions of a module compiled with coverage refer to
+                // its instrumented source.
+                if let Some(insertions) = Insertions::read(&errors_fs, &path) {
+                    error.location = error.location.map(|span| insertions.original_span(span));
+                    error.pattern = error.pattern.map(|span| insertions.original_span(span));
+                }
+                let src = errors_fs.read(&path).ok();
+                show_gleam_error(
+                    &error,
+                    src.map(Into::into),
+                    &gleam_frames(&errors_fs, &stack),
+                );
+                Ok(())
+            },
+        )?
+        .with_name("show_gleam_error")?,
+    )?;
+    let frames_fs = fs.clone();
+    sgleam.set(
+        "show_runtime_error",
+        Function::new(ctx.clone(), move |message: String, stack: String| {
+            show_runtime_error(&message, &gleam_frames(&frames_fs, &stack));
+        })?
+        .with_name("show_runtime_error")?,
+    )?;
+    sgleam.set(
+        "show_stack_overflow",
+        Function::new(ctx.clone(), move |stack: String| {
+            show_stack_overflow(&gleam_frames(&fs, &stack));
+        })?
+        .with_name("show_stack_overflow")?,
+    )?;
     sgleam.set(
         "getline",
         Function::new(ctx.clone(), getline)?.with_name("getline")?,
@@ -531,6 +636,10 @@ fn add_sgleam(ctx: &Ctx) -> Result<()> {
         "now_ms",
         Function::new(ctx.clone(), now_ms)?.with_name("now_ms")?,
     )?;
+    sgleam.set(
+        "clock",
+        Function::new(ctx.clone(), clock)?.with_name("clock")?,
+    )?;
     #[cfg(target_arch = "wasm32")]
     sgleam.set(
         "draw_svg",
@@ -569,6 +678,38 @@ fn add_sgleam(ctx: &Ctx) -> Result<()> {
     Ok(())
 }
 
+/// Reads the fields of an error thrown by the generated code for a `panic`,
+/// `todo` or failed assertion.
+fn gleam_runtime_error(error: &Object, value: Option<String>) -> Result<GleamRuntimeError> {
+    let string =
+        |name| -> Result<String> { Ok(error.get::<_, Option<String>>(name)?.unwrap_or_default()) };
+    // Positions are numbers even when integers are `BigInt`s.
+    let number = |name| -> Result<Option<u32>> {
+        Ok(error.get::<_, Value>(name)?.as_number().map(|n| n as u32))
+    };
+    let span = |start, end| -> Result<Option<SrcSpan>> {
+        Ok(match (number(start)?, number(end)?) {
+            (Some(start), Some(end)) => Some(SrcSpan { start, end }),
+            _ => None,
+        })
+    };
+    Ok(GleamRuntimeError {
+        kind: string("gleam_error")?,
+        message: string("message")?,
+        file: string("file")?,
+        line: number("line")?.unwrap_or(0) as usize,
+        location: span("start", "end")?,
+        pattern: span("pattern_start", "pattern_end")?,
+        value,
+    })
+}
+
+/// Seconds since the first call, measured with a monotonic clock.
+fn clock() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
 fn getline() -> Option<String> {
     let mut buffer = String::new();
     let stdin = std::io::stdin();
@@ -609,9 +750,10 @@ fn print_no_newline(s: String) {
     let _ = std::io::stdout().flush();
 }
 
-#[derive(Debug)]
 struct FileResolver {
     pub(crate) base: PathBuf,
+    fs: InMemoryFileSystem,
+    generation: Arc<AtomicUsize>,
 }
 
 impl Resolver for FileResolver {
@@ -628,7 +770,30 @@ impl Resolver for FileResolver {
                     .join(name),
             )
         };
-        Ok(result.to_string_lossy().into())
+        let mut result: String = result.to_string_lossy().into();
+        // QuickJS evaluates each module name once, so the user modules of a
+        // new generation get a new name. The suffix is removed by the loader.
+        let generation = self.generation.load(Ordering::Relaxed);
+        if generation > 0 && self.is_user_module(&result) {
+            result.push_str(&format!("?{generation}"));
+        }
+        Ok(result)
+    }
+}
+
+impl FileResolver {
+    /// Checks if `path` is the JavaScript of a Gleam module of the user.
+    fn is_user_module(&self, path: &str) -> bool {
+        let module = Path::new(path)
+            .strip_prefix(&self.base)
+            .ok()
+            .and_then(|module| Some(module.to_str()?.strip_suffix(".mjs")?.replace('\\', "/")));
+        module.is_some_and(|module| {
+            !is_library_module(&module)
+                && self
+                    .fs
+                    .is_file(&Project::source().join(format!("{module}.gleam")))
+        })
     }
 }
 
@@ -659,9 +824,10 @@ struct ScriptLoader {
 impl Loader for ScriptLoader {
     fn load<'js>(&mut self, ctx: &Ctx<'js>, path: &str) -> Result<Module<'js, Declared>> {
         tracing::debug!("Loading {path}");
+        let file = path.split_once('?').map_or(path, |(file, _)| file);
         let src = self
             .fs
-            .read(path.into())
+            .read(file.into())
             .map_err(|err| Error::new_loading_message(path, err.to_string()))?;
         Module::declare(ctx.clone(), path, src)
     }
@@ -1,19 +1,25 @@
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Write,
+    sync::Arc,
 };
 
+use camino::Utf8PathBuf;
 use ecow::EcoString;
 use gleam_core::{
     Error,
     ast::{
-        BitArraySize, Definition, Pattern, Statement, TargetedDefinition, UntypedPattern,
-        UntypedStatement,
+        BitArraySize, Definition, Pattern, Statement, TargetedDefinition, TypedExpr, TypedFunction,
+        TypedStatement, UntypedExpr, UntypedPattern, UntypedStatement,
+        visit::{Visit, visit_typed_expr, visit_typed_statement},
     },
     build::Module,
     error::DefinedModuleOrigin,
     io::{FileSystemReader, FileSystemWriter},
-    type_::ModuleInterface,
+    type_::{
+        ModuleInterface, Type, TypeVar, ValueConstructorVariant,
+        printer::{Names, Printer},
+    },
 };
 use indoc::formatdoc;
 use vec1::Vec1;
@@ -21,12 +27,23 @@ use vec1::Vec1;
 use super::{
     GLEAM_MODULES_NAMES,
     engine::{Engine, MainFunction},
-    error::{SgleamError, flush_buffer, show_error, stderr_buffer_writer},
-    gleam::{Project, get_args_names, get_definition_src, type_to_string},
+    error::{SgleamError, error_to_string, flush_buffer, show_error, stderr_buffer_writer},
+    format::{self, format_source},
+    gleam::{
+        Project, find_definition, find_imports, get_args_names, get_definition_src, get_module,
+        is_library_module, type_to_string,
+    },
     parser::{self, ReplItem},
-    run::get_function,
-    substitution::{SubstitutionModule, SubstitutionStep, convert, runtime_vars},
+    profile::{format_profile, start_profile, stop_profile},
+    report::{format_duration, format_table},
+    run::{copy_files, get_function, make_relative_to_current_dir},
+    search::match_type,
+    substitution::{
+        StepperOptions, SubstitutionModule, SubstitutionStep, convert, export::trace_json,
+        external::from_inspect, reduce::substitute_expr, runtime_vars,
+    },
     swrite, swriteln,
+    trace::{function_names, instrument_module, names_array, user_modules, write_instrumented},
 };
 
 pub const QUIT: &str = ":quit";
@@ -36,6 +53,24 @@ pub const STEPPER: &str = ":stepper ";
 pub const HELP: &str = ":help";
 pub const THEME: &str = ":theme ";
 pub const DEBUG: &str = ":debug";
+pub const LOAD: &str = ":load ";
+pub const RELOAD: &str = ":reload";
+pub const SAVE: &str = ":save ";
+pub const BROWSE: &str = ":browse ";
+pub const INFO: &str = ":info ";
+pub const DOC: &str = ":doc ";
+pub const SEARCH: &str = ":search ";
+pub const ENV: &str = ":env";
+pub const FORGET: &str = ":forget ";
+pub const RESET: &str = ":reset";
+pub const UNDO: &str = ":undo";
+pub const BENCH: &str = ":bench ";
+pub const TRACE: &str = ":trace ";
+pub const UNTRACE: &str = ":untrace ";
+pub const PROFILE: &str = ":profile ";
+
+/// The number of measured runs of `:bench` without `-n`.
+const BENCH_RUNS: usize = 10;
 
 pub fn welcome_message() -> String {
     format!(
@@ -60,14 +95,75 @@ enum NameEntry {
     Variable { index: usize, type_: String },
 }
 
+impl PartialEq for NameEntry {
+    fn eq(&self, other: &Self) -> bool {
+        use NameEntry::*;
+        match (self, other) {
+            // The members are only filled in by the next compilation.
+            (ModuleAlias { path, .. }, ModuleAlias { path: other, .. }) => path == other,
+            (
+                UnqualifiedValue { module, original },
+                UnqualifiedValue {
+                    module: other_module,
+                    original: other_original,
+                },
+            )
+            | (
+                UnqualifiedType { module, original },
+                UnqualifiedType {
+                    module: other_module,
+                    original: other_original,
+                },
+            ) => module == other_module && original == other_original,
+            (Const(code), Const(other)) | (Type(code), Type(other)) => code == other,
+            (
+                Variable { index, type_ },
+                Variable {
+                    index: other_index,
+                    type_: other_type,
+                },
+            ) => index == other_index && type_ == other_type,
+            _ => false,
+        }
+    }
+}
+
+/// The bindings of the session before an input, restored by `:undo`.
+#[derive(Clone, PartialEq)]
+struct Bindings {
+    names: BTreeMap<String, NameEntry>,
+    fn_bodies: BTreeMap<String, String>,
+    fn_sources: BTreeMap<String, String>,
+    let_sources: Vec<(Vec<String>, String)>,
+}
+
 #[derive(Clone)]
 pub struct Repl<E: Engine> {
     user_import: Option<String>,
+    // The file of the user module, reloaded by `:reload`.
+    loaded_path: Option<Utf8PathBuf>,
     // BTreeMap (not HashMap) so `build_source` emits imports/consts/types
     // in a stable, cross-run order — compiler diagnostics that reference
     // line numbers in the generated source stay reproducible.
     names: BTreeMap<String, NameEntry>,
     fn_bodies: BTreeMap<String, String>,
+    // The functions and `let` bindings as written by the user, without the
+    // variable bindings added to `fn_bodies`. Used by `:save`. Each `let`
+    // has the names it binds that are still defined, and is removed with
+    // the last of them.
+    fn_sources: BTreeMap<String, String>,
+    let_sources: Vec<(Vec<String>, String)>,
+    // The bindings before each input that changed them, for `:undo`.
+    history: im::Vector<Bindings>,
+    // The functions of the loaded file, and the functions traced by `:trace`.
+    // Only the traced functions (all of them while profiling) are
+    // instrumented, the modules of the loaded file are written again from
+    // their JavaScript in `user_js` and reloaded when that changes.
+    user_functions: Vec<String>,
+    user_js: Vec<(String, String)>,
+    traced: BTreeSet<String>,
+    instrumented: BTreeSet<String>,
+    profiling: bool,
     project: Project,
     existing_modules: im::HashMap<EcoString, ModuleInterface>,
     defined_modules: im::HashMap<EcoString, DefinedModuleOrigin>,
@@ -86,6 +182,16 @@ pub struct Repl<E: Engine> {
     pending_stepper_steps: Option<Vec<SubstitutionStep>>,
 }
 
+/// Why `:stepper` could not step through an expression.
+enum StepperFailure {
+    /// A message shown in the standard output.
+    Message(String),
+    /// An error in the expression, shown as a compiler diagnostic.
+    Compile(Error),
+    /// An error while stepping.
+    Evaluation(SgleamError),
+}
+
 #[repr(u32)]
 pub enum ReplOutput {
     StdOut = 0,
@@ -95,20 +201,7 @@ pub enum ReplOutput {
 
 impl<E: Engine> Repl<E> {
     pub fn new(project: Project, user_module: Option<&Module>) -> Result<Repl<E>, SgleamError> {
-        let names: BTreeMap<String, NameEntry> = GLEAM_MODULES_NAMES
-            .iter()
-            .map(|s| {
-                let short = s.rsplit('/').next().unwrap_or(s);
-                (
-                    short.to_string(),
-                    NameEntry::ModuleAlias {
-                        path: s.to_string(),
-                        members: vec![],
-                    },
-                )
-            })
-            .collect();
-        let fs = project.fs.clone();
+        let engine = E::new(project.fs.clone());
         let suffix = format!(
             "{:08x}",
             std::time::SystemTime::now()
@@ -118,12 +211,21 @@ impl<E: Engine> Repl<E> {
         );
         let mut repl = Repl {
             user_import: user_module.map(import_public_types_and_values),
-            names,
+            loaded_path: user_module.map(|module| format!("{}.gleam", module.name).into()),
+            names: default_names(),
             fn_bodies: BTreeMap::new(),
+            fn_sources: BTreeMap::new(),
+            let_sources: vec![],
+            history: im::Vector::new(),
+            user_functions: vec![],
+            user_js: vec![],
+            traced: BTreeSet::new(),
+            instrumented: BTreeSet::new(),
+            profiling: false,
             project,
             existing_modules: im::HashMap::new(),
             defined_modules: im::HashMap::new(),
-            engine: E::new(fs),
+            engine,
             iter: (0, 0),
             var_index: 0,
             debug: false,
@@ -136,13 +238,25 @@ impl<E: Engine> Repl<E> {
             substitution_module: None,
             pending_stepper_steps: None,
         };
-        // Initial compilation to populate module_members cache.
-        let _ = repl.run_check();
+        repl.read_user_modules();
+        // Initial compilation to populate module_members cache. The library
+        // modules compiled here are made available to the stepper.
+        if let Ok(modules) = repl.compile(&repl.build_source()) {
+            let mut substitution_module = SubstitutionModule::default();
+            for module in modules.iter().filter(|m| is_library_module(&m.name)) {
+                substitution_module.merge(SubstitutionModule::from_module(module));
+            }
+            repl.substitution_module = Some(substitution_module);
+        }
         Ok(repl)
     }
 
-    pub fn set_substitution_module(&mut self, substitution_module: Option<SubstitutionModule>) {
-        self.substitution_module = substitution_module;
+    /// Adds the functions of `substitution_module` to the ones available to
+    /// the stepper.
+    pub fn merge_substitution_module(&mut self, substitution_module: SubstitutionModule) {
+        self.substitution_module
+            .get_or_insert_default()
+            .merge(substitution_module);
     }
 
     pub fn names(&self) -> impl Iterator<Item = &str> {
@@ -186,16 +300,67 @@ impl<E: Engine> Repl<E> {
             return Ok(ReplOutput::StdOut);
         }
 
+        if line_trim == UNDO {
+            return Ok(self.run_undo());
+        } else if line_trim == ENV {
+            return Ok(self.run_env());
+        } else if line_trim == RESET {
+            return Ok(self.run_reset());
+        } else if let Some(name) = line_trim.strip_prefix(FORGET) {
+            return Ok(self.run_forget(name.trim()));
+        } else if let Some(names) = line_trim.strip_prefix(TRACE) {
+            return Ok(self.run_trace(names, true));
+        } else if let Some(names) = line_trim.strip_prefix(UNTRACE) {
+            return Ok(self.run_trace(names, false));
+        }
+
+        if line_trim == RELOAD {
+            return self.run_load(None);
+        } else if let Some(path) = line_trim.strip_prefix(LOAD) {
+            return self.run_load(Some(path.trim()));
+        } else if let Some(path) = line_trim.strip_prefix(SAVE) {
+            return self.run_save(path.trim());
+        } else if let Some(module) = line_trim.strip_prefix(BROWSE) {
+            return Ok(self.run_browse(module.trim()));
+        } else if let Some(name) = line_trim.strip_prefix(INFO) {
+            return Ok(self.run_info(name.trim()));
+        } else if let Some(name) = line_trim.strip_prefix(DOC) {
+            return Ok(self.run_doc(name.trim()));
+        } else if let Some(query) = line_trim.strip_prefix(SEARCH) {
+            return Ok(self.run_search(query.trim()));
+        }
+
         let mut is_type = false;
         let mut is_time = false;
         if let Some(expr) = line_trim.strip_prefix(TYPE) {
             input = expr;
             is_type = true;
         } else if let Some(expr) = line_trim.strip_prefix(TIME) {
-            input = expr;
-            is_time = true;
+            match split_runs(expr) {
+                Ok((Some(runs), expr)) => return Ok(self.run_bench_cmd(TIME, expr, runs)),
+                Ok((None, _)) => {
+                    input = expr;
+                    is_time = true;
+                }
+                Err(err) => {
+                    println!("{TIME}{err}.");
+                    return Ok(ReplOutput::Error);
+                }
+            }
+        } else if let Some(exprs) = line_trim.strip_prefix(BENCH) {
+            return match split_runs(exprs) {
+                Ok((runs, exprs)) => {
+                    Ok(self.run_bench_cmd(BENCH, exprs, runs.unwrap_or(BENCH_RUNS)))
+                }
+                Err(err) => {
+                    println!("{BENCH}{err}.");
+                    Ok(ReplOutput::Error)
+                }
+            };
         } else if let Some(expr) = line_trim.strip_prefix(STEPPER) {
             return self.run_stepper_cmd(expr);
+        } else if let Some(expr) = line_trim.strip_prefix(PROFILE) {
+            return Ok(self.run_profile_cmd(expr));
         }
 
         let items = parser::parse_repl(input).map_err(|error| Error::Parse {
@@ -210,6 +375,8 @@ impl<E: Engine> Repl<E> {
             return Ok(ReplOutput::StdOut);
         }
 
+        let before = self.bindings();
+
         // Pre-register function names so mutually recursive functions
         // can reference each other during compilation.
         for item in &items {
@@ -250,6 +417,8 @@ impl<E: Engine> Repl<E> {
             }
         }
 
+        self.push_history(before);
+
         if self.had_runtime_error {
             Ok(ReplOutput::Error)
         } else {
@@ -385,6 +554,11 @@ pub fn {print}(value: a) -> a"#
 
         let mut modules = result?;
 
+        // The functions defined in the REPL have no module.
+        instrument_module(&mut self.project.fs, &module_name, "", |name| {
+            self.fn_bodies.contains_key(name) && (self.profiling || self.traced.contains(name))
+        });
+
         // Fill in empty members for ModuleAlias entries from compiled module interfaces.
         for entry in self.names.values_mut() {
             if let NameEntry::ModuleAlias { path, members } = entry
@@ -447,10 +621,15 @@ pub fn {print}(value: a) -> a"#
 
     /// Display a compile error with adjusted line numbers.
     fn show_gleam_error(&self, err: &Error) {
-        use std::io::Write as _;
-        let offset = self.template_offset;
         let buffer_writer = stderr_buffer_writer();
         let mut buffer = buffer_writer.buffer();
+        self.write_gleam_error(err, &mut buffer);
+        flush_buffer(&buffer_writer, &buffer);
+    }
+
+    fn write_gleam_error(&self, err: &Error, buffer: &mut termcolor::Buffer) {
+        use std::io::Write as _;
+        let offset = self.template_offset;
         for mut diag in err.to_diagnostics() {
             if let Some(ref mut loc) = diag.location
                 && loc.label.span.start >= offset
@@ -465,10 +644,9 @@ pub fn {print}(value: a) -> a"#
                     }
                 }
             }
-            diag.write(&mut buffer);
+            diag.write(buffer);
             writeln!(buffer).expect("write newline");
         }
-        flush_buffer(&buffer_writer, &buffer);
     }
 
     /// Compile and execute a `repl_main` body.
@@ -502,6 +680,8 @@ pub fn {print}(value: a) -> a"#
             Definition::CustomType(t) => self.run_type(t.name.to_string(), src),
             Definition::ModuleConstant(c) => self.run_const(c.name.to_string(), src),
             Definition::Function(f) => {
+                let name = f.name.clone().expect("A function must have a name").1;
+                self.fn_sources.insert(name.to_string(), src.clone());
                 let bindings = self.var_bindings(&get_args_names(f));
 
                 src.insert_str(
@@ -510,7 +690,6 @@ pub fn {print}(value: a) -> a"#
                     &format!("\n  {bindings}"),
                 );
 
-                let name = f.name.clone().expect("A function must have a name").1;
                 self.run_fn(name.into(), src)
             }
         }
@@ -586,6 +765,8 @@ pub fn {print}(value: a) -> a"#
                     .insert(name.into(), NameEntry::Variable { index, type_ });
                 self.var_index += 1;
             }
+            self.let_sources
+                .push((names.to_vec(), format!("{pattern} = {value}")));
         } else {
             // there was an error and the variable was not saved
         }
@@ -657,21 +838,228 @@ pub fn {print}(value: a) -> a"#
             crate::error::show_error(&err);
             self.had_runtime_error = true;
         } else {
-            let time_str = if elapsed.as_secs() > 0 {
-                format!("{:.2} s", elapsed.as_secs_f64())
-            } else if elapsed.as_millis() > 0 {
-                format!("{} ms", elapsed.as_millis())
-            } else if elapsed.as_micros() > 0 {
-                format!("{} µs", elapsed.as_micros())
+            println!("Time: {}", format_duration(elapsed.as_secs_f64()));
+        }
+        Ok(())
+    }
+
+    /// Runs the expressions in `code` `runs` times each and shows statistics
+    /// of the durations. A first run of each expression is not measured. For
+    /// `:time` there must be a single expression, and its value is shown
+    /// once by the first run.
+    fn run_bench_cmd(&mut self, cmd: &str, code: &str, runs: usize) -> ReplOutput {
+        let items = match parser::parse_repl(code) {
+            Ok(items) => items,
+            Err(error) => {
+                self.show_gleam_error(&Error::Parse {
+                    path: format!("/src/{}.gleam", self.module_name()).into(),
+                    src: code.into(),
+                    error: error.into(),
+                });
+                return ReplOutput::Error;
+            }
+        };
+        let mut exprs = vec![];
+        for item in &items {
+            let ReplItem::ReplStatement(Statement::Expression(expr)) = item else {
+                println!("{cmd}command expects only expressions.");
+                return ReplOutput::Error;
+            };
+            let location = expr.location();
+            exprs.push(&code[location.start as usize..location.end as usize]);
+        }
+        let show_value = cmd == TIME;
+        if exprs.is_empty() || (show_value && exprs.len() != 1) {
+            let expected = if show_value {
+                "exactly one"
             } else {
-                format!("{} ns", elapsed.as_nanos())
+                "at least one"
             };
-            println!("Time: {time_str}");
+            println!("{cmd}command expects {expected} expression.");
+            return ReplOutput::Error;
         }
-        Ok(())
+
+        let mut results = vec![];
+        for expr in exprs {
+            // The value is shown by a first run with `repl_print`, the
+            // measured runs use a module without it.
+            let prints: &[bool] = if show_value { &[true, false] } else { &[false] };
+            let mut module = None;
+            for &print in prints {
+                let compiled = match self.compile_expr(expr, print) {
+                    Ok(compiled) => compiled,
+                    Err(err) => {
+                        self.show_gleam_error(&err);
+                        return ReplOutput::Error;
+                    }
+                };
+                // Not measured, the first run loads the module.
+                if let Err(err) = self.run_repl_main(&compiled) {
+                    show_error(&err);
+                    return ReplOutput::Error;
+                }
+                module = Some(compiled);
+            }
+            let module = module.expect("A compiled module");
+
+            let mut samples = Vec::with_capacity(runs);
+            for _ in 0..runs {
+                let start = std::time::Instant::now();
+                if let Err(err) = self.run_repl_main(&module) {
+                    show_error(&err);
+                    return ReplOutput::Error;
+                }
+                samples.push(start.elapsed().as_secs_f64());
+            }
+            results.push((expr, Durations::new(samples)));
+        }
+
+        if show_value {
+            let (_, durations) = &results[0];
+            println!(
+                "Time ({runs} runs): min {}, median {}, mean {}, std dev {}",
+                format_duration(durations.min),
+                format_duration(durations.median),
+                format_duration(durations.mean),
+                format_duration(durations.std_dev),
+            );
+            return ReplOutput::StdOut;
+        }
+
+        let fastest = results
+            .iter()
+            .map(|(_, durations)| durations.median)
+            .fold(f64::INFINITY, f64::min);
+        let mut rows = vec![vec![
+            "Expression".to_string(),
+            "Min".into(),
+            "Median".into(),
+            "Mean".into(),
+            "Std dev".into(),
+            "Relative".into(),
+        ]];
+        for (expr, durations) in &results {
+            rows.push(vec![
+                expr.to_string(),
+                format_duration(durations.min),
+                format_duration(durations.median),
+                format_duration(durations.mean),
+                format_duration(durations.std_dev),
+                if fastest > 0.0 {
+                    format!("{:.2}x", durations.median / fastest)
+                } else {
+                    "-".into()
+                },
+            ]);
+        }
+        print!("{}", format_table(&rows));
+        println!("{runs} runs of each expression.");
+        ReplOutput::StdOut
+    }
+
+    /// Evaluates `code`, printing its value, and shows the calls to the user
+    /// functions with their times.
+    fn run_profile_cmd(&mut self, code: &str) -> ReplOutput {
+        // All the user functions are instrumented while profiling.
+        self.profiling = true;
+        self.reload_instrumented();
+        let output = self.profile_expr(code);
+        self.profiling = false;
+        self.reload_instrumented();
+        output
+    }
+
+    fn profile_expr(&mut self, code: &str) -> ReplOutput {
+        let module = match self.compile_expr(code, true) {
+            Ok(module) => module,
+            Err(err) => {
+                self.show_gleam_error(&err);
+                return ReplOutput::Error;
+            }
+        };
+        if let Err(err) = start_profile(&self.engine) {
+            show_error(&err);
+            return ReplOutput::Error;
+        }
+        let result = self.run_repl_main(&module);
+        if let Err(err) = &result {
+            show_error(err);
+        }
+        match stop_profile(&self.engine) {
+            Ok(profiles) => print!("{}", format_profile(&profiles, &self.project.fs)),
+            Err(err) => show_error(&err),
+        }
+        if result.is_ok() {
+            ReplOutput::StdOut
+        } else {
+            ReplOutput::Error
+        }
+    }
+
+    fn compile_expr(&mut self, expr: &str, print: bool) -> Result<Module, Error> {
+        self.iter.1 += 1;
+        let (prefix, suffix) = if print {
+            (format!("{}({{\n", self.repl_print), "\n})")
+        } else {
+            ("{\n".into(), "\n}")
+        };
+        self.compile_main(&format!("{prefix}{expr}{suffix}"), prefix.len())
+    }
+
+    fn run_repl_main(&self, module: &Module) -> Result<(), SgleamError> {
+        self.engine.run_main(
+            &module.name,
+            MainFunction::ReplMain(self.repl_main.clone()),
+            false,
+        )
     }
 
     fn run_stepper_cmd(&mut self, code: &str) -> Result<ReplOutput, SgleamError> {
+        match self.stepper_steps(code) {
+            Ok(steps) => {
+                self.pending_stepper_steps = Some(steps);
+                Ok(ReplOutput::StdOut)
+            }
+            Err(StepperFailure::Message(message)) => {
+                println!("{message}");
+                Ok(ReplOutput::Error)
+            }
+            Err(StepperFailure::Compile(err)) => {
+                self.show_gleam_error(&err);
+                Ok(ReplOutput::Error)
+            }
+            Err(StepperFailure::Evaluation(err)) => {
+                show_error(&err);
+                Ok(ReplOutput::Error)
+            }
+        }
+    }
+
+    /// Steps through `code`, an expression with optional `:stepper` options,
+    /// and returns the steps as JSON for front ends that render the steps
+    /// themselves. Failures are reported in the `error` field.
+    pub fn stepper_json(&mut self, code: &str) -> String {
+        self.iter = (self.iter.0 + 1, 0);
+        match self.stepper_steps(code.trim()) {
+            Ok(steps) => trace_json(&steps, None),
+            Err(failure) => {
+                let error = match failure {
+                    StepperFailure::Message(message) => message,
+                    StepperFailure::Compile(err) => {
+                        let mut buffer = termcolor::Buffer::no_color();
+                        self.write_gleam_error(&err, &mut buffer);
+                        String::from_utf8_lossy(buffer.as_slice()).into_owned()
+                    }
+                    StepperFailure::Evaluation(err) => error_to_string(&err),
+                };
+                trace_json(&[], Some(&error))
+            }
+        }
+    }
+
+    fn stepper_steps(&mut self, code: &str) -> Result<Vec<SubstitutionStep>, StepperFailure> {
+        let (options, code) = StepperOptions::parse_prefix(code)
+            .map_err(|err| StepperFailure::Message(format!("{STEPPER}{err}.")))?;
         let mut src = self.build_source();
         let bindings = self.var_bindings(&[]);
         let header = format!("pub fn {}() {{\n{bindings}", self.repl_main);
@@ -680,20 +1068,21 @@ pub fn {print}(value: a) -> a"#
         src.push_str(code);
         src.push_str("\n}\n");
 
-        let module = match self.compile(&src) {
-            Ok(modules) => modules.split_off_first().0,
-            Err(err) => {
-                self.show_gleam_error(&err);
-                return Ok(ReplOutput::Error);
-            }
-        };
+        let module = self
+            .compile(&src)
+            .map_err(StepperFailure::Compile)?
+            .split_off_first()
+            .0;
         let current_substitution_module = SubstitutionModule::from_module(&module);
         let mut substitution_module = self.substitution_module.clone().unwrap_or_default();
         substitution_module.merge(current_substitution_module);
+        substitution_module.set_engine(self.engine.clone());
+        substitution_module.options = options;
 
         let Some(main) = get_function(&module, &self.repl_main) else {
-            println!("Could not find the generated expression.");
-            return Ok(ReplOutput::Error);
+            return Err(StepperFailure::Message(
+                "Could not find the generated expression.".into(),
+            ));
         };
         let user_statements: Vec<_> = main
             .body
@@ -701,41 +1090,33 @@ pub fn {print}(value: a) -> a"#
             .filter(|statement| statement.location().start >= self.template_offset)
             .collect();
         let [Statement::Expression(expr)] = user_statements.as_slice() else {
-            println!(":stepper only supports a single expression.");
-            return Ok(ReplOutput::Error);
+            return Err(StepperFailure::Message(
+                ":stepper only supports a single expression.".into(),
+            ));
         };
 
-        if let Some(name) = self.unsupported_runtime_variable_in_expr(expr) {
-            println!(
-                ":stepper does not support REPL variable `{name}` because its source expression is not available."
-            );
-            return Ok(ReplOutput::Error);
-        }
+        let values = self
+            .runtime_variable_values(expr)
+            .map_err(StepperFailure::Message)?;
 
-        let expr = match convert::typed_to_untyped(expr) {
-            Ok(expr) => expr,
-            Err(err) => {
-                println!("{err}");
-                return Ok(ReplOutput::Error);
-            }
-        };
+        let expr = convert::typed_to_untyped(expr)
+            .map_err(|err| StepperFailure::Message(err.to_string()))?;
+        let expr = substitute_expr(&expr, &values)
+            .map_err(|err| StepperFailure::Evaluation(err.into()))?;
 
-        match substitution_module.evaluate(&expr, 1000) {
-            Ok(trace) => {
-                self.pending_stepper_steps = Some(trace.into_steps());
-                Ok(ReplOutput::StdOut)
-            }
-            Err(err) => {
-                show_error(&err.into());
-                Ok(ReplOutput::Error)
-            }
-        }
+        substitution_module
+            .evaluate(&expr)
+            .map(|trace| trace.into_steps())
+            .map_err(|err| StepperFailure::Evaluation(err.into()))
     }
 
-    fn unsupported_runtime_variable_in_expr(
+    /// Fetches the values of the REPL variables referenced by `expr` and
+    /// turns them back into Gleam expressions, so they can be substituted
+    /// into the expression before it is stepped.
+    fn runtime_variable_values(
         &self,
         expr: &gleam_core::ast::TypedExpr,
-    ) -> Option<String> {
+    ) -> Result<HashMap<EcoString, UntypedExpr>, String> {
         let runtime_only_variables = self
             .names
             .iter()
@@ -746,7 +1127,873 @@ pub fn {print}(value: a) -> a"#
                 _ => None,
             })
             .collect::<HashSet<_>>();
-        runtime_vars::find_runtime_var_ref(expr, &runtime_only_variables)
+        let mut values = HashMap::new();
+        for name in runtime_vars::find_runtime_var_refs(expr, &runtime_only_variables) {
+            let Some(NameEntry::Variable { index, .. }) = self.names.get(&name) else {
+                continue;
+            };
+            let inspected = self
+                .engine
+                .call_function("sgleam/sgleam_ffi.mjs", "repl_load", &[index.to_string()])
+                .map_err(|err| format!(":stepper could not load REPL variable `{name}`: {err}."))?;
+            let value = if inspected.starts_with("//fn(") {
+                Err("is a function")
+            } else {
+                from_inspect(&inspected).map_err(|_| "cannot be written as a Gleam expression")
+            };
+            let value = value.map_err(|reason| {
+                format!(
+                    ":stepper does not support REPL variable `{name}` because its value {reason}."
+                )
+            })?;
+            values.insert(name.into(), value);
+        }
+        Ok(values)
+    }
+
+    /// Recompiles the user module from `path`, or from the last loaded file
+    /// when `path` is `None`. The definitions and variables of the session
+    /// that are still valid with the new module are kept, the others are
+    /// reported and removed. If the file does not compile, the session is
+    /// left unchanged.
+    fn run_load(&mut self, path: Option<&str>) -> Result<ReplOutput, SgleamError> {
+        let path = match (path, &self.loaded_path) {
+            (Some(path), _) => make_relative_to_current_dir(path.into())?,
+            (None, Some(path)) => path.clone(),
+            (None, None) => {
+                println!("No file loaded. Use `{LOAD}FILE` first.");
+                return Ok(ReplOutput::Error);
+            }
+        };
+
+        let paths = find_imports(vec![path.clone()])?;
+        // The sources of the previous file are written back if the new ones
+        // do not compile.
+        let sources = self.user_sources();
+        let existing_modules = self.existing_modules.clone();
+        for (file, _) in &sources {
+            self.remove_source(file);
+        }
+        let result = copy_files(&mut self.project, &paths).and_then(|_| {
+            self.project.compile_with_modules(
+                false,
+                &mut self.existing_modules,
+                &mut self.defined_modules,
+            )
+        });
+        let name = path.with_extension("");
+        let user_import = result.as_ref().ok().and_then(|modules| {
+            get_module(modules, &name.as_str().replace('\\', "/"))
+                .map(import_public_types_and_values)
+        });
+        let (modules, user_import) = match (result, user_import) {
+            (Ok(modules), Some(user_import)) => (modules, user_import),
+            (result, _) => {
+                for (file, _) in self.user_sources() {
+                    self.remove_source(&file);
+                }
+                for (file, src) in &sources {
+                    self.project.write_source(file.as_str(), src);
+                }
+                self.existing_modules = existing_modules;
+                result?;
+                println!("Could not load `{path}`.");
+                return Ok(ReplOutput::Error);
+            }
+        };
+
+        let reloaded: HashSet<EcoString> = modules
+            .iter()
+            .filter(|module| !is_library_module(&module.name))
+            .map(|module| module.name.clone())
+            .collect();
+        for (file, _) in &sources {
+            let module = file.as_str().trim_end_matches(".gleam");
+            if !reloaded.contains(module) {
+                self.existing_modules.remove(module);
+            }
+        }
+        self.user_import = Some(user_import);
+        self.loaded_path = Some(path.clone());
+        if let Some(substitution_module) = &mut self.substitution_module {
+            substitution_module.remove_user_functions();
+        }
+        for module in modules.iter().filter(|m| !is_library_module(&m.name)) {
+            self.merge_substitution_module(SubstitutionModule::from_module(module));
+        }
+        self.read_user_modules();
+        self.instrument_traced();
+        self.engine.reload_user_modules();
+        // The previous bindings may refer to the old module.
+        self.history.clear();
+
+        let invalidated = self.restore_session(&reloaded);
+        self.sync_traced();
+
+        println!("Loaded `{path}`.");
+        if !invalidated.is_empty() {
+            println!(
+                "Removed the definitions that are no longer valid: {}.",
+                invalidated.join(", ")
+            );
+        }
+        Ok(ReplOutput::StdOut)
+    }
+
+    /// Returns the paths (relative to the source directory) and contents of
+    /// the user sources.
+    fn user_sources(&self) -> Vec<(Utf8PathBuf, String)> {
+        self.project
+            .fs
+            .files()
+            .into_iter()
+            .filter_map(|path| {
+                let file = path.strip_prefix(Project::source()).ok()?;
+                let module = file.as_str().strip_suffix(".gleam")?;
+                if is_library_module(module) {
+                    return None;
+                }
+                let src = self.project.fs.read(&path).ok()?;
+                Some((file.into(), src))
+            })
+            .collect()
+    }
+
+    /// Keeps the JavaScript of the user modules, as generated by the
+    /// compiler, and the names of their functions.
+    fn read_user_modules(&mut self) {
+        self.user_js = user_modules(&self.project.fs)
+            .into_iter()
+            .filter_map(|module| {
+                let path = Project::out().join(format!("{module}.mjs"));
+                let js = self.project.fs.read(&path).ok()?;
+                Some((module, js))
+            })
+            .collect();
+        let mut functions: Vec<_> = self
+            .user_js
+            .iter()
+            .flat_map(|(_, js)| function_names(js))
+            .collect();
+        functions.sort();
+        functions.dedup();
+        self.user_functions = functions;
+        self.instrumented.clear();
+    }
+
+    /// Writes the user modules with the traced functions, or all of them
+    /// while profiling, instrumented. Returns false if they did not change.
+    fn instrument_traced(&mut self) -> bool {
+        let instrumented: BTreeSet<_> = self
+            .user_functions
+            .iter()
+            .filter(|name| self.profiling || self.traced.contains(*name))
+            .cloned()
+            .collect();
+        if instrumented == self.instrumented {
+            return false;
+        }
+        for (module, js) in &self.user_js {
+            write_instrumented(&mut self.project.fs, module, js, |name| {
+                instrumented.contains(name)
+            });
+        }
+        self.instrumented = instrumented;
+        true
+    }
+
+    /// Instruments the user modules as in `instrument_traced` and reloads
+    /// them if they changed, reporting the variables that could not be kept.
+    fn reload_instrumented(&mut self) {
+        if !self.instrument_traced() {
+            return;
+        }
+        self.engine.reload_user_modules();
+        let reloaded = self
+            .user_js
+            .iter()
+            .map(|(module, _)| EcoString::from(module.as_str()))
+            .collect();
+        let removed = self.restore_variables(variables(&self.names), &reloaded);
+        if !removed.is_empty() {
+            println!(
+                "Removed the variables that could not be reloaded: {}.",
+                removed.join(", ")
+            );
+        }
+    }
+
+    fn remove_source(&mut self, file: &Utf8PathBuf) {
+        self.project
+            .fs
+            .delete_file(&Project::source().join(file))
+            .expect("To delete a user file");
+    }
+
+    /// Checks the definitions and variables of the session against the
+    /// modules in `reloaded` and returns the names of the ones that do not
+    /// compile anymore.
+    ///
+    /// Variables keep their indices, which are used by the function bodies,
+    /// see `restore_variables`.
+    fn restore_session(&mut self, reloaded: &HashSet<EcoString>) -> Vec<String> {
+        let mut invalidated = vec![];
+        let names = std::mem::replace(&mut self.names, default_names());
+        let fn_bodies = std::mem::take(&mut self.fn_bodies);
+        let definitions: Vec<_> = names
+            .iter()
+            .filter(|(_, entry)| !matches!(entry, NameEntry::Variable { .. }))
+            .collect();
+
+        let snapshot = (self.names.clone(), self.fn_bodies.clone());
+        for (name, entry) in &definitions {
+            self.names.insert(name.to_string(), (*entry).clone());
+        }
+        self.fn_bodies = fn_bodies.clone();
+        if self.run_check().is_err() {
+            // Find the definitions that conflict with the new module by
+            // adding them one at a time.
+            (self.names, self.fn_bodies) = snapshot;
+            for (name, entry) in &definitions {
+                let previous = self.names.insert(name.to_string(), (*entry).clone());
+                if self.run_check().is_err() {
+                    match previous {
+                        Some(previous) => self.names.insert(name.to_string(), previous),
+                        None => self.names.remove(*name),
+                    };
+                    invalidated.push(name.to_string());
+                }
+            }
+            for (name, body) in &fn_bodies {
+                self.fn_bodies.insert(name.clone(), body.clone());
+                if self.run_check().is_err() {
+                    self.fn_bodies.remove(name);
+                    invalidated.push(name.clone());
+                }
+            }
+        }
+
+        // The variables of invalidated functions are not restored.
+        let variables = variables(&names)
+            .into_iter()
+            .filter(|(name, ..)| self.fn_bodies.contains_key(name) || !fn_bodies.contains_key(name))
+            .collect();
+        invalidated.extend(self.restore_variables(variables, reloaded));
+
+        self.fn_sources
+            .retain(|name, _| self.fn_bodies.contains_key(name));
+        for name in &invalidated {
+            self.forget_let_source(name);
+        }
+        invalidated
+    }
+
+    /// Adds the `variables` (with their names, indices and types) that are
+    /// still valid with the modules in `reloaded`, and returns the names of
+    /// the removed ones. The values whose type is defined in a reloaded
+    /// module are created again from their `string.inspect` representation,
+    /// the function values from their new definition.
+    fn restore_variables(
+        &mut self,
+        variables: Vec<(String, usize, String)>,
+        reloaded: &HashSet<EcoString>,
+    ) -> Vec<String> {
+        let mut removed = vec![];
+        for (name, index, type_) in variables {
+            let kept = if self.fn_bodies.contains_key(&name) {
+                self.replace_value(index, &name)
+            } else {
+                match self.variable_type(index, &type_) {
+                    Some(value_type) if mentions_module_type(&value_type, reloaded) => {
+                        self.inspect_variable(index).is_some_and(|value| {
+                            let value = format!("fn () -> {type_} {{ {value} }} ()");
+                            self.replace_value(index, &value)
+                        })
+                    }
+                    Some(_) => true,
+                    None => false,
+                }
+            };
+            if kept {
+                self.names
+                    .insert(name, NameEntry::Variable { index, type_ });
+            } else {
+                self.names.remove(&name);
+                self.forget_let_source(&name);
+                removed.push(name);
+            }
+        }
+        removed
+    }
+
+    /// Returns the type of the variable at `index`, if its `type_` is still
+    /// valid.
+    fn variable_type(&mut self, index: usize, type_: &str) -> Option<Arc<Type>> {
+        self.iter.1 += 1;
+        let body = format!("fn () -> {type_} {{ {}({index}) }} ()", self.repl_load);
+        let module = self.compile_main_with_bindings("", &body, 0).ok()?;
+        get_function(&module, &self.repl_main).map(|main| main.return_type.clone())
+    }
+
+    /// Returns the source of the runtime value of the variable at `index`,
+    /// if it can be written as a Gleam expression.
+    fn inspect_variable(&self, index: usize) -> Option<String> {
+        let inspected = self
+            .engine
+            .call_function("sgleam/sgleam_ffi.mjs", "repl_load", &[index.to_string()])
+            .ok()?;
+        (!inspected.starts_with("//fn(") && from_inspect(&inspected).is_ok()).then_some(inspected)
+    }
+
+    /// Replaces the variable at `index` by `value`.
+    fn replace_value(&mut self, index: usize, value: &str) -> bool {
+        self.iter.1 += 1;
+        let body = format!("{}({value})", self.repl_save);
+        let Ok(module) = self.compile_main_with_bindings("", &body, 0) else {
+            return false;
+        };
+        self.engine
+            .run_main(
+                &module.name,
+                MainFunction::ReplMain(self.repl_main.clone()),
+                false,
+            )
+            .is_ok()
+            && self.engine.has_var(self.var_index)
+            && self
+                .engine
+                .call_function(
+                    "sgleam/sgleam_ffi.mjs",
+                    "repl_replace",
+                    &[index.to_string()],
+                )
+                .is_ok()
+    }
+
+    /// Writes the session to `path` as a Gleam module, with the `let`
+    /// bindings in a `main` function.
+    fn run_save(&self, path: &str) -> Result<ReplOutput, SgleamError> {
+        let has_main = self.fn_sources.contains_key("main");
+        let src = format_source(&self.session_source(!has_main))?;
+        format::write(camino::Utf8Path::new(path), &src)?;
+        println!("Saved the session to `{path}`.");
+        if has_main && !self.let_sources.is_empty() {
+            println!("The `let` bindings were not saved because `main` is already defined.");
+        }
+        Ok(ReplOutput::StdOut)
+    }
+
+    /// Returns the imports, consts, types and functions of the session as
+    /// written by the user, followed by a `main` with the `let` bindings
+    /// when `with_main` is true.
+    fn session_source(&self, with_main: bool) -> String {
+        let mut definitions = String::new();
+        for entry in self.names.values() {
+            if let NameEntry::Const(code) = entry {
+                swriteln!(definitions, "{code}\n");
+            }
+        }
+        for entry in self.names.values() {
+            if let NameEntry::Type(code) = entry {
+                swriteln!(definitions, "{code}\n");
+            }
+        }
+        for src in self.fn_sources.values() {
+            swriteln!(definitions, "{src}\n");
+        }
+        if with_main && !self.let_sources.is_empty() {
+            swriteln!(definitions, "pub fn main() {{");
+            for (_, src) in &self.let_sources {
+                swriteln!(definitions, "  {src}");
+            }
+            swriteln!(definitions, "}}");
+        }
+
+        // Imports grouped by module, as a module can be imported only once.
+        let mut imports: BTreeMap<&str, (Option<&str>, Vec<String>)> = BTreeMap::new();
+        for (name, entry) in &self.names {
+            match entry {
+                NameEntry::ModuleAlias { path, .. } => {
+                    imports.entry(path.as_str()).or_default().0 = Some(name.as_str());
+                }
+                NameEntry::UnqualifiedValue { module, original } => {
+                    let member = if name == original {
+                        original.clone()
+                    } else {
+                        format!("{original} as {name}")
+                    };
+                    imports.entry(module.as_str()).or_default().1.push(member);
+                }
+                NameEntry::UnqualifiedType { module, original } => {
+                    let member = if name == original {
+                        format!("type {original}")
+                    } else {
+                        format!("type {original} as {name}")
+                    };
+                    imports.entry(module.as_str()).or_default().1.push(member);
+                }
+                _ => {}
+            }
+        }
+
+        let mut src = String::new();
+        if let Some(user) = &self.user_import {
+            swriteln!(src, "{user}");
+        }
+        for (module, (alias, members)) in imports {
+            // The REPL imports the standard modules by default, only the
+            // used ones are saved.
+            let alias = alias.filter(|alias| definitions.contains(&format!("{alias}.")));
+            if alias.is_none() && members.is_empty() {
+                continue;
+            }
+            swrite!(src, "import {module}");
+            if !members.is_empty() {
+                swrite!(src, ".{{{}}}", members.join(", "));
+            }
+            if let Some(alias) = alias
+                && Some(alias) != module.rsplit('/').next()
+            {
+                swrite!(src, " as {alias}");
+            }
+            src.push('\n');
+        }
+        src.push('\n');
+        src.push_str(&definitions);
+        src
+    }
+
+    /// Lists the public types and values of `module`, a module path or alias.
+    fn run_browse(&self, module: &str) -> ReplOutput {
+        let path = match self.names.get(module) {
+            Some(NameEntry::ModuleAlias { path, .. }) => path.as_str(),
+            _ => module,
+        };
+        let Some(interface) = self.existing_modules.get(path) else {
+            println!("Unknown module `{module}`.");
+            return ReplOutput::Error;
+        };
+        let names = Names::new();
+
+        let mut types: Vec<String> = interface
+            .public_type_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        types.sort();
+        for name in types {
+            let Some(type_) = interface.types.get(name.as_str()) else {
+                continue;
+            };
+            let mut printer = Printer::new(&names);
+            let parameters: Vec<_> = type_
+                .parameters
+                .iter()
+                .map(|parameter| printer.print_type(parameter).to_string())
+                .collect();
+            if parameters.is_empty() {
+                println!("type {name}");
+            } else {
+                println!("type {name}({})", parameters.join(", "));
+            }
+        }
+
+        let mut values: Vec<String> = interface
+            .public_value_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        values.sort();
+        for name in values {
+            if let Some(value) = interface.values.get(name.as_str()) {
+                println!("{name}: {}", Printer::new(&names).print_type(&value.type_));
+            }
+        }
+        ReplOutput::StdOut
+    }
+
+    /// Shows the type, the origin and the definition of `name`.
+    fn run_info(&mut self, name: &str) -> ReplOutput {
+        match self.names.get(name).cloned() {
+            Some(NameEntry::ModuleAlias { path, .. }) => {
+                println!(
+                    "`{name}` is the module {path}. Use `{BROWSE}{name}` to list its members."
+                );
+            }
+            Some(NameEntry::Variable { type_, .. }) => {
+                println!("{name}: {type_}");
+                println!("Defined in the REPL");
+                let src = self.fn_sources.get(name).or_else(|| {
+                    self.let_sources
+                        .iter()
+                        .rev()
+                        .find(|(names, _)| names.iter().any(|n| n == name))
+                        .map(|(_, src)| src)
+                });
+                if let Some(src) = src {
+                    println!("{src}");
+                }
+            }
+            Some(NameEntry::Const(code)) => {
+                if let Some(type_) = self.value_type(name) {
+                    println!("{name}: {type_}");
+                }
+                println!("Defined in the REPL");
+                println!("{code}");
+            }
+            Some(NameEntry::Type(code)) => {
+                println!("Defined in the REPL");
+                println!("{code}");
+            }
+            _ => {
+                let Some((module, member)) = self.resolve_module_member(name) else {
+                    println!("Unknown name `{name}`.");
+                    return ReplOutput::Error;
+                };
+                if let Some(type_) = self.value_type(name) {
+                    println!("{name}: {type_}");
+                }
+                println!("Defined in {module}");
+                if let Some((src, _)) = self.find_module_definition(&module, &member) {
+                    println!("{src}");
+                }
+            }
+        }
+        ReplOutput::StdOut
+    }
+
+    /// Shows the documentation comment of `name`.
+    fn run_doc(&mut self, name: &str) -> ReplOutput {
+        if let Some(NameEntry::Variable { .. } | NameEntry::Const(_) | NameEntry::Type(_)) =
+            self.names.get(name)
+        {
+            println!("`{name}` is defined in the REPL and has no documentation.");
+            return ReplOutput::StdOut;
+        }
+        let Some((module, member)) = self.resolve_module_member(name) else {
+            println!("Unknown name `{name}`.");
+            return ReplOutput::Error;
+        };
+        if let Some(type_) = self.value_type(name) {
+            println!("{name}: {type_}\n");
+        }
+        match self.find_module_definition(&module, &member) {
+            Some((_, doc)) if !doc.is_empty() => println!("{doc}"),
+            _ => println!("No documentation for `{name}`."),
+        }
+        ReplOutput::StdOut
+    }
+
+    /// Returns the module and the name in that module of `name`, which is
+    /// imported from a module, qualified with a module alias (`list.map`) or
+    /// a member of the user module.
+    fn resolve_module_member(&self, name: &str) -> Option<(String, String)> {
+        if let Some((alias, member)) = name.split_once('.') {
+            return match self.names.get(alias) {
+                Some(NameEntry::ModuleAlias { path, .. }) => Some((path.clone(), member.into())),
+                _ => None,
+            };
+        }
+        match self.names.get(name) {
+            Some(
+                NameEntry::UnqualifiedValue { module, original }
+                | NameEntry::UnqualifiedType { module, original },
+            ) => Some((module.clone(), original.clone())),
+            Some(_) => None,
+            None => {
+                let module = self.user_module_name()?;
+                let interface = self.existing_modules.get(module.as_str())?;
+                (interface.values.contains_key(name) || interface.types.contains_key(name))
+                    .then(|| (module, name.into()))
+            }
+        }
+    }
+
+    /// Returns the name of the user module, if one is loaded.
+    fn user_module_name(&self) -> Option<String> {
+        self.user_import.as_ref()?;
+        let module = self.loaded_path.as_ref()?.with_extension("");
+        Some(module.as_str().replace('\\', "/"))
+    }
+
+    /// Lists the public values of the library modules and of the user module
+    /// whose types match the type `query`, the exact matches first.
+    fn run_search(&mut self, query: &str) -> ReplOutput {
+        let mut src = self.build_source();
+        let header = format!("pub fn {}() -> ", self.repl_main);
+        self.template_offset = (src.len() + header.len()) as u32;
+        src.push_str(&header);
+        src.push_str(query);
+        src.push_str(" {\n  panic\n}\n");
+        let module = match self.compile(&src) {
+            Ok(modules) => modules.split_off_first().0,
+            Err(err) => {
+                self.show_gleam_error(&err);
+                return ReplOutput::Error;
+            }
+        };
+        let main = get_function(&module, &self.repl_main).expect("repl main function");
+        let query_type = &main.return_type;
+
+        let user_module = self.user_module_name();
+        let mut matches = vec![];
+        for (module_name, interface) in &self.existing_modules {
+            let prefix = if user_module.as_deref() == Some(module_name.as_str()) {
+                String::new()
+            } else if is_library_module(module_name) {
+                format!("{}.", self.module_alias(module_name))
+            } else {
+                continue;
+            };
+            for name in interface.public_value_names() {
+                let Some(value) = interface.values.get(name.as_str()) else {
+                    continue;
+                };
+                if let Some(type_match) = match_type(query_type, &value.type_) {
+                    matches.push((type_match, format!("{prefix}{name}"), value.type_.clone()));
+                }
+            }
+        }
+
+        if matches.is_empty() {
+            println!("No values found with type `{query}`.");
+            return ReplOutput::StdOut;
+        }
+        matches.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        let names = Names::new();
+        for (_, name, type_) in matches {
+            println!("{name}: {}", Printer::new(&names).print_type(&type_));
+        }
+        ReplOutput::StdOut
+    }
+
+    /// Returns the alias of `module` in the REPL, or its last segment if it
+    /// is not imported.
+    fn module_alias<'a>(&'a self, module: &'a str) -> &'a str {
+        self.names
+            .iter()
+            .find_map(|(alias, entry)| match entry {
+                NameEntry::ModuleAlias { path, .. } if path == module => Some(alias.as_str()),
+                _ => None,
+            })
+            .unwrap_or_else(|| module.rsplit('/').next().unwrap_or(module))
+    }
+
+    /// Returns the source and the documentation of `name` in `module`.
+    fn find_module_definition(&self, module: &str, name: &str) -> Option<(String, String)> {
+        let path = Project::source().join(format!("{module}.gleam"));
+        let src = self.project.fs.read(&path).ok()?;
+        find_definition(&src, name)
+    }
+
+    /// Returns the type of the expression `name`, if it is a value.
+    fn value_type(&mut self, name: &str) -> Option<String> {
+        self.iter.1 += 1;
+        let body = format!("{}({name})", self.repl_print);
+        let module = self.compile_main(&body, 0).ok()?;
+        let main = get_function(&module, &self.repl_main)?;
+        Some(type_to_string(&module, &main.return_type))
+    }
+
+    fn bindings(&self) -> Bindings {
+        Bindings {
+            names: self.names.clone(),
+            fn_bodies: self.fn_bodies.clone(),
+            fn_sources: self.fn_sources.clone(),
+            let_sources: self.let_sources.clone(),
+        }
+    }
+
+    /// Saves `before` in the history if the bindings changed since then.
+    fn push_history(&mut self, before: Bindings) {
+        if before != self.bindings() {
+            self.history.push_back(before);
+        }
+    }
+
+    fn restore_bindings(&mut self, bindings: Bindings) {
+        self.names = bindings.names;
+        self.fn_bodies = bindings.fn_bodies;
+        self.fn_sources = bindings.fn_sources;
+        self.let_sources = bindings.let_sources;
+    }
+
+    /// Restores the bindings before the last input that changed them, if
+    /// they still compile.
+    fn run_undo(&mut self) -> ReplOutput {
+        let Some(bindings) = self.history.pop_back() else {
+            println!("Nothing to undo.");
+            return ReplOutput::Error;
+        };
+        let current = self.bindings();
+        self.restore_bindings(bindings.clone());
+        if let Err(err) = self.run_check() {
+            self.restore_bindings(current);
+            self.history.push_back(bindings);
+            self.show_gleam_error(&err);
+            println!("Could not undo the last change.");
+            return ReplOutput::Error;
+        }
+        self.sync_traced();
+        println!("Undid the last change.");
+        ReplOutput::StdOut
+    }
+
+    /// Lists the bindings of the session, except the default imports.
+    fn run_env(&mut self) -> ReplOutput {
+        let entries: Vec<_> = self
+            .names
+            .iter()
+            .filter(|(name, entry)| !is_default_import(name, entry))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect();
+        if entries.is_empty() {
+            println!("No bindings.");
+        }
+        for (name, entry) in entries {
+            match entry {
+                NameEntry::ModuleAlias { path, .. } => println!("import {name}: module {path}"),
+                NameEntry::UnqualifiedValue { module, .. } => match self.value_type(&name) {
+                    Some(type_) => println!("import {name}: {type_} from {module}"),
+                    None => println!("import {name} from {module}"),
+                },
+                NameEntry::UnqualifiedType { module, .. } => {
+                    println!("import type {name} from {module}")
+                }
+                NameEntry::Const(_) => match self.value_type(&name) {
+                    Some(type_) => println!("const {name}: {type_}"),
+                    None => println!("const {name}"),
+                },
+                NameEntry::Type(_) => println!("type {name}"),
+                NameEntry::Variable { type_, .. } if self.fn_bodies.contains_key(&name) => {
+                    println!("fn {name}: {type_}")
+                }
+                NameEntry::Variable { type_, .. } => println!("let {name}: {type_}"),
+            }
+        }
+        ReplOutput::StdOut
+    }
+
+    /// Removes the binding `name`, unless other definitions depend on it.
+    fn run_forget(&mut self, name: &str) -> ReplOutput {
+        let Some(entry) = self.names.get(name).cloned() else {
+            println!("Unknown name `{name}`.");
+            return ReplOutput::Error;
+        };
+        let snapshot = self.clone();
+        let before = self.bindings();
+        self.names.remove(name);
+        if matches!(entry, NameEntry::Variable { .. }) {
+            self.fn_bodies.remove(name);
+            self.fn_sources.remove(name);
+            self.forget_let_source(name);
+        }
+        if self.run_check().is_err() {
+            *self = snapshot;
+            println!("Cannot forget `{name}` because other definitions depend on it.");
+            return ReplOutput::Error;
+        }
+        self.push_history(before);
+        println!("Forgot `{name}`.");
+        ReplOutput::StdOut
+    }
+
+    /// Removes `name` from the `let` sources, and the ones that do not bind
+    /// other names.
+    fn forget_let_source(&mut self, name: &str) {
+        for (names, _) in &mut self.let_sources {
+            names.retain(|n| n != name);
+        }
+        self.let_sources.retain(|(names, _)| !names.is_empty());
+    }
+
+    /// Starts (`trace`) or stops tracing the calls to the functions `names`.
+    fn run_trace(&mut self, names: &str, trace: bool) -> ReplOutput {
+        let names: Vec<&str> = names
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() {
+            let cmd = if trace { TRACE } else { UNTRACE };
+            println!("{cmd}command expects the names of functions.");
+            return ReplOutput::Error;
+        }
+        if let Some(name) = names.iter().find(|name| !self.is_traceable(name)) {
+            println!("`{name}` is not a function defined in the REPL or in the loaded file.");
+            return ReplOutput::Error;
+        }
+        for name in &names {
+            if trace {
+                self.traced.insert(name.to_string());
+            } else {
+                self.traced.remove(*name);
+            }
+        }
+        self.update_traced();
+        self.reload_instrumented();
+        let names = names
+            .iter()
+            .map(|name| format!("`{name}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if trace {
+            println!("Tracing {names}.");
+        } else {
+            println!("Stopped tracing {names}.");
+        }
+        ReplOutput::StdOut
+    }
+
+    fn is_traceable(&self, name: &str) -> bool {
+        self.fn_bodies.contains_key(name) || self.user_functions.iter().any(|f| f == name)
+    }
+
+    /// Stops tracing the functions that do not exist anymore.
+    fn sync_traced(&mut self) {
+        self.traced = self
+            .traced
+            .iter()
+            .filter(|name| self.is_traceable(name))
+            .cloned()
+            .collect();
+        self.update_traced();
+    }
+
+    /// Sets the functions traced by the engine.
+    fn update_traced(&self) {
+        let names: Vec<_> = self.traced.iter().map(String::as_str).collect();
+        if let Err(err) = self.engine.call_function(
+            "sgleam/sgleam_ffi.mjs",
+            "trace_functions",
+            &[names_array(&names)],
+        ) {
+            show_error(&err);
+        }
+    }
+
+    /// Returns to a new session, keeping the loaded file and the compiled
+    /// library modules.
+    fn run_reset(&mut self) -> ReplOutput {
+        if let Err(err) = self
+            .engine
+            .call_function("sgleam/sgleam_ffi.mjs", "repl_reset", &[])
+        {
+            show_error(&err);
+            return ReplOutput::Error;
+        }
+        // The traced functions of the REPL do not exist anymore.
+        self.traced.clear();
+        self.update_traced();
+        self.names = default_names();
+        self.fn_bodies.clear();
+        self.fn_sources.clear();
+        self.let_sources.clear();
+        self.var_index = 0;
+        // The variables of the previous bindings were removed.
+        self.history.clear();
+        self.reload_instrumented();
+        println!("The session was reset.");
+        ReplOutput::StdOut
     }
 
     fn run_import(&mut self, import: &gleam_core::ast::Import<()>) -> Result<(), Error> {
@@ -804,22 +2051,301 @@ pub fn {print}(value: a) -> a"#
         // Remove stale function body to avoid module-level name conflict
         // (e.g., `fn f() { 1 } const f = 10` in the same input).
         self.fn_bodies.remove(&name);
+        self.fn_sources.remove(&name);
         self.names.insert(name, NameEntry::Const(code));
         self.run_check()
     }
 
+    /// Defines the type `name`. When the type is redefined, the variables
+    /// that hold values of the previous definition are removed, and the
+    /// consts and functions that use it are checked and saved again against
+    /// the new definition, or removed if they do not compile anymore.
     fn run_type(&mut self, name: String, code: String) -> Result<(), Error> {
-        if self.names.values().any(
-            |item| matches!(item, NameEntry::Variable { type_, .. } if type_mentions(&name, type_)),
-        ) {
-            println!("Cannot redefine type `{name}` while variables of that type exist.");
-            return Ok(());
+        let mut words = vec![name.clone()];
+        if let Some(NameEntry::Type(previous)) = self.names.get(&name) {
+            words.extend(type_constructors(previous));
+        }
+        words.extend(type_constructors(&code));
+        let types = self.dependent_types(words);
+        let users = self.type_users(&types)?;
+        self.names.insert(name.clone(), NameEntry::Type(code));
+
+        // The values of the variables were created with the previous
+        // definition.
+        let mut removed: Vec<String> = variables(&self.names)
+            .into_iter()
+            .filter(|(variable, _, type_)| {
+                !self.fn_bodies.contains_key(variable)
+                    && types.iter().any(|word| type_mentions(word, type_))
+            })
+            .map(|(variable, ..)| variable)
+            .collect();
+        for variable in &removed {
+            self.names.remove(variable);
+            self.forget_let_source(variable);
+        }
+        let mut pending = vec![];
+        for user in users {
+            let entry = self.names.remove(&user).expect("A function of the session");
+            let body = self.fn_bodies.remove(&user).expect("A function body");
+            let src = self.fn_sources.remove(&user);
+            pending.push((user, Some((body, src)), entry));
+        }
+        // The consts that do not compile anymore are found with the
+        // functions.
+        if self.run_check().is_err() {
+            let consts: Vec<_> = self
+                .names
+                .iter()
+                .filter(|(_, entry)| matches!(entry, NameEntry::Const(_)))
+                .map(|(name, entry)| (name.clone(), None, entry.clone()))
+                .collect();
+            for (name, ..) in &consts {
+                self.names.remove(name);
+            }
+            pending.extend(consts);
+        }
+        self.run_check()?;
+
+        // Adds back the definitions that compile with the new type, in as
+        // many passes as needed for the ones that use each other. The saved
+        // functions, and the variables bound in the function bodies, use the
+        // previous definition, so the functions are defined again from their
+        // sources.
+        let mut updated = vec![];
+        loop {
+            let count = pending.len();
+            pending.retain(|(dependent, function, entry)| {
+                let before = self.bindings();
+                let defined = match function {
+                    Some((_, Some(src))) => self.redefine_function(src),
+                    Some((body, None)) => {
+                        self.names.insert(dependent.clone(), entry.clone());
+                        self.fn_bodies.insert(dependent.clone(), body.clone());
+                        self.run_check().is_ok()
+                    }
+                    None => {
+                        self.names.insert(dependent.clone(), entry.clone());
+                        self.run_check().is_ok()
+                    }
+                };
+                if !defined {
+                    self.restore_bindings(before);
+                    return true;
+                }
+                if function.is_some() {
+                    updated.push(dependent.clone());
+                }
+                false
+            });
+            if pending.len() == count {
+                break;
+            }
+        }
+        removed.extend(pending.into_iter().map(|(dependent, ..)| dependent));
+
+        let quoted = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| format!("`{name}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if !removed.is_empty() {
+            println!(
+                "Removed the bindings that depend on the previous definition of `{name}`: {}.",
+                quoted(&removed)
+            );
+        }
+        if !updated.is_empty() {
+            println!(
+                "Updated the definitions that use `{name}`: {}.",
+                quoted(&updated)
+            );
+        }
+        Ok(())
+    }
+
+    /// Defines again the function with source `src`, with the variables and
+    /// definitions of the session. Returns false if it does not compile.
+    fn redefine_function(&mut self, src: &str) -> bool {
+        let Ok(items) = parser::parse_repl(src) else {
+            return false;
+        };
+        let Some(ReplItem::ReplDefinition(targeted)) = items.into_iter().next() else {
+            return false;
+        };
+        self.iter.1 += 1;
+        self.run_definition(targeted, src).is_ok()
+    }
+
+    /// Returns the types and constructors in `words` and the types whose
+    /// definitions mention them, as their values contain values of those
+    /// types.
+    fn dependent_types(&self, mut words: Vec<String>) -> Vec<String> {
+        loop {
+            let mut new_words = vec![];
+            for (name, entry) in &self.names {
+                if let NameEntry::Type(code) = entry
+                    && !words.contains(name)
+                    && words.iter().any(|word| type_mentions(word, code))
+                {
+                    new_words.push(name.clone());
+                    new_words.extend(type_constructors(code));
+                }
+            }
+            if new_words.is_empty() {
+                return words;
+            }
+            words.extend(new_words);
+        }
+    }
+
+    /// Returns the functions of the session that use the `types` (or their
+    /// constructors) defined in the REPL, and the ones that call those,
+    /// found in their typed definitions.
+    fn type_users(&mut self, types: &[String]) -> Result<Vec<String>, Error> {
+        let module = self.compile(&self.build_source())?.split_off_first().0;
+        let functions: Vec<_> = module
+            .ast
+            .definitions
+            .functions
+            .iter()
+            .filter_map(|function| Some((function.name.as_ref()?.1.to_string(), function)))
+            .filter(|(name, _)| self.fn_bodies.contains_key(name))
+            .collect();
+        let mut users: Vec<String> = vec![];
+        loop {
+            let new_users: Vec<_> = functions
+                .iter()
+                .filter(|(name, function)| {
+                    !users.contains(name)
+                        && TypeUseFinder {
+                            module: &module.name,
+                            types,
+                            functions: &users,
+                            load: &self.repl_load,
+                            found: false,
+                        }
+                        .uses(function)
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            if new_users.is_empty() {
+                return Ok(users);
+            }
+            users.extend(new_users);
         }
-        self.names.insert(name, NameEntry::Type(code));
-        self.run_check()
     }
 }
 
+/// Finds the uses, in a function of the REPL `module`, of the `types` and
+/// `functions` defined in it.
+struct TypeUseFinder<'a> {
+    module: &'a EcoString,
+    types: &'a [String],
+    functions: &'a [String],
+    load: &'a str,
+    found: bool,
+}
+
+impl TypeUseFinder<'_> {
+    fn uses(mut self, function: &TypedFunction) -> bool {
+        self.found = self.mentions(&function.return_type)
+            || function
+                .arguments
+                .iter()
+                .any(|argument| self.mentions(&argument.type_));
+        for statement in &function.body {
+            self.visit_typed_statement(statement);
+        }
+        self.found
+    }
+
+    fn mentions(&self, type_: &Type) -> bool {
+        mentions_type(type_, &|module: &EcoString, name: &EcoString| {
+            module == self.module && self.types.iter().any(|type_| type_ == name.as_str())
+        })
+    }
+
+    /// Checks if `expr` is the load of a variable, added to the bodies of the
+    /// functions, as in `fn () -> Int { load(0) } ()`.
+    fn is_load(&self, expr: &TypedExpr) -> bool {
+        let TypedExpr::Call { fun, .. } = expr else {
+            return false;
+        };
+        let TypedExpr::Fn { body, .. } = fun.as_ref() else {
+            return false;
+        };
+        matches!(
+            body.first(),
+            Statement::Expression(TypedExpr::Call { fun, .. })
+                if matches!(fun.as_ref(), TypedExpr::Var { name, .. } if name == self.load)
+        )
+    }
+}
+
+impl<'ast> Visit<'ast> for TypeUseFinder<'_> {
+    fn visit_typed_statement(&mut self, statement: &'ast TypedStatement) {
+        // The variables bound in the body are used only where they appear.
+        match statement {
+            Statement::Assignment(assignment) if self.is_load(&assignment.value) => {}
+            _ => visit_typed_statement(self, statement),
+        }
+    }
+
+    fn visit_typed_expr(&mut self, expr: &'ast TypedExpr) {
+        if self.found {
+            return;
+        }
+        if let TypedExpr::Var { constructor, .. } = expr
+            && let ValueConstructorVariant::ModuleFn { module, name, .. } = &constructor.variant
+            && module == self.module
+            && self
+                .functions
+                .iter()
+                .any(|function| function == name.as_str())
+        {
+            self.found = true;
+            return;
+        }
+        if self.mentions(&expr.type_()) {
+            self.found = true;
+            return;
+        }
+        visit_typed_expr(self, expr);
+    }
+}
+
+/// The names of a new session: the library modules imported with their
+/// default aliases.
+fn default_names() -> BTreeMap<String, NameEntry> {
+    GLEAM_MODULES_NAMES
+        .iter()
+        .map(|s| {
+            let short = s.rsplit('/').next().unwrap_or(s);
+            (
+                short.to_string(),
+                NameEntry::ModuleAlias {
+                    path: s.to_string(),
+                    members: vec![],
+                },
+            )
+        })
+        .collect()
+}
+
+/// Checks if `entry` is the import of a library module with its default
+/// alias `name`.
+fn is_default_import(name: &str, entry: &NameEntry) -> bool {
+    matches!(
+        entry,
+        NameEntry::ModuleAlias { path, .. }
+            if GLEAM_MODULES_NAMES.contains(&path.as_str())
+                && path.rsplit('/').next() == Some(name)
+    )
+}
+
 fn assignment_find_names(pattern: &UntypedPattern, names: &mut Vec<String>) {
     match pattern {
         Pattern::Int { .. }
@@ -872,6 +2398,69 @@ fn bit_array_size_find_names(bit_array_size: &BitArraySize<()>, names: &mut Vec<
     }
 }
 
+/// Splits the `-n RUNS` option from the start of `code`.
+fn split_runs(code: &str) -> Result<(Option<usize>, &str), String> {
+    let code = code.trim_start();
+    let Some(rest) = code
+        .strip_prefix("-n")
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+    else {
+        return Ok((None, code));
+    };
+    let rest = rest.trim_start();
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    match rest[..end].parse() {
+        Ok(runs) if runs > 0 => Ok((Some(runs), rest[end..].trim_start())),
+        _ => Err(format!(
+            "-n expects a positive number of runs, got `{}`",
+            &rest[..end]
+        )),
+    }
+}
+
+/// Statistics of the durations of the runs of an expression, in seconds.
+struct Durations {
+    min: f64,
+    median: f64,
+    mean: f64,
+    std_dev: f64,
+}
+
+impl Durations {
+    fn new(mut samples: Vec<f64>) -> Durations {
+        samples.sort_by(f64::total_cmp);
+        let count = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let median = (samples[(samples.len() - 1) / 2] + samples[samples.len() / 2]) / 2.0;
+        let variance = if samples.len() > 1 {
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0)
+        } else {
+            0.0
+        };
+        Durations {
+            min: samples[0],
+            median,
+            mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+}
+
+/// Returns the constructors of the custom type defined in `code`.
+fn type_constructors(code: &str) -> Vec<String> {
+    match parser::parse_repl(code).ok().as_deref() {
+        Some([ReplItem::ReplDefinition(targeted)]) => match &targeted.definition {
+            Definition::CustomType(type_) => type_
+                .constructors
+                .iter()
+                .map(|constructor| constructor.name.to_string())
+                .collect(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
 /// Check if a type string mentions a type name as a whole word.
 /// E.g. `type_mentions("Option", "Option(Int)")` is true,
 /// but `type_mentions("In", "Int")` is false.
@@ -889,6 +2478,57 @@ fn type_mentions(name: &str, type_: &str) -> bool {
     false
 }
 
+/// Returns the names, indices and types of the variables in `names`.
+fn variables(names: &BTreeMap<String, NameEntry>) -> Vec<(String, usize, String)> {
+    names
+        .iter()
+        .filter_map(|(name, entry)| match entry {
+            NameEntry::Variable { index, type_ } => Some((name.clone(), *index, type_.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks if `type_` mentions a type defined in one of `modules`.
+fn mentions_module_type(type_: &Type, modules: &HashSet<EcoString>) -> bool {
+    mentions_type(type_, &|module: &EcoString, _: &EcoString| {
+        modules.contains(module)
+    })
+}
+
+/// Checks if `type_` mentions a named type for which `is_type(module, name)`
+/// holds.
+fn mentions_type(type_: &Type, is_type: &impl Fn(&EcoString, &EcoString) -> bool) -> bool {
+    match type_ {
+        Type::Named {
+            module,
+            name,
+            arguments,
+            ..
+        } => {
+            is_type(module, name)
+                || arguments
+                    .iter()
+                    .any(|argument| mentions_type(argument, is_type))
+        }
+        Type::Fn { arguments, return_ } => {
+            mentions_type(return_, is_type)
+                || arguments
+                    .iter()
+                    .any(|argument| mentions_type(argument, is_type))
+        }
+        Type::Tuple { .. } => type_
+            .tuple_types()
+            .unwrap_or_default()
+            .iter()
+            .any(|element| mentions_type(element, is_type)),
+        Type::Var { type_ } => match &*type_.borrow() {
+            TypeVar::Link { type_ } => mentions_type(type_, is_type),
+            TypeVar::Unbound { .. } | TypeVar::Generic { .. } => false,
+        },
+    }
+}
+
 fn import_public_types_and_values(module: &Module) -> String {
     let mut import = String::new();
     let name = &module.name;
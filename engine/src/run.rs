@@ -1,22 +1,32 @@
+use std::io::Write as _;
+
 use camino::{Utf8Path, Utf8PathBuf};
 
 use gleam_core::{
     ast::TypedFunction,
     build::{Module, Origin, Target},
+    error::{FileIoAction, FileKind},
     type_,
 };
 
 use crate::{
+    coverage::{coverage_counts, format_coverage, format_lcov, instrument, restore_sources},
     engine::{Engine, MainFunction},
-    error::SgleamError,
-    gleam::{Project, fn_type_to_string, get_module},
+    error::{SgleamError, flush_buffer, stderr_buffer_writer},
+    gleam::{Project, fn_type_to_string, get_module, is_library_module},
+    lint::{LintOptions, lint_module, lints_to_json},
+    profile::{format_profile, start_profile, stop_profile},
+    trace::{instrument_user_modules, names_array},
 };
 
 use crate::quickjs::QuickJsEngine as JsEngine;
 
 const SGLEAM_SMAIN: &str = "smain";
 
-pub fn run_main(paths: &[Utf8PathBuf]) -> Result<(), SgleamError> {
+/// Runs the main function of the first file of `paths`, printing the calls
+/// to the user functions named in `trace`. With `profile`, the calls to the
+/// user functions are counted and reported at the end.
+pub fn run_main(paths: &[Utf8PathBuf], trace: &[String], profile: bool) -> Result<(), SgleamError> {
     let mut project = Project::default();
     let modules = copy_files_and_build(&mut project, paths)?;
     let name = paths[0].with_extension("");
@@ -25,7 +35,33 @@ pub fn run_main(paths: &[Utf8PathBuf]) -> Result<(), SgleamError> {
     if let Some(module) = get_module(&modules, &name) {
         let main = get_main(module)?;
         let show_output = main != MainFunction::Main;
-        JsEngine::new(project.fs.clone()).run_main(&module.name, main, show_output)?;
+        let engine = JsEngine::new(project.fs.clone());
+        if !trace.is_empty() || profile {
+            let found = instrument_user_modules(&mut project.fs, |name| {
+                profile || trace.iter().any(|traced| traced == name)
+            });
+            for name in trace.iter().filter(|name| !found.contains(name)) {
+                eprintln!("Ignoring `{name}`: is not a function of the program.");
+            }
+            let names: Vec<_> = trace
+                .iter()
+                .filter(|name| found.contains(name))
+                .map(String::as_str)
+                .collect();
+            engine.call_function(
+                "sgleam/sgleam_ffi.mjs",
+                "trace_functions",
+                &[names_array(&names)],
+            )?;
+        }
+        if profile {
+            start_profile(&engine)?;
+        }
+        let result = engine.run_main(&module.name, main, show_output);
+        if profile {
+            eprint!("{}", format_profile(&stop_profile(&engine)?, &project.fs));
+        }
+        result?;
     } else {
         // The compiler ignored the file because of the name and printed a warning.
     }
@@ -39,9 +75,26 @@ pub fn run_check(paths: &[Utf8PathBuf]) -> Result<(), SgleamError> {
     Ok(())
 }
 
-pub fn run_test(user_files: &[Utf8PathBuf], paths: &[Utf8PathBuf]) -> Result<(), SgleamError> {
+/// Runs the `_examples` functions of `user_files`. With `coverage`, the
+/// sources of the user modules are shown with the number of executions of
+/// each line, and with `lcov` the coverage is also written to that file.
+pub fn run_test(
+    user_files: &[Utf8PathBuf],
+    paths: &[Utf8PathBuf],
+    coverage: bool,
+    lcov: Option<&Utf8Path>,
+) -> Result<(), SgleamError> {
     let mut project = Project::default();
-    let modules = copy_files_and_build(&mut project, paths)?;
+    let mut modules = copy_files_and_build(&mut project, paths)?;
+    let probes = if coverage || lcov.is_some() {
+        let probes = instrument(&mut project, &modules);
+        modules = project.compile_quietly()?;
+        modules.retain(|module| !is_library_module(&module.name));
+        restore_sources(&mut project, &probes);
+        Some(probes)
+    } else {
+        None
+    };
     let modules: Vec<_> = modules
         .iter()
         .filter_map(|module| {
@@ -58,7 +111,55 @@ pub fn run_test(user_files: &[Utf8PathBuf], paths: &[Utf8PathBuf]) -> Result<(),
         })
         .collect();
 
-    JsEngine::new(project.fs.clone()).run_tests(&modules)?;
+    let engine = JsEngine::new(project.fs.clone());
+    engine.run_tests(&modules)?;
+    if let Some(probes) = probes {
+        let counts = coverage_counts(&engine)?;
+        if coverage {
+            print!("{}", format_coverage(&probes, &counts));
+        }
+        if let Some(lcov) = lcov {
+            std::fs::write(lcov, format_lcov(&probes, &counts))?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks `user_files` against the design recipe and shows the problems as
+/// warnings or, with `json`, as a JSON array in the standard output.
+pub fn run_lint(
+    user_files: &[Utf8PathBuf],
+    paths: &[Utf8PathBuf],
+    options: &LintOptions,
+    json: bool,
+) -> Result<(), SgleamError> {
+    let mut project = Project::default();
+    let modules = copy_files_and_build(&mut project, paths)?;
+    let lints: Vec<_> = modules
+        .iter()
+        .filter_map(|module| {
+            let path = module.input_path.strip_prefix(Project::source()).ok()?;
+            user_files
+                .iter()
+                .any(|file| file == path)
+                .then(|| lint_module(module, path.into(), options))
+        })
+        .flatten()
+        .collect();
+
+    if json {
+        print!("{}", lints_to_json(&lints));
+    } else if lints.is_empty() {
+        println!("No problems found.");
+    } else {
+        let buffer_writer = stderr_buffer_writer();
+        let mut buffer = buffer_writer.buffer();
+        for lint in &lints {
+            lint.to_diagnostic().write(&mut buffer);
+            writeln!(buffer).expect("write to buffer");
+        }
+        flush_buffer(&buffer_writer, &buffer);
+    }
     Ok(())
 }
 
@@ -122,13 +223,56 @@ pub fn copy_files_and_build(
     project: &mut Project,
     paths: &[Utf8PathBuf],
 ) -> Result<Vec<Module>, gleam_core::Error> {
+    let mut modules = copy_files_and_build_all(project, paths)?;
+    modules.retain(|module| !is_library_module(&module.name));
+    Ok(modules)
+}
+
+/// Like `copy_files_and_build`, but also returns the library modules
+/// (`gleam/*` and `sgleam/*`) compiled along with the user files.
+pub fn copy_files_and_build_all(
+    project: &mut Project,
+    paths: &[Utf8PathBuf],
+) -> Result<Vec<Module>, gleam_core::Error> {
+    copy_files(project, paths)?;
+    project.compile(false)
+}
+
+pub fn copy_files(project: &mut Project, paths: &[Utf8PathBuf]) -> Result<(), gleam_core::Error> {
     for path in paths.iter().filter(|p| validate_path(p)) {
         project.copy_file_to_source(path)?;
     }
-    let mut modules = project.compile(false)?;
-    modules
-        .retain(|module| !module.name.starts_with("gleam/") && !module.name.starts_with("sgleam/"));
-    Ok(modules)
+    Ok(())
+}
+
+/// Returns `path` relative to the current directory, which must contain it.
+pub fn make_relative_to_current_dir(path: Utf8PathBuf) -> Result<Utf8PathBuf, SgleamError> {
+    let current_dir = canonicalise(get_current_dir()?)?;
+    canonicalise(path.clone())?
+        .strip_prefix(&current_dir)
+        .map(|p| Utf8PathBuf::from(p.as_str().replace('\\', "/")))
+        .map_err(|_| SgleamError::PathNotInCurrentDir { current_dir, path })
+}
+
+fn canonicalise(path: Utf8PathBuf) -> Result<Utf8PathBuf, gleam_core::Error> {
+    path.canonicalize_utf8()
+        .map_err(|e| gleam_core::Error::FileIo {
+            kind: FileKind::File,
+            action: FileIoAction::Canonicalise,
+            path,
+            err: Some(e.to_string()),
+        })
+}
+
+fn get_current_dir() -> Result<Utf8PathBuf, gleam_core::Error> {
+    let curr_dir = std::env::current_dir().map_err(|e| gleam_core::Error::FileIo {
+        kind: FileKind::Directory,
+        action: FileIoAction::Open,
+        path: ".".into(),
+        err: Some(e.to_string()),
+    })?;
+    Utf8PathBuf::from_path_buf(curr_dir.clone())
+        .map_err(|_| gleam_core::Error::NonUtf8Path { path: curr_dir })
 }
 
 fn validate_path(path: &Utf8Path) -> bool {
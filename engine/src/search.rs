@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use ecow::EcoString;
+use gleam_core::type_::{Type, TypeVar};
+
+/// How the type of a value matches a searched type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TypeMatch {
+    /// The types are the same, up to the names of the type variables.
+    Exact,
+    /// The type of the value is more general, the searched type is one of
+    /// its instances (`fn(List(a)) -> Int` for `fn(List(Int)) -> Int`).
+    Generalized,
+}
+
+/// Returns how `candidate` matches `query`, if it matches.
+pub fn match_type(query: &Type, candidate: &Type) -> Option<TypeMatch> {
+    let query = SearchType::from(query);
+    let candidate = SearchType::from(candidate);
+    if !is_instance(&candidate, &query, &mut HashMap::new()) {
+        return None;
+    }
+    if is_instance(&query, &candidate, &mut HashMap::new()) {
+        Some(TypeMatch::Exact)
+    } else {
+        Some(TypeMatch::Generalized)
+    }
+}
+
+/// A type with the links of the type variables resolved.
+#[derive(Clone, Debug, PartialEq)]
+enum SearchType {
+    Var(u64),
+    Named {
+        module: EcoString,
+        name: EcoString,
+        arguments: Vec<SearchType>,
+    },
+    Fn {
+        arguments: Vec<SearchType>,
+        return_: Box<SearchType>,
+    },
+    Tuple(Vec<SearchType>),
+}
+
+impl From<&Type> for SearchType {
+    fn from(type_: &Type) -> Self {
+        match type_ {
+            Type::Named {
+                module,
+                name,
+                arguments,
+                ..
+            } => SearchType::Named {
+                module: module.clone(),
+                name: name.clone(),
+                arguments: arguments.iter().map(|t| t.as_ref().into()).collect(),
+            },
+            Type::Fn { arguments, return_ } => SearchType::Fn {
+                arguments: arguments.iter().map(|t| t.as_ref().into()).collect(),
+                return_: Box::new(return_.as_ref().into()),
+            },
+            Type::Tuple { .. } => SearchType::Tuple(
+                type_
+                    .tuple_types()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|t| t.as_ref().into())
+                    .collect(),
+            ),
+            Type::Var { type_ } => match &*type_.borrow() {
+                TypeVar::Link { type_ } => type_.as_ref().into(),
+                TypeVar::Unbound { id } | TypeVar::Generic { id } => SearchType::Var(*id),
+            },
+        }
+    }
+}
+
+/// Checks if `target` is an instance of `pattern`, that is, if the type
+/// variables of `pattern` can be replaced to get `target`. The type variables
+/// of `target` only match themselves.
+fn is_instance(
+    pattern: &SearchType,
+    target: &SearchType,
+    bindings: &mut HashMap<u64, SearchType>,
+) -> bool {
+    match (pattern, target) {
+        (SearchType::Var(id), _) => match bindings.get(id) {
+            Some(bound) => bound == target,
+            None => {
+                bindings.insert(*id, target.clone());
+                true
+            }
+        },
+        (
+            SearchType::Named {
+                module,
+                name,
+                arguments,
+            },
+            SearchType::Named {
+                module: target_module,
+                name: target_name,
+                arguments: target_arguments,
+            },
+        ) => {
+            module == target_module
+                && name == target_name
+                && are_instances(arguments, target_arguments, bindings)
+        }
+        (
+            SearchType::Fn { arguments, return_ },
+            SearchType::Fn {
+                arguments: target_arguments,
+                return_: target_return,
+            },
+        ) => {
+            are_instances(arguments, target_arguments, bindings)
+                && is_instance(return_, target_return, bindings)
+        }
+        (SearchType::Tuple(elements), SearchType::Tuple(target_elements)) => {
+            are_instances(elements, target_elements, bindings)
+        }
+        _ => false,
+    }
+}
+
+fn are_instances(
+    patterns: &[SearchType],
+    targets: &[SearchType],
+    bindings: &mut HashMap<u64, SearchType>,
+) -> bool {
+    patterns.len() == targets.len()
+        && patterns
+            .iter()
+            .zip(targets)
+            .all(|(pattern, target)| is_instance(pattern, target, bindings))
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+
+use camino::Utf8PathBuf;
+use ecow::EcoString;
+use gleam_core::{
+    ast::SrcSpan,
+    io::{FileSystemReader, memory::InMemoryFileSystem},
+};
+
+use crate::{
+    coverage::Insertions,
+    gleam::{Project, is_library_module},
+    trace::gleam_name,
+};
+
+/// The positions of the generated JavaScript of a module in its Gleam
+/// source, decoded from the source map written by the compiler.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// The mappings of each generated line, sorted by the generated column.
+    lines: Vec<Vec<Mapping>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Mapping {
+    column: u32,
+    source_line: u32,
+    source_column: u32,
+}
+
+impl SourceMap {
+    /// Parses the `mappings` of a source map in the JSON format. Only the
+    /// first source is used, the compiler writes one map for each module.
+    pub fn parse(json: &str) -> Option<SourceMap> {
+        let mappings = json_string_field(json, "mappings")?;
+        let mut lines = vec![];
+        let (mut source_line, mut source_column) = (0i64, 0i64);
+        for line in mappings.split(';') {
+            let mut column = 0i64;
+            let mut segments = vec![];
+            for segment in line.split(',').filter(|segment| !segment.is_empty()) {
+                let fields = decode_vlq(segment)?;
+                column += fields[0];
+                // Segments with only the generated column have no source.
+                if let [_, _, line, col, ..] = fields[..] {
+                    source_line += line;
+                    source_column += col;
+                    segments.push(Mapping {
+                        column: column as u32,
+                        source_line: source_line as u32,
+                        source_column: source_column as u32,
+                    });
+                }
+            }
+            lines.push(segments);
+        }
+        Some(SourceMap { lines })
+    }
+
+    /// Returns the source line and column of the generated `line` and
+    /// `column`, all starting at 0. Without the column, the first mapping of
+    /// the line is used.
+    pub fn lookup(&self, line: u32, column: Option<u32>) -> Option<(u32, u32)> {
+        let mappings = self.lines.get(line as usize)?;
+        let mapping = match column {
+            Some(column) => mappings
+                .iter()
+                .rev()
+                .find(|mapping| mapping.column <= column)
+                .or(mappings.first()),
+            None => mappings.first(),
+        }?;
+        Some((mapping.source_line, mapping.source_column))
+    }
+}
+
+/// Returns the value of the string field `name` of a JSON object, which must
+/// not have escapes.
+fn json_string_field<'a>(json: &'a str, name: &str) -> Option<&'a str> {
+    let start = json.find(&format!("\"{name}\""))? + name.len() + 2;
+    let rest = json[start..].trim_start().strip_prefix(':')?;
+    let rest = rest.trim_start().strip_prefix('"')?;
+    Some(&rest[..rest.find('"')?])
+}
+
+/// Decodes the base64 VLQ numbers of a segment of the mappings.
+fn decode_vlq(segment: &str) -> Option<Vec<i64>> {
+    let mut values = vec![];
+    let (mut value, mut shift) = (0i64, 0);
+    for c in segment.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as i64;
+        value |= (digit & 31) << shift;
+        if digit & 32 != 0 {
+            shift += 5;
+            continue;
+        }
+        values.push(if value & 1 == 1 {
+            -(value >> 1)
+        } else {
+            value >> 1
+        });
+        (value, shift) = (0, 0);
+    }
+    (!values.is_empty()).then_some(values)
+}
+
+/// A frame of the stack of a JavaScript error, as formatted by QuickJS
+/// (`at name (path:line:column)`). Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsFrame {
+    pub function: String,
+    pub path: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+pub fn parse_stack(stack: &str) -> Vec<JsFrame> {
+    stack
+        .lines()
+        .filter_map(|line| {
+            let (function, location) = line.trim().strip_prefix("at ")?.split_once(" (")?;
+            let (rest, last) = location.strip_suffix(')')?.rsplit_once(':')?;
+            let last = last.parse().ok()?;
+            let (path, line, column) = match rest
+                .rsplit_once(':')
+                .and_then(|(path, line)| Some((path, line.parse().ok()?)))
+            {
+                Some((path, line)) => (path, line, Some(last)),
+                None => (rest, last, None),
+            };
+            // The user modules reloaded by the REPL have a `?generation` suffix.
+            let path = path.split_once('?').map_or(path, |(path, _)| path);
+            Some(JsFrame {
+                function: function.into(),
+                path: path.into(),
+                line,
+                column,
+            })
+        })
+        .collect()
+}
+
+/// A frame of the stack in a Gleam source of the user.
+#[derive(Debug, Clone)]
+pub struct GleamFrame {
+    pub function: String,
+    /// The path of the source, relative to the current directory.
+    pub path: Utf8PathBuf,
+    pub src: EcoString,
+    /// The line of the frame, starting at 1.
+    pub line: usize,
+    /// From the position of the frame to the end of its line.
+    pub location: SrcSpan,
+}
+
+/// Maps the frames of `stack` in the user modules to their Gleam sources,
+/// using the source maps in the build directory of `fs`. The frames of the
+/// library and of code without sources (like the REPL) are skipped.
+pub fn gleam_frames(fs: &InMemoryFileSystem, stack: &str) -> Vec<GleamFrame> {
+    let mut modules: HashMap<String, Option<(SourceMap, EcoString, Option<Insertions>)>> =
+        HashMap::new();
+    parse_stack(stack)
+        .into_iter()
+        .filter_map(|frame| {
+            let module = frame
+                .path
+                .strip_prefix(Project::out().as_str())?
+                .strip_prefix('/')?
+                .strip_suffix(".mjs")?;
+            if module == "gleam" || module == "prelude" || is_library_module(module) {
+                return None;
+            }
+            let (map, src, insertions) = modules
+                .entry(module.into())
+                .or_insert_with(|| {
+                    let json = fs.read(&Project::out().join(format!("{module}.mjs.map")));
+                    let path = Project::source().join(format!("{module}.gleam"));
+                    let src = fs.read(&path).ok()?.into();
+                    Some((
+                        SourceMap::parse(&json.ok()?)?,
+                        src,
+                        Insertions::read(fs, &path),
+                    ))
+                })
+                .as_ref()?;
+            let (line, mut column) = map.lookup(
+                frame.line.checked_sub(1)?,
+                frame.column.map(|c| c.saturating_sub(1)),
+            )?;
+            // The source map of a module compiled with coverage refers to
+            // its instrumented source.
+            if let Some(insertions) = insertions {
+                column = insertions.original_column(src, line as usize, column as usize) as u32;
+            }
+            Some(GleamFrame {
+                function: gleam_name(&frame.function).into(),
+                path: format!("{module}.gleam").into(),
+                src: src.clone(),
+                line: line as usize + 1,
+                location: line_span(src, line as usize, Some(column as usize)),
+            })
+        })
+        .collect()
+}
+
+/// Returns the span from the `column` of the `line` of `src` (both starting
+/// at 0) to the end of the line. Without the column, the span starts at the
+/// first character that is not a space.
+pub fn line_span(src: &str, line: usize, column: Option<usize>) -> SrcSpan {
+    let line_start = src
+        .split_inclusive('\n')
+        .take(line)
+        .map(str::len)
+        .sum::<usize>()
+        .min(src.len());
+    let text = src[line_start..].lines().next().unwrap_or("").trim_end();
+    let start = match column {
+        Some(column) => text.char_indices().nth(column).map(|(index, _)| index),
+        None => text.find(|c: char| !c.is_whitespace()),
+    }
+    .unwrap_or(text.len());
+    SrcSpan {
+        start: (line_start + start) as u32,
+        end: (line_start + text.len()) as u32,
+    }
+}
+
+/// Consecutive repetitions of the same frames, as in a recursion.
+#[derive(Debug, Clone)]
+pub struct Recursion {
+    /// The frames of one repetition, the most recent first. There is more
+    /// than one for mutually recursive functions.
+    pub frames: Vec<GleamFrame>,
+    pub count: usize,
+}
+
+// The largest number of mutually recursive functions that are detected.
+const MAX_CYCLE: usize = 8;
+
+/// Groups the consecutive repetitions of the same calls in `frames`. Each
+/// frame not in a repetition is a group with a count of 1.
+pub fn collapse_recursion(frames: &[GleamFrame]) -> Vec<Recursion> {
+    let mut groups = vec![];
+    let mut start = 0;
+    while start < frames.len() {
+        // The smallest cycle wins the ties.
+        let (size, count) = (1..=MAX_CYCLE)
+            .rev()
+            .map(|size| (size, repetitions(&frames[start..], size)))
+            .filter(|&(_, count)| count > 1)
+            .max_by_key(|&(size, count)| size * count)
+            .unwrap_or((1, 1));
+        groups.push(Recursion {
+            frames: frames[start..start + size].to_vec(),
+            count,
+        });
+        start += size * count;
+    }
+    groups
+}
+
+fn repetitions(frames: &[GleamFrame], size: usize) -> usize {
+    let Some(cycle) = frames.get(..size) else {
+        return 0;
+    };
+    frames
+        .chunks_exact(size)
+        .take_while(|chunk| {
+            chunk
+                .iter()
+                .zip(cycle)
+                .all(|(frame, other)| frame.is_same_call(other))
+        })
+        .count()
+}
+
+impl GleamFrame {
+    fn is_same_call(&self, other: &GleamFrame) -> bool {
+        self.function == other.function && self.path == other.path && self.line == other.line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_vlq_values() {
+        assert_eq!(decode_vlq("AAAA"), Some(vec![0, 0, 0, 0]));
+        assert_eq!(decode_vlq("AACD"), Some(vec![0, 0, 1, -1]));
+        assert_eq!(decode_vlq("gBAAgB"), Some(vec![16, 0, 0, 16]));
+        assert_eq!(decode_vlq("A!"), None);
+    }
+
+    #[test]
+    fn lookup_finds_the_closest_mapping() {
+        let map = SourceMap::parse(r#"{"version": 3, "mappings": ";AAAA,IAAI;AACA"}"#).unwrap();
+        assert_eq!(map.lookup(0, Some(3)), None);
+        assert_eq!(map.lookup(1, Some(2)), Some((0, 0)));
+        assert_eq!(map.lookup(1, Some(9)), Some((0, 4)));
+        assert_eq!(map.lookup(2, None), Some((1, 4)));
+    }
+
+    fn frame(function: &str, line: usize) -> GleamFrame {
+        GleamFrame {
+            function: function.into(),
+            path: "m.gleam".into(),
+            src: "".into(),
+            line,
+            location: SrcSpan { start: 0, end: 0 },
+        }
+    }
+
+    fn collapsed(frames: &[GleamFrame]) -> Vec<(Vec<String>, usize)> {
+        collapse_recursion(frames)
+            .into_iter()
+            .map(|group| {
+                let names = group.frames.into_iter().map(|f| f.function).collect();
+                (names, group.count)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn collapse_recursion_groups_the_repeated_calls() {
+        let frames = [
+            frame("count_down", 3),
+            frame("count_down", 6),
+            frame("count_down", 6),
+            frame("count_down", 6),
+            frame("main", 2),
+        ];
+        assert_eq!(
+            collapsed(&frames),
+            [
+                (vec!["count_down".into()], 1),
+                (vec!["count_down".into()], 3),
+                (vec!["main".into()], 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn collapse_recursion_groups_the_mutual_calls() {
+        let frames = [
+            frame("is_odd", 9),
+            frame("is_even", 5),
+            frame("is_odd", 9),
+            frame("is_even", 5),
+            frame("is_odd", 9),
+            frame("main", 2),
+        ];
+        assert_eq!(
+            collapsed(&frames),
+            [
+                (vec!["is_odd".into(), "is_even".into()], 2),
+                (vec!["is_odd".into()], 1),
+                (vec!["main".into()], 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_span_ends_at_the_end_of_the_line() {
+        let src = "pub fn main() {\n  f(1)  \n}\n";
+        assert_eq!(line_span(src, 1, Some(2)), SrcSpan { start: 18, end: 22 });
+        assert_eq!(line_span(src, 1, None), SrcSpan { start: 18, end: 22 });
+        assert_eq!(line_span(src, 1, Some(4)), SrcSpan { start: 20, end: 22 });
+        assert_eq!(line_span(src, 5, None), SrcSpan { start: 27, end: 27 });
+    }
+
+    #[test]
+    fn parse_quickjs_stack() {
+        let stack = "    at fact (/build/fact.mjs:12:10)\n    at <eval> (eval_script:3)\n    at map (native)\n";
+        assert_eq!(
+            parse_stack(stack),
+            vec![
+                JsFrame {
+                    function: "fact".into(),
+                    path: "/build/fact.mjs".into(),
+                    line: 12,
+                    column: Some(10),
+                },
+                JsFrame {
+                    function: "<eval>".into(),
+                    path: "eval_script".into(),
+                    line: 3,
+                    column: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_stack_removes_the_generation() {
+        let stack = "    at fact (/build/fact.mjs?2:12:10)\n";
+        assert_eq!(parse_stack(stack)[0].path, "/build/fact.mjs");
+    }
+}
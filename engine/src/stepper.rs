@@ -1,55 +1,98 @@
 use camino::Utf8PathBuf;
-use gleam_core::ast::Statement;
+use gleam_core::io::FileSystemReader;
 
 use crate::{
+    engine::Engine,
     error::SgleamError,
     gleam::{Project, get_module},
-    run::copy_files_and_build,
-    substitution::{SubstitutionModule, SubstitutionStep},
+    quickjs::QuickJsEngine,
+    run::copy_files,
+    substitution::{StepperOptions, SubstitutionModule, SubstitutionStep},
 };
 
-pub fn build_stepper(path: Utf8PathBuf) -> Result<Vec<SubstitutionStep>, SgleamError> {
+/// The function appended to the module to step an entry expression in the
+/// scope of the module.
+const STEPPER_ENTRY: &str = "sgleam_stepper_entry";
+
+/// Steps through the body of `main` of the module at `path`, or through
+/// `expr` if given.
+pub fn build_stepper(
+    path: Utf8PathBuf,
+    expr: Option<&str>,
+    options: StepperOptions,
+) -> Result<Vec<SubstitutionStep>, SgleamError> {
     let paths = crate::gleam::find_imports(vec![path.clone()])?;
     let mut project = Project::default();
-    let modules = copy_files_and_build(&mut project, &paths)?;
+    copy_files(&mut project, &paths)?;
 
     let name = path.with_extension("");
     let name = name.as_str().replace('\\', "/");
+
+    steps_in_project(project, &name, expr, options)
+}
+
+/// Like `build_stepper`, for a module given by its source code.
+pub fn build_stepper_source(
+    source: &str,
+    expr: Option<&str>,
+    options: StepperOptions,
+) -> Result<Vec<SubstitutionStep>, SgleamError> {
+    let mut project = Project::default();
+    project.write_source("user.gleam", source);
+    steps_in_project(project, "user", expr, options)
+}
+
+fn steps_in_project(
+    mut project: Project,
+    name: &str,
+    expr: Option<&str>,
+    options: StepperOptions,
+) -> Result<Vec<SubstitutionStep>, SgleamError> {
+    if let Some(expr) = expr {
+        let file = format!("{name}.gleam");
+        let src = project.fs.read(&Project::source().join(&file))?;
+        project.write_source(
+            &file,
+            &format!("{src}\npub fn {STEPPER_ENTRY}() {{\n{expr}\n}}\n"),
+        );
+    }
+    let modules = project.compile(false)?;
+
     let module =
-        get_module(&modules, &name).ok_or_else(|| SgleamError::Other("Module not found".into()))?;
+        get_module(&modules, name).ok_or_else(|| SgleamError::Other("Module not found".into()))?;
 
-    let main = module
+    let entry = if expr.is_some() {
+        STEPPER_ENTRY
+    } else {
+        "main"
+    };
+    let function = module
         .ast
         .definitions
         .functions
         .iter()
-        .find(|f| f.name.as_ref().map(|n| n.1.as_str()) == Some("main"))
+        .find(|f| f.name.as_ref().map(|n| n.1.as_str()) == Some(entry))
         .ok_or_else(|| SgleamError::Other("Function main not found".into()))?;
 
-    let expr = match main.body.as_slice() {
-        [Statement::Expression(expr)] => expr.clone(),
-        _ => {
-            return Err(SgleamError::Other(
-                "main function has multiple statements; use a single expression like `1 + 2`"
-                    .into(),
-            ));
-        }
-    };
-
     let mut validator = crate::substitution::validate::Validator::new(
         module.code.clone(),
         module.input_path.clone(),
     );
-    validator.validate_expr(&expr)?;
+    for statement in &function.body {
+        validator.validate_statement(statement)?;
+    }
 
-    let untyped_expr = crate::substitution::convert::typed_to_untyped(&expr)?;
+    // A body with `let` statements is stepped as a block.
+    let untyped_expr = crate::substitution::convert::typed_body_to_untyped(&function.body)?;
 
     let mut substitution_module = SubstitutionModule::default();
     for module in modules {
         substitution_module.merge(SubstitutionModule::from_module(&module));
     }
+    substitution_module.set_engine(QuickJsEngine::new(project.fs.clone()));
+    substitution_module.options = options;
 
-    let trace = substitution_module.evaluate(&untyped_expr, 1000)?;
+    let trace = substitution_module.evaluate(&untyped_expr)?;
 
     Ok(trace.into_steps())
 }
@@ -1,11 +1,17 @@
-use gleam_core::ast::{
-    AssignmentKind, CallArg, Clause, Pattern, SrcSpan, Statement, TailPattern, TypedExpr,
-    UntypedExpr, UntypedStatement,
+use ecow::EcoString;
+use gleam_core::{
+    ast::{
+        Arg, AssignmentKind, CallArg, Clause, FunctionLiteralKind, ImplicitCallArgOrigin, Pattern,
+        SrcSpan, Statement, TailPattern, TypedExpr, TypedStatement, TypedUse, UntypedExpr,
+        UntypedStatement, Use, UseAssignment,
+    },
+    type_::{ModuleValueConstructor, ValueConstructorVariant},
 };
 use std::sync::Arc;
 use vec1::Vec1;
 
-use super::SubstitutionError;
+use super::{SubstitutionError, guard::convert_guard};
+use crate::gleam::is_library_module;
 
 const S: SrcSpan = SrcSpan { start: 0, end: 0 };
 
@@ -16,10 +22,7 @@ pub fn typed_body_to_untyped(
     if let [Statement::Expression(expr)] = body {
         return typed_to_untyped(expr);
     }
-    let stmts: Vec<UntypedStatement> = body
-        .iter()
-        .map(convert_statement)
-        .collect::<Result<Vec<_>, _>>()?;
+    let stmts = convert_statements(body)?;
 
     let stmts = Vec1::try_from_vec(stmts).unwrap_or_else(|_| {
         Vec1::try_from(vec![Statement::Expression(UntypedExpr::Var {
@@ -35,6 +38,28 @@ pub fn typed_body_to_untyped(
     })
 }
 
+/// A reference to the function `name` of `module`. Library functions and
+/// `qualified` references are referenced as `module.name`, with the full
+/// module path as the container so `SubstitutionModule` can find them;
+/// unqualified user functions by their name only.
+fn function_reference(module: &EcoString, name: &EcoString, qualified: bool) -> UntypedExpr {
+    if !qualified && !is_library_module(module) {
+        return UntypedExpr::Var {
+            location: S,
+            name: name.clone(),
+        };
+    }
+    UntypedExpr::FieldAccess {
+        location: S,
+        label_location: S,
+        label: name.clone(),
+        container: Box::new(UntypedExpr::Var {
+            location: S,
+            name: module.clone(),
+        }),
+    }
+}
+
 pub fn typed_to_untyped(expr: &TypedExpr) -> Result<UntypedExpr, SubstitutionError> {
     match expr {
         TypedExpr::Int {
@@ -58,10 +83,32 @@ pub fn typed_to_untyped(expr: &TypedExpr) -> Result<UntypedExpr, SubstitutionErr
             value: value.clone(),
         }),
 
-        TypedExpr::Var { name, .. } => Ok(UntypedExpr::Var {
-            location: S,
-            name: name.clone(),
-        }),
+        TypedExpr::Var {
+            name, constructor, ..
+        } => match &constructor.variant {
+            ValueConstructorVariant::ModuleFn {
+                module,
+                name: fn_name,
+                ..
+            } => Ok(function_reference(module, fn_name, false)),
+            _ => Ok(UntypedExpr::Var {
+                location: S,
+                name: name.clone(),
+            }),
+        },
+
+        TypedExpr::ModuleSelect { constructor, .. } => match constructor {
+            ModuleValueConstructor::Fn { module, name, .. } => {
+                Ok(function_reference(module, name, true))
+            }
+            ModuleValueConstructor::Record { name, .. } => Ok(UntypedExpr::Var {
+                location: S,
+                name: name.clone(),
+            }),
+            ModuleValueConstructor::Constant { .. } => {
+                panic!("Unsupported module constant should have been caught by validator")
+            }
+        },
 
         TypedExpr::BinOp {
             name,
@@ -134,10 +181,7 @@ pub fn typed_to_untyped(expr: &TypedExpr) -> Result<UntypedExpr, SubstitutionErr
         }),
 
         TypedExpr::Block { statements, .. } => {
-            let stmts: Vec<UntypedStatement> = statements
-                .iter()
-                .map(convert_statement)
-                .collect::<Result<Vec<_>, _>>()?;
+            let stmts = convert_statements(statements)?;
             let stmts = Vec1::try_from_vec(stmts).expect("Empty block caught by validator");
             Ok(UntypedExpr::Block {
                 location: S,
@@ -145,6 +189,32 @@ pub fn typed_to_untyped(expr: &TypedExpr) -> Result<UntypedExpr, SubstitutionErr
             })
         }
 
+        TypedExpr::Fn {
+            arguments, body, ..
+        } => Ok(UntypedExpr::Fn {
+            location: S,
+            end_of_head_byte_index: 0,
+            kind: FunctionLiteralKind::Anonymous { head: S },
+            arguments: arguments.iter().map(convert_arg).collect(),
+            body: Vec1::try_from_vec(convert_statements(body)?)
+                .expect("Empty function body caught by validator"),
+            return_annotation: None,
+        }),
+
+        TypedExpr::Echo {
+            expression: Some(expression),
+            message,
+            ..
+        } => Ok(UntypedExpr::Echo {
+            location: S,
+            keyword_end: 0,
+            expression: Some(Box::new(typed_to_untyped(expression)?)),
+            message: message
+                .as_ref()
+                .map(|m| typed_to_untyped(m).map(Box::new))
+                .transpose()?,
+        }),
+
         _ => panic!(
             "Unsupported expression should have been caught by validator: {:#?}",
             expr
@@ -172,14 +242,93 @@ fn convert_clause(
             .map(convert_pattern)
             .collect::<Vec<_>>(),
         alternative_patterns: vec![],
-        guard: None,
+        guard: clause.guard.as_ref().map(convert_guard),
         then: typed_to_untyped(&clause.then)?,
     })
 }
 
-fn convert_statement(
-    stmt: &gleam_core::ast::TypedStatement,
-) -> Result<UntypedStatement, SubstitutionError> {
+fn convert_arg(arg: &Arg<Arc<gleam_core::type_::Type>>) -> Arg<()> {
+    Arg {
+        names: arg.names.clone(),
+        location: S,
+        annotation: None,
+        type_: (),
+    }
+}
+
+/// Converts a sequence of statements. The typed `use` holds the rest of the
+/// block in its callback, which is put back after the `use` statement so the
+/// desugaring can be shown as a step.
+fn convert_statements(
+    stmts: &[TypedStatement],
+) -> Result<Vec<UntypedStatement>, SubstitutionError> {
+    let mut out = Vec::with_capacity(stmts.len());
+    for stmt in stmts {
+        match stmt {
+            Statement::Use(use_) => convert_use(use_, &mut out)?,
+            stmt => out.push(convert_statement(stmt)?),
+        }
+    }
+    Ok(out)
+}
+
+fn convert_use(use_: &TypedUse, out: &mut Vec<UntypedStatement>) -> Result<(), SubstitutionError> {
+    let TypedExpr::Call { fun, arguments, .. } = use_.call.as_ref() else {
+        panic!("A use call should be a function call");
+    };
+    let is_callback = |arg: &CallArg<TypedExpr>| arg.implicit == Some(ImplicitCallArgOrigin::Use);
+    let call = typed_to_untyped(fun)?;
+    let call_arguments = arguments
+        .iter()
+        .filter(|arg| !is_callback(arg))
+        .map(convert_call_arg)
+        .collect::<Result<Vec<_>, _>>()?;
+    let call = if call_arguments.is_empty() {
+        call
+    } else {
+        UntypedExpr::Call {
+            location: S,
+            fun: Box::new(call),
+            arguments: call_arguments,
+            open_parenthesis: 0,
+        }
+    };
+    out.push(Statement::Use(Use {
+        call: Box::new(call),
+        location: S,
+        right_hand_side_location: S,
+        assignments_location: S,
+        assignments: use_
+            .assignments
+            .iter()
+            .map(|assignment| UseAssignment {
+                location: S,
+                pattern: convert_pattern(&assignment.pattern),
+                annotation: None,
+            })
+            .collect(),
+    }));
+
+    let Some(TypedExpr::Fn { body, .. }) = arguments
+        .iter()
+        .find(|arg| is_callback(arg))
+        .map(|arg| &arg.value)
+    else {
+        panic!("A use call should have a callback");
+    };
+    // The assignments generated for the patterns of the `use` are created
+    // again when it is desugared.
+    let generated = body
+        .iter()
+        .take_while(|stmt| {
+            matches!(stmt, Statement::Assignment(a) if matches!(a.kind, AssignmentKind::Generated))
+        })
+        .count();
+    out.extend(convert_statements(&body[generated..])?);
+    Ok(())
+}
+
+fn convert_statement(stmt: &TypedStatement) -> Result<UntypedStatement, SubstitutionError> {
     match stmt {
         Statement::Expression(expr) => Ok(Statement::Expression(typed_to_untyped(expr)?)),
         Statement::Assignment(assignment) => {
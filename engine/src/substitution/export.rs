@@ -0,0 +1,283 @@
+//! Exports stepper traces as JSON, Markdown or a standalone HTML page.
+//!
+//! The reduced subexpression of each step comes from the spans tracked by the
+//! stepper. When a span is missing, it is found by comparing the rendered code
+//! of consecutive steps: the text between their common prefix and common
+//! suffix is the redex in the previous step and its result in the next one.
+
+use std::{fmt::Write as _, ops::Range, str::FromStr};
+
+use super::SubstitutionStep;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Json,
+    Markdown,
+    Html,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(TraceFormat::Json),
+            "markdown" | "md" => Ok(TraceFormat::Markdown),
+            "html" => Ok(TraceFormat::Html),
+            _ => Err(format!(
+                "unknown format `{s}`, expected json, markdown or html"
+            )),
+        }
+    }
+}
+
+pub fn export_trace(steps: &[SubstitutionStep], format: TraceFormat) -> String {
+    let steps = highlighted_steps(steps);
+    match format {
+        TraceFormat::Json => to_json(&steps, None),
+        TraceFormat::Markdown => to_markdown(&steps),
+        TraceFormat::Html => to_html(&steps),
+    }
+}
+
+/// A step with the byte ranges of the code that changed around it.
+struct HighlightedStep<'a> {
+    step: &'a SubstitutionStep,
+    /// The result of the reduction that produced this step.
+    result: Option<Range<usize>>,
+    /// The subexpression reduced to produce the next step.
+    redex: Option<Range<usize>>,
+}
+
+fn highlighted_steps(steps: &[SubstitutionStep]) -> Vec<HighlightedStep<'_>> {
+    let mut out: Vec<HighlightedStep> = steps
+        .iter()
+        .map(|step| HighlightedStep {
+            step,
+            result: None,
+            redex: None,
+        })
+        .collect();
+    for i in 1..out.len() {
+        let (redex, result) = changed_ranges(&steps[i - 1].formatted, &steps[i].formatted);
+        out[i - 1].redex = Some(steps[i].redex.clone().unwrap_or(redex));
+        out[i].result = Some(steps[i].result.clone().unwrap_or(result));
+    }
+    out
+}
+
+/// Returns the byte ranges of `before` and `after` outside their common
+/// prefix and suffix.
+pub fn changed_ranges(before: &str, after: &str) -> (Range<usize>, Range<usize>) {
+    let prefix: usize = before
+        .chars()
+        .zip(after.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    let suffix: usize = before[prefix..]
+        .chars()
+        .rev()
+        .zip(after[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    (prefix..before.len() - suffix, prefix..after.len() - suffix)
+}
+
+// ---------------------------------------------------------------------------
+// JSON
+// ---------------------------------------------------------------------------
+
+/// Exports `steps` as JSON. When the stepper failed, `error` holds its
+/// diagnostics, exported in an `error` field.
+pub fn trace_json(steps: &[SubstitutionStep], error: Option<&str>) -> String {
+    to_json(&highlighted_steps(steps), error)
+}
+
+fn to_json(steps: &[HighlightedStep], error: Option<&str>) -> String {
+    let mut out = String::from("{\"steps\": [");
+    for (index, h) in steps.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let code = &h.step.formatted;
+        let _ = write!(
+            out,
+            "\n  {{\"index\": {index}, \"code\": {}, \"note\": {}, \"context\": {}, \"result\": {}, \"redex\": {}}}",
+            json_string(code),
+            json_opt_string(h.step.note.as_deref()),
+            json_opt_string(h.step.context.as_deref()),
+            json_range(code, h.result.as_ref()),
+            json_range(code, h.redex.as_ref()),
+        );
+    }
+    out.push_str("\n]");
+    if let Some(error) = error {
+        let _ = write!(out, ", \"error\": {}", json_string(error));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Ranges are exported as character offsets, which are easier to use from
+/// JavaScript than byte offsets.
+fn json_range(code: &str, range: Option<&Range<usize>>) -> String {
+    match range {
+        Some(range) => format!(
+            "{{\"start\": {}, \"end\": {}}}",
+            code[..range.start].chars().count(),
+            code[..range.end].chars().count()
+        ),
+        None => "null".into(),
+    }
+}
+
+fn json_opt_string(s: Option<&str>) -> String {
+    s.map(json_string).unwrap_or_else(|| "null".into())
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Markdown
+// ---------------------------------------------------------------------------
+
+fn to_markdown(steps: &[HighlightedStep]) -> String {
+    let mut out = String::new();
+    for (index, h) in steps.iter().enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "### Step {}\n", index + 1);
+        if let Some(note) = &h.step.note {
+            let _ = writeln!(out, "*{note}*\n");
+        }
+        let _ = writeln!(out, "```gleam\n{}\n```", h.step.formatted);
+        if let Some(redex) = &h.redex
+            && !redex.is_empty()
+        {
+            let redex = h.step.formatted[redex.clone()].trim();
+            let ticks = if redex.contains('`') { "``" } else { "`" };
+            let _ = writeln!(out, "\nNext: {ticks} {redex} {ticks} is reduced.");
+        }
+        if let Some(context) = &h.step.context {
+            let _ = writeln!(out, "\nUsing:\n\n```gleam\n{context}\n```");
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// HTML
+// ---------------------------------------------------------------------------
+
+const HTML_STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+header { display: flex; gap: 1em; align-items: center; }
+.step { display: none; }
+.step.current { display: block; }
+.panes { display: flex; gap: 1em; }
+.pane { flex: 1; min-width: 0; }
+pre { background: #f6f8fa; padding: 1em; border-radius: 4px; overflow-x: auto; }
+mark.redex { background: #ffe08a; }
+mark.result { background: #b7f0b1; }
+.note { font-style: italic; color: #555; }
+.help { color: #777; font-size: 0.9em; }
+"#;
+
+const HTML_SCRIPT: &str = r#"
+const steps = document.querySelectorAll(".step");
+const counter = document.getElementById("counter");
+let current = 0;
+function show(index) {
+  current = Math.max(0, Math.min(steps.length - 1, index));
+  steps.forEach((step, i) => step.classList.toggle("current", i === current));
+  counter.textContent = `Step ${current + 1}/${steps.length}`;
+}
+document.addEventListener("keydown", (event) => {
+  switch (event.key) {
+    case "ArrowRight": case "ArrowDown": case "l": case "j": case " ":
+      show(current + 1); break;
+    case "ArrowLeft": case "ArrowUp": case "h": case "k":
+      show(current - 1); break;
+    case "Home": show(0); break;
+    case "End": show(steps.length - 1); break;
+    default: return;
+  }
+  event.preventDefault();
+});
+show(0);
+"#;
+
+fn to_html(steps: &[HighlightedStep]) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Stepper</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n<header><strong id=\"counter\"></strong><span class=\"help\">arrows: navigate, Home/End: first/last step</span></header>\n"
+    );
+    for (index, h) in steps.iter().enumerate() {
+        let _ = writeln!(out, "<section class=\"step\">");
+        if let Some(note) = &h.step.note {
+            let _ = writeln!(out, "<p class=\"note\">{}</p>", html_escape(note));
+        }
+        let _ = writeln!(out, "<div class=\"panes\">");
+        if index > 0 {
+            let previous = &steps[index - 1];
+            let _ = writeln!(
+                out,
+                "<div class=\"pane\"><pre><code>{}</code></pre></div>",
+                html_code(&previous.step.formatted, previous.redex.as_ref(), "redex")
+            );
+        }
+        let _ = writeln!(
+            out,
+            "<div class=\"pane\"><pre><code>{}</code></pre></div>",
+            html_code(&h.step.formatted, h.result.as_ref(), "result")
+        );
+        let _ = writeln!(out, "</div>");
+        if let Some(context) = &h.step.context {
+            let _ = writeln!(out, "<pre><code>{}</code></pre>", html_escape(context));
+        }
+        let _ = writeln!(out, "</section>");
+    }
+    let _ = write!(out, "<script>{HTML_SCRIPT}</script>\n</body>\n</html>\n");
+    out
+}
+
+fn html_code(code: &str, range: Option<&Range<usize>>, class: &str) -> String {
+    match range {
+        Some(range) if !range.is_empty() => format!(
+            "{}<mark class=\"{class}\">{}</mark>{}",
+            html_escape(&code[..range.start]),
+            html_escape(&code[range.clone()]),
+            html_escape(&code[range.end..])
+        ),
+        _ => html_escape(code),
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
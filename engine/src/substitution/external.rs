@@ -0,0 +1,245 @@
+//! Functions the stepper evaluates in a single step by running the compiled
+//! JavaScript code, like `@external` functions and library functions that
+//! cannot be stepped through.
+//!
+//! Arguments are converted to JavaScript source and the result is read back
+//! by parsing its `string.inspect` representation as a Gleam expression.
+
+use std::{fmt, rc::Rc};
+
+use ecow::EcoString;
+use gleam_core::ast::{Statement, UntypedExpr};
+
+use crate::{
+    engine::Engine,
+    parser::{ReplItem, parse_repl},
+};
+
+use super::{SubstitutionError, reduce::is_value, render::render_expr};
+
+/// A function evaluated through the JavaScript engine.
+#[derive(Debug, Clone)]
+pub struct SubstitutionExternal {
+    /// The name shown in the step notes, like `int.to_string`.
+    pub name: EcoString,
+    /// The JavaScript module, relative to the build directory.
+    pub module: String,
+    /// The function exported by `module`.
+    pub function: EcoString,
+}
+
+impl SubstitutionExternal {
+    /// An `@external(javascript, path, function)` declared in `module`.
+    pub fn declared(name: EcoString, module: &str, path: &str, function: &EcoString) -> Self {
+        Self {
+            name,
+            module: resolve_js_module(module, path),
+            function: function.clone(),
+        }
+    }
+
+    /// A public function of `module` called through its compiled code.
+    pub fn exported(name: EcoString, module: &str, function: &str) -> Self {
+        Self {
+            name,
+            module: format!("{module}.mjs"),
+            function: js_name(function),
+        }
+    }
+}
+
+type CallFunction = dyn Fn(&str, &str, &[String]) -> Result<String, String>;
+
+/// Calls JavaScript functions on behalf of the stepper.
+#[derive(Clone)]
+pub struct ExternalCaller(Rc<CallFunction>);
+
+impl ExternalCaller {
+    pub fn new<E: Engine>(engine: E) -> Self {
+        Self(Rc::new(move |module, function, arguments| {
+            engine
+                .call_function(module, function, arguments)
+                .map_err(|err| err.to_string())
+        }))
+    }
+
+    pub fn call(
+        &self,
+        external: &SubstitutionExternal,
+        arguments: &[UntypedExpr],
+    ) -> Result<UntypedExpr, SubstitutionError> {
+        let error = |message: String| SubstitutionError::External {
+            function: external.name.to_string(),
+            message,
+        };
+        let arguments = arguments
+            .iter()
+            .map(to_js)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error)?;
+        let result = (self.0)(&external.module, &external.function, &arguments).map_err(error)?;
+        from_inspect(&result).map_err(error)
+    }
+}
+
+impl fmt::Debug for ExternalCaller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExternalCaller")
+    }
+}
+
+/// Converts a value to the JavaScript source of its runtime representation.
+fn to_js(expr: &UntypedExpr) -> Result<String, String> {
+    match expr {
+        UntypedExpr::Int { int_value, .. } if gleam_core::javascript::is_bigint_enabled() => {
+            Ok(format!("{int_value}n"))
+        }
+        UntypedExpr::Int { int_value, .. } => Ok(int_value.to_string()),
+        UntypedExpr::Float { float_value, .. } => Ok(float_value.value().to_string()),
+        UntypedExpr::String { value, .. } => Ok(js_string(value)),
+        UntypedExpr::NegateInt { value, .. } => Ok(format!("-{}", to_js(value)?)),
+        UntypedExpr::Var { name, .. } if name == "True" => Ok("true".into()),
+        UntypedExpr::Var { name, .. } if name == "False" => Ok("false".into()),
+        UntypedExpr::Var { name, .. } if name == "Nil" => Ok("undefined".into()),
+        UntypedExpr::List { elements, tail, .. } => {
+            let elements = elements.iter().map(to_js).collect::<Result<Vec<_>, _>>()?;
+            let tail = match tail {
+                Some(tail) => format!(", {}", to_js(tail)?),
+                None => String::new(),
+            };
+            Ok(format!("toList([{}]{tail})", elements.join(", ")))
+        }
+        UntypedExpr::Tuple { elements, .. } => {
+            let elements = elements.iter().map(to_js).collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("[{}]", elements.join(", ")))
+        }
+        UntypedExpr::Call { fun, arguments, .. } => match (fun.as_ref(), arguments.as_slice()) {
+            (UntypedExpr::Var { name, .. }, [argument]) if name == "Ok" || name == "Error" => {
+                Ok(format!("new {name}({})", to_js(&argument.value)?))
+            }
+            _ => Err(cannot_pass(expr)),
+        },
+        _ => Err(cannot_pass(expr)),
+    }
+}
+
+fn cannot_pass(expr: &UntypedExpr) -> String {
+    let value = render_expr(expr).unwrap_or_else(|_| "this value".into());
+    format!("cannot pass `{value}` to JavaScript")
+}
+
+fn js_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    // The value is Gleam source, so escapes other than `\u{...}` have the
+    // same meaning in JavaScript.
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'u') => {
+                chars.next();
+                out.push_str("\\u");
+            }
+            '\\' => {
+                out.push('\\');
+                if let Some(next) = chars.next() {
+                    out.push(next);
+                }
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parses the output of `string.inspect` back into a value.
+pub fn from_inspect(text: &str) -> Result<UntypedExpr, String> {
+    let cannot_represent = || format!("cannot represent the result `{text}`");
+    let items = parse_repl(text).map_err(|_| cannot_represent())?;
+    match items.as_slice() {
+        [ReplItem::ReplStatement(Statement::Expression(expr))] if is_value(expr) => {
+            Ok(expr.clone())
+        }
+        _ => Err(cannot_represent()),
+    }
+}
+
+/// Resolves the path of an `@external` JavaScript module declared in `module`
+/// to a path relative to the build directory.
+fn resolve_js_module(module: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = module.split('/').collect();
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// The name of a Gleam function in the generated JavaScript code.
+fn js_name(name: &str) -> EcoString {
+    const RESERVED: &[&str] = &[
+        "await",
+        "arguments",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "debugger",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "enum",
+        "eval",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "function",
+        "if",
+        "implements",
+        "import",
+        "in",
+        "instanceof",
+        "interface",
+        "let",
+        "new",
+        "null",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "static",
+        "super",
+        "switch",
+        "then",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typeof",
+        "undefined",
+        "var",
+        "void",
+        "while",
+        "with",
+        "yield",
+    ];
+    if RESERVED.contains(&name) {
+        format!("{name}$").into()
+    } else {
+        name.into()
+    }
+}
@@ -0,0 +1,348 @@
+//! Conversions between case clause guards and expressions.
+//!
+//! Guards are kept as `ClauseGuard` inside the untyped clauses so they render
+//! as guards, but they are evaluated (and substituted) as ordinary
+//! expressions, reusing the primitive operator reductions. The values that
+//! have no guard syntax (lists, tuples and constructors with arguments) are
+//! kept as variables named by their source.
+
+use std::collections::HashMap;
+
+use ecow::EcoString;
+use gleam_core::ast::{
+    BinOp, ClauseGuard, Constant, SrcSpan, TypedClauseGuard, UntypedClauseGuard, UntypedExpr,
+};
+
+use super::{SubstitutionError, external::from_inspect, reduce::is_value, render::render_expr};
+
+const S: SrcSpan = SrcSpan { start: 0, end: 0 };
+
+/// Returns a description of the first construct in `guard` that the stepper
+/// cannot evaluate.
+pub fn unsupported_guard(guard: &TypedClauseGuard) -> Option<&'static str> {
+    if let Some((_, left, right)) = guard_bin_op(guard) {
+        return unsupported_guard(left).or_else(|| unsupported_guard(right));
+    }
+    match guard {
+        ClauseGuard::Not { expression, .. } => unsupported_guard(expression),
+        ClauseGuard::Var { .. } => None,
+        ClauseGuard::Constant(constant) => match constant {
+            Constant::Int { .. } | Constant::Float { .. } | Constant::String { .. } => None,
+            Constant::Record { arguments, .. } if arguments.is_empty() => None,
+            _ => Some("this kind of constant in case guards"),
+        },
+        ClauseGuard::TupleIndex { .. } => Some("tuple indexing in case guards"),
+        ClauseGuard::FieldAccess { .. } => Some("record access in case guards"),
+        ClauseGuard::ModuleSelect { .. } => Some("module access in case guards"),
+        _ => Some("this kind of case guard"),
+    }
+}
+
+/// Converts a typed guard to an untyped one. Unsupported guards must have
+/// been rejected by the validator.
+pub fn convert_guard(guard: &TypedClauseGuard) -> UntypedClauseGuard {
+    if let Some((op, left, right)) = guard_bin_op(guard) {
+        return bin_op_guard(op, convert_guard(left), convert_guard(right));
+    }
+    match guard {
+        ClauseGuard::Not { expression, .. } => ClauseGuard::Not {
+            location: S,
+            expression: Box::new(convert_guard(expression)),
+        },
+        ClauseGuard::Var { name, .. } => var_guard(name.clone()),
+        ClauseGuard::Constant(constant) => match constant {
+            Constant::Int {
+                value, int_value, ..
+            } => ClauseGuard::Constant(Constant::Int {
+                location: S,
+                value: value.clone(),
+                int_value: int_value.clone(),
+            }),
+            Constant::Float {
+                value, float_value, ..
+            } => ClauseGuard::Constant(Constant::Float {
+                location: S,
+                value: value.clone(),
+                float_value: *float_value,
+            }),
+            Constant::String { value, .. } => ClauseGuard::Constant(Constant::String {
+                location: S,
+                value: value.clone(),
+            }),
+            Constant::Record { name, .. } => var_guard(name.clone()),
+            _ => panic!("Unsupported guard constant should have been caught by validator"),
+        },
+        _ => panic!("Unsupported guard should have been caught by validator"),
+    }
+}
+
+/// Converts a guard to the equivalent expression.
+pub fn guard_to_expr(guard: &UntypedClauseGuard) -> UntypedExpr {
+    if let Some((name, left, right)) = guard_bin_op(guard) {
+        return UntypedExpr::BinOp {
+            location: S,
+            name,
+            name_location: S,
+            left: Box::new(guard_to_expr(left)),
+            right: Box::new(guard_to_expr(right)),
+        };
+    }
+    match guard {
+        ClauseGuard::Not { expression, .. } => UntypedExpr::NegateBool {
+            location: S,
+            value: Box::new(guard_to_expr(expression)),
+        },
+        ClauseGuard::Var { name, .. } if is_name(name) => UntypedExpr::Var {
+            location: S,
+            name: name.clone(),
+        },
+        ClauseGuard::Var { name, .. } => {
+            from_inspect(name).expect("a guard value is named by its source")
+        }
+        ClauseGuard::Constant(Constant::Int {
+            value, int_value, ..
+        }) => UntypedExpr::Int {
+            location: S,
+            value: value.clone(),
+            int_value: int_value.clone(),
+        },
+        ClauseGuard::Constant(Constant::Float {
+            value, float_value, ..
+        }) => UntypedExpr::Float {
+            location: S,
+            value: value.clone(),
+            float_value: *float_value,
+        },
+        ClauseGuard::Constant(Constant::String { value, .. }) => UntypedExpr::String {
+            location: S,
+            value: value.clone(),
+        },
+        _ => panic!("Unsupported guard should have been caught by validator"),
+    }
+}
+
+/// Converts an expression back to a guard. Values that have no guard syntax
+/// (constructors with arguments, lists, tuples) become variables named by
+/// their source.
+pub fn expr_to_guard(expr: &UntypedExpr) -> Result<UntypedClauseGuard, SubstitutionError> {
+    let guard = match expr {
+        UntypedExpr::BinOp {
+            name, left, right, ..
+        } => bin_op_guard(*name, expr_to_guard(left)?, expr_to_guard(right)?),
+        UntypedExpr::NegateBool { value, .. } => ClauseGuard::Not {
+            location: S,
+            expression: Box::new(expr_to_guard(value)?),
+        },
+        UntypedExpr::Var { name, .. } => var_guard(name.clone()),
+        UntypedExpr::Int {
+            value, int_value, ..
+        } => ClauseGuard::Constant(Constant::Int {
+            location: S,
+            value: value.clone(),
+            int_value: int_value.clone(),
+        }),
+        UntypedExpr::Float {
+            value, float_value, ..
+        } => ClauseGuard::Constant(Constant::Float {
+            location: S,
+            value: value.clone(),
+            float_value: *float_value,
+        }),
+        UntypedExpr::String { value, .. } => ClauseGuard::Constant(Constant::String {
+            location: S,
+            value: value.clone(),
+        }),
+        UntypedExpr::NegateInt { value, .. } => match value.as_ref() {
+            UntypedExpr::Int {
+                value, int_value, ..
+            } => ClauseGuard::Constant(Constant::Int {
+                location: S,
+                value: format!("-{value}").into(),
+                int_value: -int_value.clone(),
+            }),
+            _ => return Err(unsupported_guard_value(expr)),
+        },
+        _ if is_value(expr) => var_guard(render_expr(expr)?.into()),
+        _ => return Err(unsupported_guard_value(expr)),
+    };
+    Ok(guard)
+}
+
+/// Replaces the variables of `guard` bound in `env`.
+pub fn substitute_guard(
+    guard: &UntypedClauseGuard,
+    env: &HashMap<EcoString, UntypedExpr>,
+) -> Result<UntypedClauseGuard, SubstitutionError> {
+    let expr = super::reduce::substitute_expr(&guard_to_expr(guard), env)?;
+    expr_to_guard(&expr)
+}
+
+fn unsupported_guard_value(expr: &UntypedExpr) -> SubstitutionError {
+    match render_expr(expr) {
+        Ok(value) => SubstitutionError::UnsupportedGuardValue { value },
+        Err(err) => err,
+    }
+}
+
+/// Checks if `name` is a variable or constructor name, and not the source of
+/// a value.
+fn is_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn var_guard(name: EcoString) -> UntypedClauseGuard {
+    ClauseGuard::Var {
+        location: S,
+        type_: (),
+        name,
+        definition_location: S,
+    }
+}
+
+fn guard_bin_op<T, R>(
+    guard: &ClauseGuard<T, R>,
+) -> Option<(BinOp, &ClauseGuard<T, R>, &ClauseGuard<T, R>)> {
+    let (op, left, right) = match guard {
+        ClauseGuard::Equals { left, right, .. } => (BinOp::Eq, left, right),
+        ClauseGuard::NotEquals { left, right, .. } => (BinOp::NotEq, left, right),
+        ClauseGuard::GtInt { left, right, .. } => (BinOp::GtInt, left, right),
+        ClauseGuard::GtEqInt { left, right, .. } => (BinOp::GtEqInt, left, right),
+        ClauseGuard::LtInt { left, right, .. } => (BinOp::LtInt, left, right),
+        ClauseGuard::LtEqInt { left, right, .. } => (BinOp::LtEqInt, left, right),
+        ClauseGuard::GtFloat { left, right, .. } => (BinOp::GtFloat, left, right),
+        ClauseGuard::GtEqFloat { left, right, .. } => (BinOp::GtEqFloat, left, right),
+        ClauseGuard::LtFloat { left, right, .. } => (BinOp::LtFloat, left, right),
+        ClauseGuard::LtEqFloat { left, right, .. } => (BinOp::LtEqFloat, left, right),
+        ClauseGuard::AddInt { left, right, .. } => (BinOp::AddInt, left, right),
+        ClauseGuard::AddFloat { left, right, .. } => (BinOp::AddFloat, left, right),
+        ClauseGuard::SubInt { left, right, .. } => (BinOp::SubInt, left, right),
+        ClauseGuard::SubFloat { left, right, .. } => (BinOp::SubFloat, left, right),
+        ClauseGuard::MultInt { left, right, .. } => (BinOp::MultInt, left, right),
+        ClauseGuard::MultFloat { left, right, .. } => (BinOp::MultFloat, left, right),
+        ClauseGuard::DivInt { left, right, .. } => (BinOp::DivInt, left, right),
+        ClauseGuard::DivFloat { left, right, .. } => (BinOp::DivFloat, left, right),
+        ClauseGuard::RemainderInt { left, right, .. } => (BinOp::RemainderInt, left, right),
+        ClauseGuard::Or { left, right, .. } => (BinOp::Or, left, right),
+        ClauseGuard::And { left, right, .. } => (BinOp::And, left, right),
+        _ => return None,
+    };
+    Some((op, left.as_ref(), right.as_ref()))
+}
+
+fn bin_op_guard(
+    op: BinOp,
+    left: UntypedClauseGuard,
+    right: UntypedClauseGuard,
+) -> UntypedClauseGuard {
+    let location = S;
+    let left = Box::new(left);
+    let right = Box::new(right);
+    match op {
+        BinOp::Eq => ClauseGuard::Equals {
+            location,
+            left,
+            right,
+        },
+        BinOp::NotEq => ClauseGuard::NotEquals {
+            location,
+            left,
+            right,
+        },
+        BinOp::GtInt => ClauseGuard::GtInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::GtEqInt => ClauseGuard::GtEqInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::LtInt => ClauseGuard::LtInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::LtEqInt => ClauseGuard::LtEqInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::GtFloat => ClauseGuard::GtFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::GtEqFloat => ClauseGuard::GtEqFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::LtFloat => ClauseGuard::LtFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::LtEqFloat => ClauseGuard::LtEqFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::AddInt => ClauseGuard::AddInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::AddFloat => ClauseGuard::AddFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::SubInt => ClauseGuard::SubInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::SubFloat => ClauseGuard::SubFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::MultInt => ClauseGuard::MultInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::MultFloat => ClauseGuard::MultFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::DivInt => ClauseGuard::DivInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::DivFloat => ClauseGuard::DivFloat {
+            location,
+            left,
+            right,
+        },
+        BinOp::RemainderInt => ClauseGuard::RemainderInt {
+            location,
+            left,
+            right,
+        },
+        BinOp::Or => ClauseGuard::Or {
+            location,
+            left,
+            right,
+        },
+        BinOp::And => ClauseGuard::And {
+            location,
+            left,
+            right,
+        },
+        BinOp::Concatenate => panic!("String concatenation is not allowed in case guards"),
+    }
+}
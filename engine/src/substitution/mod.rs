@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
 
 use ecow::EcoString;
 use gleam_core::ast::{SrcSpan, UntypedExpr};
@@ -6,14 +9,20 @@ use gleam_core::build::Module;
 use thiserror::Error;
 
 pub mod convert;
+pub mod export;
+pub mod external;
+pub mod guard;
 pub mod reduce;
 pub mod render;
 pub mod runtime_vars;
 pub mod validate;
 
+use external::{ExternalCaller, SubstitutionExternal};
 use render::render_expr;
 use validate::Validator;
 
+use crate::{engine::Engine, gleam::is_library_module};
+
 // ---------------------------------------------------------------------------
 // Data types
 // ---------------------------------------------------------------------------
@@ -24,6 +33,15 @@ pub struct SubstitutionStep {
     pub formatted: String,
     pub note: Option<String>,
     pub context: Option<String>,
+    /// The child indexes (see `render::child`) leading to the subexpression
+    /// reduced to produce this step.
+    pub path: Vec<usize>,
+    /// The byte range of the reduced subexpression in the previous step.
+    pub redex: Option<Range<usize>>,
+    /// The byte range of the result of the reduction in `formatted`.
+    pub result: Option<Range<usize>>,
+    /// The function called to produce this step, if any.
+    pub call: Option<EcoString>,
 }
 
 impl SubstitutionStep {
@@ -34,6 +52,22 @@ impl SubstitutionStep {
             formatted,
             note,
             context: None,
+            path: vec![],
+            redex: None,
+            result: None,
+            call: None,
+        }
+    }
+
+    /// Lifts a step that reduced the child `index` of `expr` to a step of
+    /// `expr`, which must already contain the reduced child.
+    pub fn within(self, index: usize, expr: UntypedExpr) -> Self {
+        let mut path = vec![index];
+        path.extend(self.path);
+        Self {
+            path,
+            call: self.call,
+            ..Self::new(expr, self.note).with_context_opt(self.context)
         }
     }
 
@@ -48,6 +82,87 @@ impl SubstitutionStep {
     }
 }
 
+/// Controls how much of an evaluation the stepper shows.
+#[derive(Debug, Clone)]
+pub struct StepperOptions {
+    /// The maximum number of steps of an evaluation.
+    pub max_steps: usize,
+    /// Calls to these functions are shown as a single step.
+    pub collapse: HashSet<EcoString>,
+    /// Calls made deeper than this (calls in the entry expression have depth
+    /// 0, calls in their bodies depth 1, and so on) are shown as a single
+    /// step.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for StepperOptions {
+    fn default() -> Self {
+        Self {
+            max_steps: 1000,
+            collapse: HashSet::new(),
+            max_depth: None,
+        }
+    }
+}
+
+impl StepperOptions {
+    /// Parses the options at the start of `input` (`--max-steps N`,
+    /// `--collapse NAME` and `--max-depth N`), returning the options and the
+    /// rest of `input`.
+    pub fn parse_prefix(input: &str) -> Result<(Self, &str), String> {
+        let mut options = Self::default();
+        let mut rest = input.trim_start();
+        while let Some(after) = rest.strip_prefix("--") {
+            let (name, after) = after.split_once(char::is_whitespace).unwrap_or((after, ""));
+            let after = after.trim_start();
+            let (value, after) = after.split_once(char::is_whitespace).unwrap_or((after, ""));
+            if value.is_empty() {
+                return Err(format!("missing value for --{name}"));
+            }
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid value for --{name}: {value}"))
+            };
+            match name {
+                "max-steps" => options.max_steps = number()?,
+                "max-depth" => options.max_depth = Some(number()?),
+                "collapse" => {
+                    options.collapse.insert(value.into());
+                }
+                _ => return Err(format!("unknown option --{name}")),
+            }
+            rest = after.trim_start();
+        }
+        Ok((options, rest))
+    }
+
+    fn collapses(&self, function: &str, depth: usize) -> bool {
+        self.collapse.contains(function) || self.max_depth.is_some_and(|max| depth > max)
+    }
+}
+
+/// The functions called in `steps`, in the order of their first call.
+pub fn called_functions(steps: &[SubstitutionStep]) -> Vec<&str> {
+    let mut functions: Vec<&str> = vec![];
+    for function in steps.iter().filter_map(|step| step.call.as_deref()) {
+        if !functions.contains(&function) {
+            functions.push(function);
+        }
+    }
+    functions
+}
+
+/// The index of the first step after `current` that calls `function`.
+pub fn next_call(steps: &[SubstitutionStep], current: usize, function: &str) -> Option<usize> {
+    steps
+        .iter()
+        .enumerate()
+        .skip(current + 1)
+        .find(|(_, step)| step.call.as_deref() == Some(function))
+        .map(|(index, _)| index)
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct SubstitutionTrace {
     steps: Vec<SubstitutionStep>,
@@ -74,25 +189,63 @@ impl SubstitutionTrace {
 #[derive(Debug, Clone)]
 pub struct SubstitutionFunction {
     pub name: EcoString,
+    /// The defining module, for library functions.
+    pub module: Option<EcoString>,
     pub arguments: Vec<EcoString>,
     pub body: UntypedExpr,
 }
 
+impl SubstitutionFunction {
+    /// The name used in the step notes, qualified for library functions.
+    pub fn display_name(&self) -> EcoString {
+        match &self.module {
+            Some(module) => qualified_display_name(module, &self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Functions available to the stepper.
+///
+/// User functions are keyed by their name and by `module.name`. Library
+/// functions are keyed by `module.name` (e.g. `gleam/list.length`) only,
+/// which is how `convert` refers to them.
 #[derive(Debug, Clone, Default)]
 pub struct SubstitutionModule {
     pub functions: HashMap<EcoString, SubstitutionFunction>,
+    pub externals: HashMap<EcoString, SubstitutionExternal>,
     pub unsupported_functions: HashMap<EcoString, SubstitutionError>,
+    pub caller: Option<ExternalCaller>,
+    pub options: StepperOptions,
 }
 
 impl SubstitutionModule {
     pub fn from_module(module: &Module) -> Self {
+        let library = is_library_module(&module.name);
         let mut functions = HashMap::new();
+        let mut externals = HashMap::new();
         let mut unsupported_functions = HashMap::new();
         for function in &module.ast.definitions.functions {
             let mut validator = Validator::new(module.code.clone(), module.input_path.clone());
             let Some((_, name)) = function.name.as_ref() else {
                 continue;
             };
+            let (key, display_name, fn_module) = if library {
+                (
+                    format!("{}.{name}", module.name).into(),
+                    qualified_display_name(&module.name, name),
+                    Some(module.name.clone()),
+                )
+            } else {
+                (name.clone(), name.clone(), None)
+            };
+
+            if let Some((path, js_function, ..)) = &function.external_javascript {
+                let external =
+                    SubstitutionExternal::declared(display_name, &module.name, path, js_function);
+                externals.insert(key, external);
+                continue;
+            }
             let arguments: Vec<EcoString> = function
                 .arguments
                 .iter()
@@ -110,50 +263,95 @@ impl SubstitutionModule {
             match validation_result.and_then(|_| convert::typed_body_to_untyped(&function.body)) {
                 Ok(body) => {
                     functions.insert(
-                        name.clone(),
+                        key,
                         SubstitutionFunction {
                             name: name.clone(),
+                            module: fn_module,
                             arguments,
                             body,
                         },
                     );
                 }
+                // Library functions the stepper cannot step through are
+                // still evaluated as a whole, if they can be imported.
+                Err(_) if library && function.publicity.is_public() => {
+                    let external = SubstitutionExternal::exported(display_name, &module.name, name);
+                    externals.insert(key, external);
+                }
                 Err(err) => {
-                    unsupported_functions.insert(name.clone(), err);
+                    unsupported_functions.insert(key, err);
                 }
             }
         }
+        // Qualified references into a user module must not find a function
+        // with the same name of another module (e.g. the REPL), so user
+        // functions are also keyed by `module.name`.
+        if !library {
+            add_qualified_keys(&mut functions, &module.name);
+            add_qualified_keys(&mut externals, &module.name);
+            add_qualified_keys(&mut unsupported_functions, &module.name);
+        }
         Self {
             functions,
+            externals,
             unsupported_functions,
+            caller: None,
+            options: StepperOptions::default(),
         }
     }
 
+    /// Evaluates external functions with `engine`.
+    pub fn set_engine<E: Engine>(&mut self, engine: E) {
+        self.caller = Some(ExternalCaller::new(engine));
+    }
+
     pub fn merge(&mut self, other: SubstitutionModule) {
         for (name, function) in other.functions {
             self.unsupported_functions.remove(&name);
+            self.externals.remove(&name);
             self.functions.insert(name, function);
         }
+        for (name, external) in other.externals {
+            self.unsupported_functions.remove(&name);
+            self.functions.remove(&name);
+            self.externals.insert(name, external);
+        }
         for (name, err) in other.unsupported_functions {
-            if !self.functions.contains_key(&name) {
+            if !self.functions.contains_key(&name) && !self.externals.contains_key(&name) {
                 self.unsupported_functions.insert(name, err);
             }
         }
+        if other.caller.is_some() {
+            self.caller = other.caller;
+        }
+    }
+
+    /// Removes the functions of the user modules, keeping the library ones.
+    pub fn remove_user_functions(&mut self) {
+        let library = |key: &EcoString| {
+            key.rsplit_once('.')
+                .is_some_and(|(module, _)| is_library_module(module))
+        };
+        self.functions.retain(|key, _| library(key));
+        self.externals.retain(|key, _| library(key));
+        self.unsupported_functions.retain(|key, _| library(key));
     }
 
     pub fn find_function(&self, name: &str) -> Option<&SubstitutionFunction> {
         self.functions.get(name)
     }
 
+    pub fn find_external(&self, name: &str) -> Option<&SubstitutionExternal> {
+        self.externals.get(name)
+    }
+
     pub fn unsupported_error(&self, name: &str) -> Option<&SubstitutionError> {
         self.unsupported_functions.get(name)
     }
 
-    pub fn evaluate(
-        &self,
-        expr: &UntypedExpr,
-        max_steps: usize,
-    ) -> Result<SubstitutionTrace, SubstitutionError> {
+    /// Evaluates `expr` step by step, as configured by `self.options`.
+    pub fn evaluate(&self, expr: &UntypedExpr) -> Result<SubstitutionTrace, SubstitutionError> {
+        let mut steps = StepCounter::new(self.options.max_steps);
         let mut trace = SubstitutionTrace::new();
         let mut current = expr.clone();
         trace.push_step(SubstitutionStep::new(current.clone(), None));
@@ -162,21 +360,157 @@ impl SubstitutionModule {
             return Ok(trace);
         }
 
-        for _ in 0..max_steps {
-            match self.reduce_once(&current) {
-                Ok(Some(step)) => {
-                    current = step.expr.clone();
-                    trace.push_step(step);
+        // The paths of the function bodies substituted for calls. A call is
+        // as deep as the bodies that contain it: calls in the entry
+        // expression have depth 0 and the calls in the body of a function
+        // called at depth `d` have depth `d + 1`.
+        let mut bodies: Vec<Vec<usize>> = vec![];
+        while let Some(mut step) = steps.reduce_once(self, &current)? {
+            let previous = trace.steps().last().expect("The first step");
+            // A reduction around a body replaces it, so it is finished.
+            bodies.retain(|body| body.len() <= step.path.len() || !body.starts_with(&step.path));
+            if let Some(call @ UntypedExpr::Call { .. }) =
+                render::subexpr_at(&previous.expr, &step.path)
+            {
+                let depth = bodies
+                    .iter()
+                    .filter(|body| step.path.starts_with(body))
+                    .count();
+                let collapses = step
+                    .call
+                    .as_ref()
+                    .is_some_and(|name| self.options.collapses(name, depth));
+                if collapses {
+                    step = self.collapse_call(call, step, &mut steps)?;
+                } else {
+                    bodies.push(step.path.clone());
                 }
-                Ok(None) => return Ok(trace),
-                Err(err) => return Err(err),
             }
+            step.redex = render::span_at(&previous.formatted, &previous.expr, &step.path);
+            // A runtime error aborts the whole evaluation, so the last step
+            // shows only the error.
+            if let Some(error) = reduce::find_panic(&step.expr) {
+                let error = error.clone();
+                let mut last =
+                    SubstitutionStep::new(error, step.note).with_context_opt(step.context);
+                last.redex = step.redex;
+                trace.push_step(last);
+                return Ok(trace);
+            }
+            step.result = render::span_at(&step.formatted, &step.expr, &step.path);
+            current = step.expr.clone();
+            trace.push_step(step);
+        }
+        Ok(trace)
+    }
+
+    /// Replaces the body substituted for `call` in `step` by its value, so
+    /// the call is shown as a single step.
+    fn collapse_call(
+        &self,
+        call: &UntypedExpr,
+        step: SubstitutionStep,
+        steps: &mut StepCounter,
+    ) -> Result<SubstitutionStep, SubstitutionError> {
+        let mut expr = step.expr;
+        let body = render::subexpr_at_mut(&mut expr, &step.path)
+            .expect("The body substituted for the call");
+        let value = steps.evaluate_value(self, body)?;
+        let note = format!(
+            "{} evaluates to {}",
+            render_expr(call)?,
+            render_expr(&value)?
+        );
+        *body = value;
+        let mut collapsed = SubstitutionStep::new(expr, Some(note)).with_context_opt(step.context);
+        collapsed.path = step.path;
+        collapsed.call = step.call;
+        Ok(collapsed)
+    }
+}
+
+/// The steps left of an evaluation, shared by the evaluations of the
+/// collapsed calls.
+struct StepCounter {
+    max_steps: usize,
+    left: usize,
+}
+
+impl StepCounter {
+    fn new(max_steps: usize) -> Self {
+        Self {
+            max_steps,
+            left: max_steps,
+        }
+    }
+
+    fn reduce_once(
+        &mut self,
+        module: &SubstitutionModule,
+        expr: &UntypedExpr,
+    ) -> Result<Option<SubstitutionStep>, SubstitutionError> {
+        let step = module.reduce_once(expr)?;
+        if step.is_some() {
+            if self.left == 0 {
+                return Err(SubstitutionError::StepLimitExceeded(self.max_steps));
+            }
+            self.left -= 1;
         }
+        Ok(step)
+    }
 
-        Err(SubstitutionError::StepLimitExceeded(max_steps))
+    /// Evaluates `expr` to a value without recording the steps. A runtime
+    /// error results in the `panic` expression.
+    fn evaluate_value(
+        &mut self,
+        module: &SubstitutionModule,
+        expr: &UntypedExpr,
+    ) -> Result<UntypedExpr, SubstitutionError> {
+        let mut current = expr.clone();
+        while let Some(step) = self.reduce_once(module, &current)? {
+            if let Some(error) = reduce::find_panic(&step.expr) {
+                return Ok(error.clone());
+            }
+            current = step.expr;
+        }
+        Ok(current)
     }
 }
 
+/// Splits `text` into lines, each with the part of `span` inside it, relative
+/// to the start of the line.
+pub fn lines_with_span<'a>(
+    text: &'a str,
+    span: Option<&Range<usize>>,
+) -> Vec<(&'a str, Option<Range<usize>>)> {
+    let mut start = 0;
+    text.split('\n')
+        .map(|line| {
+            let end = start + line.len();
+            let part = span
+                .map(|span| span.start.max(start)..span.end.min(end))
+                .filter(|part| part.start < part.end)
+                .map(|part| part.start - start..part.end - start);
+            start = end + 1;
+            (line, part)
+        })
+        .collect()
+}
+
+fn add_qualified_keys<T: Clone>(map: &mut HashMap<EcoString, T>, module: &str) {
+    let qualified: Vec<_> = map
+        .iter()
+        .map(|(name, value)| (format!("{module}.{name}").into(), value.clone()))
+        .collect();
+    map.extend(qualified);
+}
+
+/// `list.length` for the function `length` of `gleam/list`.
+pub fn qualified_display_name(module: &str, name: &str) -> EcoString {
+    let short = module.rsplit('/').next().unwrap_or(module);
+    format!("{short}.{name}").into()
+}
+
 // ---------------------------------------------------------------------------
 // Errors
 // ---------------------------------------------------------------------------
@@ -193,6 +527,15 @@ pub enum SubstitutionError {
         path: Utf8PathBuf,
     },
 
+    #[error("Could not evaluate {function}: {message}")]
+    External { function: String, message: String },
+
+    #[error("The stepper cannot use {value} in a case guard")]
+    UnsupportedGuardValue { value: String },
+
+    #[error("The Int result overflows to Infinity at runtime")]
+    IntOverflow { negative: bool },
+
     #[error("Step limit exceeded after {0} steps")]
     StepLimitExceeded(usize),
 
@@ -2,11 +2,21 @@ use std::collections::HashMap;
 
 use ecow::EcoString;
 use gleam_core::ast::{
-    BinOp, CallArg, Clause, Pattern, SrcSpan, Statement, TailPattern, UntypedExpr, UntypedStatement,
+    Arg, ArgNames, AssignmentKind, BinOp, CallArg, Clause, FunctionLiteralKind,
+    ImplicitCallArgOrigin, Pattern, SrcSpan, Statement, TailPattern, UntypedExpr, UntypedStatement,
+    UntypedUse,
 };
 use num_bigint::BigInt;
+use vec1::Vec1;
 
-use super::{SubstitutionError, SubstitutionModule, SubstitutionStep};
+use super::{
+    SubstitutionError, SubstitutionModule, SubstitutionStep,
+    guard::{guard_to_expr, substitute_guard},
+    render::{render_expr, render_pattern},
+};
+
+/// The message of the runtime error raised by a failed `let assert`.
+const LET_ASSERT_MESSAGE: &str = "Pattern match failed, no pattern matched the value.";
 
 const S: SrcSpan = SrcSpan { start: 0, end: 0 };
 
@@ -16,19 +26,25 @@ impl SubstitutionModule {
         expr: &UntypedExpr,
     ) -> Result<Option<SubstitutionStep>, SubstitutionError> {
         match expr {
-            UntypedExpr::Call { fun, arguments, .. } => {
+            UntypedExpr::Call { fun, arguments, .. } if !is_value(expr) => {
                 if !is_value(fun) {
-                    return self.reduce_child_value(fun);
+                    let Some(inner) = self.reduce_child_value(fun)? else {
+                        return Ok(None);
+                    };
+                    let mut reduced = expr.clone();
+                    let UntypedExpr::Call { fun, .. } = &mut reduced else {
+                        unreachable!();
+                    };
+                    **fun = inner.expr.clone();
+                    return Ok(Some(inner.within(0, reduced)));
                 }
                 if let Some((i, inner)) = self.reduce_arguments(arguments)? {
                     let mut reduced = expr.clone();
                     let UntypedExpr::Call { arguments, .. } = &mut reduced else {
                         unreachable!();
                     };
-                    arguments[i].value = inner.expr;
-                    return Ok(Some(
-                        SubstitutionStep::new(reduced, inner.note).with_context_opt(inner.context),
-                    ));
+                    arguments[i].value = inner.expr.clone();
+                    return Ok(Some(inner.within(i + 1, reduced)));
                 }
                 Ok(Some(self.reduce_call(expr)?))
             }
@@ -69,12 +85,21 @@ impl SubstitutionModule {
                 if let Some(inner) = self.reduce_case_subjects(expr)? {
                     return Ok(Some(inner));
                 }
-                let (reduced, note) = reduce_case(expr);
-                Ok(Some(SubstitutionStep::new(reduced, Some(note.into()))))
+                let (reduced, note) = self.reduce_case(expr)?;
+                Ok(Some(SubstitutionStep::new(reduced, Some(note))))
             }
 
             UntypedExpr::Block { statements, .. } => self.reduce_block(statements),
 
+            UntypedExpr::Echo {
+                expression: Some(value),
+                message,
+                ..
+            } => self.reduce_echo(expr, value, message.as_deref()),
+
+            // A runtime error ends the evaluation.
+            UntypedExpr::Panic { .. } => Ok(None),
+
             _ if is_value(expr) => Ok(None),
 
             _ => panic!(
@@ -130,12 +155,44 @@ impl SubstitutionModule {
         let UntypedExpr::Call { fun, arguments, .. } = expr else {
             unreachable!();
         };
-        let UntypedExpr::Var { name, .. } = fun.as_ref() else {
-            panic!("Unsupported function reference should have been caught by validator");
+        if let UntypedExpr::Fn {
+            arguments: parameters,
+            body,
+            ..
+        } = fun.as_ref()
+        {
+            return reduce_fn_call(parameters, body, arguments);
+        }
+        let (key, name) = match fun.as_ref() {
+            UntypedExpr::Var { name, .. } => (name.clone(), name.clone()),
+            UntypedExpr::FieldAccess {
+                label, container, ..
+            } => match container.as_ref() {
+                UntypedExpr::Var { name: module, .. } => {
+                    (format!("{module}.{label}").into(), label.clone())
+                }
+                _ => panic!("Unsupported function reference should have been caught by validator"),
+            },
+            _ => panic!("Unsupported function reference should have been caught by validator"),
         };
 
-        let function = self.find_function(name).ok_or_else(|| {
-            if let Some(err) = self.unsupported_error(name) {
+        if let Some(external) = self.find_external(&key) {
+            let Some(caller) = &self.caller else {
+                return Err(SubstitutionError::External {
+                    function: external.name.to_string(),
+                    message: "no JavaScript engine is available".into(),
+                });
+            };
+            let values: Vec<UntypedExpr> = arguments.iter().map(|a| a.value.clone()).collect();
+            let reduced = caller.call(external, &values)?;
+            let mut step =
+                SubstitutionStep::new(reduced, Some(format!("evaluate {}", external.name)));
+            step.call = Some(external.name.clone());
+            return Ok(step);
+        }
+
+        let function = self.find_function(&key).ok_or_else(|| {
+            if let Some(err) = self.unsupported_error(&key) {
                 err.clone()
             } else {
                 panic!("Function not found: {}", name);
@@ -149,11 +206,12 @@ impl SubstitutionModule {
             .zip(arguments.iter().map(|a| a.value.clone()))
             .collect();
 
-        let reduced = substitute_expr(&function.body, &env);
-        let mut s = SubstitutionStep::new(
-            reduced,
-            Some(format!("substitute call to {}", function.name)),
-        );
+        let reduced = substitute_expr(&function.body, &env)?;
+        let display_name = function.display_name();
+
+        let mut s =
+            SubstitutionStep::new(reduced, Some(format!("substitute call to {display_name}")));
+        s.call = Some(display_name);
         if let Ok(context_str) = crate::substitution::render::render_function(
             &function.name,
             &function.arguments,
@@ -165,13 +223,51 @@ impl SubstitutionModule {
     }
 
     fn reduce_primitive(&self, expr: &UntypedExpr) -> Result<SubstitutionStep, SubstitutionError> {
-        let reduced = reduce_primitive_expr(expr);
+        let reduced = reduce_primitive_expr(expr)?;
         Ok(SubstitutionStep::new(
             reduced,
             Some("reduce primitive operator".into()),
         ))
     }
 
+    /// Reduces the value and then the message of an `echo`, which then
+    /// evaluates to its value. The printed text is shown in the note.
+    fn reduce_echo(
+        &self,
+        expr: &UntypedExpr,
+        value: &UntypedExpr,
+        message: Option<&UntypedExpr>,
+    ) -> Result<Option<SubstitutionStep>, SubstitutionError> {
+        for (index, child) in [Some(value), message].into_iter().flatten().enumerate() {
+            if is_value(child) {
+                continue;
+            }
+            let Some(inner) = self.reduce_once(child)? else {
+                return Ok(None);
+            };
+            let mut reduced = expr.clone();
+            let UntypedExpr::Echo {
+                expression,
+                message,
+                ..
+            } = &mut reduced
+            else {
+                unreachable!();
+            };
+            let slot = if index == 0 { expression } else { message };
+            *slot = Some(Box::new(inner.expr.clone()));
+            return Ok(Some(inner.within(index, reduced)));
+        }
+        let printed = render_expr(value)?;
+        let note = match message {
+            Some(UntypedExpr::String { value: message, .. }) => {
+                format!("echo prints {message}: {printed}")
+            }
+            _ => format!("echo prints {printed}"),
+        };
+        Ok(Some(SubstitutionStep::new(value.clone(), Some(note))))
+    }
+
     fn reduce_in_binop_left(
         &self,
         expr: &UntypedExpr,
@@ -184,10 +280,8 @@ impl SubstitutionModule {
         let UntypedExpr::BinOp { left, .. } = &mut reduced else {
             unreachable!();
         };
-        **left = inner.expr;
-        Ok(Some(
-            SubstitutionStep::new(reduced, inner.note).with_context_opt(inner.context),
-        ))
+        **left = inner.expr.clone();
+        Ok(Some(inner.within(0, reduced)))
     }
 
     fn reduce_in_binop_right(
@@ -202,10 +296,8 @@ impl SubstitutionModule {
         let UntypedExpr::BinOp { right, .. } = &mut reduced else {
             unreachable!();
         };
-        **right = inner.expr;
-        Ok(Some(
-            SubstitutionStep::new(reduced, inner.note).with_context_opt(inner.context),
-        ))
+        **right = inner.expr.clone();
+        Ok(Some(inner.within(1, reduced)))
     }
 
     fn reduce_block(
@@ -234,24 +326,49 @@ impl SubstitutionModule {
             match stmt {
                 Statement::Assignment(assignment) if is_value(&assignment.value) => {
                     let mut env = HashMap::new();
-                    collect_pattern_bindings(&assignment.pattern, &assignment.value, &mut env);
-                    let mut remaining: Vec<UntypedStatement> =
-                        Vec::with_capacity(statements.len() - i - 1);
-                    for s in statements.iter().skip(i + 1) {
-                        remaining.push(substitute_statement(s, &env));
-                        if let Statement::Assignment(a) = s {
-                            remove_pattern_names(&a.pattern, &mut env);
+                    let note = if let AssignmentKind::Assert { message, .. } = &assignment.kind {
+                        if let Some(message) = message.as_ref().filter(|m| !is_value(m)) {
+                            let Some(inner) = self.reduce_once(message)? else {
+                                return Ok(None);
+                            };
+                            let mut new_stmts: Vec<UntypedStatement> =
+                                statements.iter().cloned().collect();
+                            let Statement::Assignment(ref mut asgn) = new_stmts[i] else {
+                                unreachable!();
+                            };
+                            if let AssignmentKind::Assert { message, .. } = &mut asgn.kind {
+                                *message = Some(inner.expr.clone());
+                            }
+                            // The message is not a child, so the path stops
+                            // at the statement.
+                            let mut step = inner.within(i, block_from_statements(new_stmts)?);
+                            step.path.truncate(1);
+                            return Ok(Some(step));
                         }
-                    }
+                        if !match_pattern(&assignment.pattern, &assignment.value, &mut env) {
+                            return Ok(Some(let_assert_failure(
+                                &assignment.pattern,
+                                &assignment.value,
+                                message.as_ref(),
+                            )?));
+                        }
+                        "let assert matched, substitute"
+                    } else {
+                        collect_pattern_bindings(&assignment.pattern, &assignment.value, &mut env);
+                        "substitute let"
+                    };
+                    let mut remaining: Vec<UntypedStatement> =
+                        statements.iter().skip(i + 1).cloned().collect();
+                    substitute_statements(remaining.iter_mut(), &env)?;
                     if remaining.is_empty() {
                         return Ok(Some(SubstitutionStep::new(
                             assignment.value.clone(),
-                            Some("substitute let".into()),
+                            Some(note.into()),
                         )));
                     } else {
                         return Ok(Some(SubstitutionStep::new(
                             block_from_statements(remaining)?,
-                            Some("substitute let".into()),
+                            Some(note.into()),
                         )));
                     }
                 }
@@ -264,11 +381,8 @@ impl SubstitutionModule {
                     let Statement::Assignment(ref mut asgn) = new_stmts[i] else {
                         unreachable!();
                     };
-                    asgn.value = inner.expr;
-                    return Ok(Some(
-                        SubstitutionStep::new(block_from_statements(new_stmts)?, inner.note)
-                            .with_context_opt(inner.context),
-                    ));
+                    asgn.value = inner.expr.clone();
+                    return Ok(Some(inner.within(i, block_from_statements(new_stmts)?)));
                 }
 
                 Statement::Expression(expr) => {
@@ -279,11 +393,8 @@ impl SubstitutionModule {
                         };
                         let mut new_stmts: Vec<UntypedStatement> =
                             statements.iter().cloned().collect();
-                        new_stmts[i] = Statement::Expression(inner.expr);
-                        return Ok(Some(
-                            SubstitutionStep::new(block_from_statements(new_stmts)?, inner.note)
-                                .with_context_opt(inner.context),
-                        ));
+                        new_stmts[i] = Statement::Expression(inner.expr.clone());
+                        return Ok(Some(inner.within(i, block_from_statements(new_stmts)?)));
                     }
                     if !is_value(expr) {
                         let Some(inner) = self.reduce_once(expr)? else {
@@ -291,11 +402,8 @@ impl SubstitutionModule {
                         };
                         let mut new_stmts: Vec<UntypedStatement> =
                             statements.iter().cloned().collect();
-                        new_stmts[i] = Statement::Expression(inner.expr);
-                        return Ok(Some(
-                            SubstitutionStep::new(block_from_statements(new_stmts)?, inner.note)
-                                .with_context_opt(inner.context),
-                        ));
+                        new_stmts[i] = Statement::Expression(inner.expr.clone());
+                        return Ok(Some(inner.within(i, block_from_statements(new_stmts)?)));
                     }
                     let remaining: Vec<UntypedStatement> =
                         statements.iter().skip(i + 1).cloned().collect();
@@ -305,6 +413,18 @@ impl SubstitutionModule {
                     )));
                 }
 
+                Statement::Use(use_) => {
+                    let mut new_stmts: Vec<UntypedStatement> =
+                        statements.iter().take(i).cloned().collect();
+                    let rest: Vec<UntypedStatement> =
+                        statements.iter().skip(i + 1).cloned().collect();
+                    new_stmts.push(Statement::Expression(desugar_use(use_, rest)));
+                    return Ok(Some(SubstitutionStep::new(
+                        block_from_statements(new_stmts)?,
+                        Some("desugar use".into()),
+                    )));
+                }
+
                 _ => unreachable!(),
             }
         }
@@ -327,25 +447,254 @@ impl SubstitutionModule {
                 let UntypedExpr::Case { subjects, .. } = &mut reduced else {
                     unreachable!();
                 };
-                subjects[i] = inner.expr;
-                return Ok(Some(
-                    SubstitutionStep::new(reduced, inner.note).with_context_opt(inner.context),
-                ));
+                subjects[i] = inner.expr.clone();
+                return Ok(Some(inner.within(i, reduced)));
             }
         }
         Ok(None)
     }
+
+    fn reduce_case(&self, expr: &UntypedExpr) -> Result<(UntypedExpr, String), SubstitutionError> {
+        let UntypedExpr::Case {
+            subjects, clauses, ..
+        } = expr
+        else {
+            unreachable!();
+        };
+
+        let clauses = clauses.as_ref().expect("No clauses in case");
+        let first = clauses.first().expect("Empty clauses in case");
+
+        let without_first = || {
+            let remaining = clauses[1..].to_vec();
+            if remaining.is_empty() {
+                panic!("Non-exhaustive patterns caught by validator");
+            }
+            UntypedExpr::Case {
+                location: S,
+                subjects: subjects.clone(),
+                clauses: Some(remaining),
+            }
+        };
+
+        let Some(env) = match_clause(first, subjects) else {
+            return Ok((without_first(), "remove first case branch".into()));
+        };
+
+        let Some(guard) = &first.guard else {
+            let result = substitute_expr(&first.then, &env)?;
+            return Ok((result, "select case branch".into()));
+        };
+
+        let guard = guard_to_expr(guard);
+        let shown = render_expr(&guard)?;
+        if self.evaluate_guard(&substitute_expr(&guard, &env)?)? {
+            let result = substitute_expr(&first.then, &env)?;
+            Ok((result, format!("guard {shown} is True, select case branch")))
+        } else {
+            Ok((
+                without_first(),
+                format!("guard {shown} is False, try next clause"),
+            ))
+        }
+    }
+
+    /// Evaluates a guard to a `Bool`. Guards only contain variables, literals
+    /// and operators, so no function call can happen.
+    fn evaluate_guard(&self, guard: &UntypedExpr) -> Result<bool, SubstitutionError> {
+        let mut current = guard.clone();
+        while let Some(step) = self.reduce_once(&current)? {
+            current = step.expr;
+        }
+        Ok(is_bool_lit(&current, true))
+    }
+}
+
+/// Builds the step for a `let assert` whose pattern does not match `value`:
+/// the whole expression is replaced by the runtime error.
+fn let_assert_failure(
+    pattern: &Pattern<()>,
+    value: &UntypedExpr,
+    message: Option<&UntypedExpr>,
+) -> Result<SubstitutionStep, SubstitutionError> {
+    let message = match message {
+        Some(UntypedExpr::String { value, .. }) => value.to_string(),
+        _ => LET_ASSERT_MESSAGE.into(),
+    };
+    let note = format!(
+        "let assert failed: {} does not match {}",
+        render_expr(value)?,
+        render_pattern(pattern)
+    );
+    Ok(SubstitutionStep::new(panic_expr(message), Some(note)))
+}
+
+fn panic_expr(message: String) -> UntypedExpr {
+    UntypedExpr::Panic {
+        location: S,
+        message: Some(Box::new(string_expr(message))),
+    }
+}
+
+/// Substitutes the arguments of a call to an anonymous function into its
+/// body.
+fn reduce_fn_call(
+    parameters: &[Arg<()>],
+    body: &Vec1<UntypedStatement>,
+    arguments: &[CallArg<UntypedExpr>],
+) -> Result<SubstitutionStep, SubstitutionError> {
+    let env: HashMap<EcoString, UntypedExpr> = parameters
+        .iter()
+        .zip(arguments)
+        .filter_map(|(parameter, argument)| {
+            let name = parameter.names.get_variable_name()?;
+            Some((name.clone(), argument.value.clone()))
+        })
+        .collect();
+    let mut body = body.clone();
+    substitute_statements(body.iter_mut(), &env)?;
+    Ok(SubstitutionStep::new(
+        block_from_statements(body.into_vec())?,
+        Some("substitute call to anonymous function".into()),
+    ))
+}
+
+/// Rewrites `use` followed by `rest` as a call with `rest` in a callback.
+/// Patterns other than variables and discards are matched with a `case` in
+/// the callback.
+fn desugar_use(use_: &UntypedUse, rest: Vec<UntypedStatement>) -> UntypedExpr {
+    let mut body = match Vec1::try_from_vec(rest) {
+        Ok(rest) => block_or_expr(rest),
+        Err(_) => UntypedExpr::Var {
+            location: S,
+            name: "Nil".into(),
+        },
+    };
+    let mut parameters = Vec::with_capacity(use_.assignments.len());
+    for (i, assignment) in use_.assignments.iter().enumerate().rev() {
+        let names = match &assignment.pattern {
+            Pattern::Variable { name, .. } => ArgNames::Named {
+                name: name.clone(),
+                location: S,
+            },
+            Pattern::Discard { name, .. } => ArgNames::Discard {
+                name: name.clone(),
+                location: S,
+            },
+            pattern => {
+                let name: EcoString = format!("_use{i}").into();
+                body = UntypedExpr::Case {
+                    location: S,
+                    subjects: vec![UntypedExpr::Var {
+                        location: S,
+                        name: name.clone(),
+                    }],
+                    clauses: Some(vec![Clause {
+                        location: S,
+                        pattern: vec![pattern.clone()],
+                        alternative_patterns: vec![],
+                        guard: None,
+                        then: body,
+                    }]),
+                };
+                ArgNames::Named { name, location: S }
+            }
+        };
+        parameters.push(Arg {
+            names,
+            location: S,
+            annotation: None,
+            type_: (),
+        });
+    }
+    parameters.reverse();
+    let callback = CallArg {
+        label: None,
+        location: S,
+        value: UntypedExpr::Fn {
+            location: S,
+            end_of_head_byte_index: 0,
+            kind: FunctionLiteralKind::Use { location: S },
+            arguments: parameters,
+            body: Vec1::new(Statement::Expression(body)),
+            return_annotation: None,
+        },
+        implicit: Some(ImplicitCallArgOrigin::Use),
+    };
+    match use_.call.as_ref() {
+        UntypedExpr::Call { fun, arguments, .. } => {
+            let mut arguments = arguments.clone();
+            arguments.push(callback);
+            UntypedExpr::Call {
+                location: S,
+                fun: fun.clone(),
+                arguments,
+                open_parenthesis: 0,
+            }
+        }
+        fun => UntypedExpr::Call {
+            location: S,
+            fun: Box::new(fun.clone()),
+            arguments: vec![callback],
+            open_parenthesis: 0,
+        },
+    }
+}
+
+fn block_or_expr(statements: Vec1<UntypedStatement>) -> UntypedExpr {
+    match statements.as_slice() {
+        [Statement::Expression(expr)] => expr.clone(),
+        _ => UntypedExpr::Block {
+            location: S,
+            statements,
+        },
+    }
+}
+
+/// Returns the runtime error (a `panic` produced by a failed `let assert`)
+/// inside `expr`, if any.
+pub fn find_panic(expr: &UntypedExpr) -> Option<&UntypedExpr> {
+    match expr {
+        UntypedExpr::Panic { .. } => Some(expr),
+        UntypedExpr::Call { fun, arguments, .. } => {
+            find_panic(fun).or_else(|| arguments.iter().find_map(|a| find_panic(&a.value)))
+        }
+        UntypedExpr::BinOp { left, right, .. } => find_panic(left).or_else(|| find_panic(right)),
+        UntypedExpr::NegateBool { value, .. } | UntypedExpr::NegateInt { value, .. } => {
+            find_panic(value)
+        }
+        UntypedExpr::List { elements, tail, .. } => elements
+            .iter()
+            .find_map(find_panic)
+            .or_else(|| tail.as_deref().and_then(find_panic)),
+        UntypedExpr::Tuple { elements, .. } => elements.iter().find_map(find_panic),
+        UntypedExpr::Case { subjects, .. } => subjects.iter().find_map(find_panic),
+        UntypedExpr::Echo {
+            expression,
+            message,
+            ..
+        } => expression
+            .as_deref()
+            .and_then(find_panic)
+            .or_else(|| message.as_deref().and_then(find_panic)),
+        UntypedExpr::Block { statements, .. } => statements.iter().find_map(|s| match s {
+            Statement::Expression(expr) => find_panic(expr),
+            Statement::Assignment(assignment) => find_panic(&assignment.value),
+            _ => None,
+        }),
+        _ => None,
+    }
 }
 
 fn rebuild_negate(expr: &UntypedExpr, inner: SubstitutionStep) -> SubstitutionStep {
     let mut reduced = expr.clone();
     match &mut reduced {
         UntypedExpr::NegateBool { value, .. } | UntypedExpr::NegateInt { value, .. } => {
-            **value = inner.expr;
+            **value = inner.expr.clone();
         }
         _ => unreachable!(),
     }
-    SubstitutionStep::new(reduced, inner.note).with_context_opt(inner.context)
+    inner.within(0, reduced)
 }
 
 fn block_from_statements(stmts: Vec<UntypedStatement>) -> Result<UntypedExpr, SubstitutionError> {
@@ -368,12 +717,22 @@ pub fn is_value(expr: &UntypedExpr) -> bool {
 
         UntypedExpr::Var { .. } => true,
 
+        // A qualified function reference.
+        UntypedExpr::FieldAccess { .. } => true,
+
         UntypedExpr::List { elements, tail, .. } => {
             elements.iter().all(is_value) && tail.as_deref().is_none_or(is_value)
         }
 
         UntypedExpr::Tuple { elements, .. } => elements.iter().all(is_value),
 
+        UntypedExpr::Fn { .. } => true,
+
+        UntypedExpr::Call { fun, arguments, .. } => {
+            matches!(fun.as_ref(), UntypedExpr::Var { name, .. } if is_constructor_name(name))
+                && arguments.iter().all(|a| is_value(&a.value))
+        }
+
         UntypedExpr::NegateInt { value, .. } => matches!(value.as_ref(), UntypedExpr::Int { .. }),
         UntypedExpr::NegateBool { value, .. } => {
             matches!(value.as_ref(), UntypedExpr::Var { name, .. } if name == "True" || name == "False")
@@ -383,53 +742,123 @@ pub fn is_value(expr: &UntypedExpr) -> bool {
     }
 }
 
-fn reduce_primitive_expr(expr: &UntypedExpr) -> UntypedExpr {
-    match expr {
+fn reduce_primitive_expr(expr: &UntypedExpr) -> Result<UntypedExpr, SubstitutionError> {
+    Ok(match expr {
         UntypedExpr::BinOp {
             name, left, right, ..
         } => {
-            let lv = value_from_expr(left);
+            let lv = || value_from_expr(left);
             match name {
-                BinOp::And => match lv {
+                BinOp::And => match lv()? {
                     PrimVal::Bool(false) => bool_expr(false),
                     PrimVal::Bool(true) => {
-                        let PrimVal::Bool(v) = value_from_expr(right) else {
+                        let PrimVal::Bool(v) = value_from_expr(right)? else {
                             panic!("Invalid operand type");
                         };
                         bool_expr(v)
                     }
                     _ => panic!("Invalid operand type"),
                 },
-                BinOp::Or => match lv {
+                BinOp::Or => match lv()? {
                     PrimVal::Bool(true) => bool_expr(true),
                     PrimVal::Bool(false) => {
-                        let PrimVal::Bool(v) = value_from_expr(right) else {
+                        let PrimVal::Bool(v) = value_from_expr(right)? else {
                             panic!("Invalid operand type");
                         };
                         bool_expr(v)
                     }
                     _ => panic!("Invalid operand type"),
                 },
+                BinOp::Eq => bool_expr(values_equal(left, right)),
+                BinOp::NotEq => bool_expr(!values_equal(left, right)),
                 _ => {
-                    let rv = value_from_expr(right);
-                    primitive_bin_op(*name, lv, rv)
+                    let rv = value_from_expr(right)?;
+                    primitive_bin_op(*name, lv()?, rv)?
                 }
             }
         }
 
         UntypedExpr::NegateBool { value, .. } => {
-            let PrimVal::Bool(v) = value_from_expr(value) else {
+            let PrimVal::Bool(v) = value_from_expr(value)? else {
                 panic!("Invalid operand type");
             };
             bool_expr(!v)
         }
 
-        UntypedExpr::NegateInt { value, .. } => match value_from_expr(value) {
+        UntypedExpr::NegateInt { value, .. } => match value_from_expr(value)? {
             PrimVal::Int(v) => int_expr(-v),
             _ => panic!("Invalid operand type"),
         },
 
         _ => unreachable!(),
+    })
+}
+
+/// Structural equality of two values, as done by `==`.
+fn values_equal(left: &UntypedExpr, right: &UntypedExpr) -> bool {
+    match (left, right) {
+        (UntypedExpr::Int { int_value: a, .. }, UntypedExpr::Int { int_value: b, .. }) => {
+            runtime_ints_equal(a, b)
+        }
+        (UntypedExpr::Float { float_value: a, .. }, UntypedExpr::Float { float_value: b, .. }) => {
+            a.value() == b.value()
+        }
+        (UntypedExpr::String { value: a, .. }, UntypedExpr::String { value: b, .. }) => a == b,
+        (UntypedExpr::Var { name: a, .. }, UntypedExpr::Var { name: b, .. }) => a == b,
+        (UntypedExpr::NegateInt { value: a, .. }, UntypedExpr::NegateInt { value: b, .. })
+        | (UntypedExpr::NegateBool { value: a, .. }, UntypedExpr::NegateBool { value: b, .. }) => {
+            values_equal(a, b)
+        }
+        (UntypedExpr::Tuple { elements: a, .. }, UntypedExpr::Tuple { elements: b, .. }) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b))
+        }
+        (UntypedExpr::List { .. }, UntypedExpr::List { .. }) => {
+            match (list_uncons(left), list_uncons(right)) {
+                (None, None) => true,
+                (Some((a, a_rest)), Some((b, b_rest))) => {
+                    values_equal(&a, &b) && values_equal(&a_rest, &b_rest)
+                }
+                _ => false,
+            }
+        }
+        (
+            UntypedExpr::Call {
+                fun: a_fun,
+                arguments: a_args,
+                ..
+            },
+            UntypedExpr::Call {
+                fun: b_fun,
+                arguments: b_args,
+                ..
+            },
+        ) => {
+            values_equal(a_fun, b_fun)
+                && a_args.len() == b_args.len()
+                && a_args
+                    .iter()
+                    .zip(b_args)
+                    .all(|(a, b)| values_equal(&a.value, &b.value))
+        }
+        _ => false,
+    }
+}
+
+/// Splits a list value into its first element and the rest, following tails.
+fn list_uncons(list: &UntypedExpr) -> Option<(UntypedExpr, UntypedExpr)> {
+    let UntypedExpr::List { elements, tail, .. } = list else {
+        return None;
+    };
+    match elements.split_first() {
+        Some((first, rest)) => Some((
+            first.clone(),
+            UntypedExpr::List {
+                location: S,
+                elements: rest.to_vec(),
+                tail: tail.clone(),
+            },
+        )),
+        None => tail.as_deref().and_then(list_uncons),
     }
 }
 
@@ -441,9 +870,9 @@ enum PrimVal {
     Bool(bool),
 }
 
-fn value_from_expr(expr: &UntypedExpr) -> PrimVal {
-    match expr {
-        UntypedExpr::Int { int_value, .. } => PrimVal::Int(int_value.clone()),
+fn value_from_expr(expr: &UntypedExpr) -> Result<PrimVal, SubstitutionError> {
+    Ok(match expr {
+        UntypedExpr::Int { int_value, .. } => PrimVal::Int(runtime_int(int_value)?),
         UntypedExpr::Float { float_value, .. } => PrimVal::Float(float_value.value()),
         UntypedExpr::String { value, .. } => PrimVal::String(value.to_string()),
         UntypedExpr::Var { name, .. } => match name.as_ref() {
@@ -452,13 +881,16 @@ fn value_from_expr(expr: &UntypedExpr) -> PrimVal {
             _ => panic!("Invalid value expression"),
         },
         _ => panic!("Invalid value expression"),
-    }
+    })
 }
 
-fn primitive_bin_op(op: BinOp, left: PrimVal, right: PrimVal) -> UntypedExpr {
-    match op {
-        BinOp::Eq => bool_expr(left == right),
-        BinOp::NotEq => bool_expr(left != right),
+fn primitive_bin_op(
+    op: BinOp,
+    left: PrimVal,
+    right: PrimVal,
+) -> Result<UntypedExpr, SubstitutionError> {
+    Ok(match op {
+        BinOp::Eq | BinOp::NotEq => unreachable!("handled in reduce_primitive_expr"),
         BinOp::LtInt => int_cmp(left, right, |a, b| a < b, op),
         BinOp::LtEqInt => int_cmp(left, right, |a, b| a <= b, op),
         BinOp::GtInt => int_cmp(left, right, |a, b| a > b, op),
@@ -467,9 +899,9 @@ fn primitive_bin_op(op: BinOp, left: PrimVal, right: PrimVal) -> UntypedExpr {
         BinOp::LtEqFloat => float_cmp(left, right, |a, b| a <= b, op),
         BinOp::GtFloat => float_cmp(left, right, |a, b| a > b, op),
         BinOp::GtEqFloat => float_cmp(left, right, |a, b| a >= b, op),
-        BinOp::AddInt => int_arith(left, right, |a, b| a + b, op),
-        BinOp::SubInt => int_arith(left, right, |a, b| a - b, op),
-        BinOp::MultInt => int_arith(left, right, |a, b| a * b, op),
+        BinOp::AddInt => int_arith(left, right, |a, b| a + b, op)?,
+        BinOp::SubInt => int_arith(left, right, |a, b| a - b, op)?,
+        BinOp::MultInt => int_arith(left, right, |a, b| a * b, op)?,
         BinOp::DivInt => int_arith(
             left,
             right,
@@ -481,7 +913,7 @@ fn primitive_bin_op(op: BinOp, left: PrimVal, right: PrimVal) -> UntypedExpr {
                 }
             },
             op,
-        ),
+        )?,
         BinOp::RemainderInt => int_arith(
             left,
             right,
@@ -493,7 +925,7 @@ fn primitive_bin_op(op: BinOp, left: PrimVal, right: PrimVal) -> UntypedExpr {
                 }
             },
             op,
-        ),
+        )?,
         BinOp::AddFloat => float_arith(left, right, |a, b| a + b, op),
         BinOp::SubFloat => float_arith(left, right, |a, b| a - b, op),
         BinOp::MultFloat => float_arith(left, right, |a, b| a * b, op),
@@ -510,7 +942,7 @@ fn primitive_bin_op(op: BinOp, left: PrimVal, right: PrimVal) -> UntypedExpr {
             _ => panic!("Invalid operand type"),
         },
         BinOp::And | BinOp::Or => unreachable!("handled in reduce_once"),
-    }
+    })
 }
 
 fn int_cmp(
@@ -541,14 +973,78 @@ fn int_arith(
     left: PrimVal,
     right: PrimVal,
     f: impl FnOnce(BigInt, BigInt) -> BigInt,
-    _op: BinOp,
-) -> UntypedExpr {
+    op: BinOp,
+) -> Result<UntypedExpr, SubstitutionError> {
     match (left, right) {
-        (PrimVal::Int(a), PrimVal::Int(b)) => int_expr(f(a, b)),
+        (PrimVal::Int(a), PrimVal::Int(b)) if gleam_core::javascript::is_bigint_enabled() => {
+            Ok(int_expr(f(a, b)))
+        }
+        (PrimVal::Int(a), PrimVal::Int(b)) => Ok(int_expr(number_to_int(number_arith(
+            op,
+            int_to_number(&a),
+            int_to_number(&b),
+        ))?)),
         _ => panic!("Invalid operand type"),
     }
 }
 
+/// Int arithmetic in Number mode, following the JavaScript prelude:
+/// operations are done on doubles, division truncates and dividing by zero
+/// gives zero.
+fn number_arith(op: BinOp, a: f64, b: f64) -> f64 {
+    match op {
+        BinOp::AddInt => a + b,
+        BinOp::SubInt => a - b,
+        BinOp::MultInt => a * b,
+        BinOp::DivInt if b == 0.0 => 0.0,
+        BinOp::DivInt => (a / b).trunc(),
+        BinOp::RemainderInt if b == 0.0 => 0.0,
+        BinOp::RemainderInt => a % b,
+        _ => unreachable!("not an int arithmetic operator"),
+    }
+}
+
+/// The value an Int has at runtime. In Number mode, integers are doubles, so
+/// literals beyond 2^53 lose precision.
+fn runtime_int(value: &BigInt) -> Result<BigInt, SubstitutionError> {
+    if gleam_core::javascript::is_bigint_enabled() {
+        Ok(value.clone())
+    } else {
+        number_to_int(int_to_number(value))
+    }
+}
+
+/// Compares two Ints as the runtime does, where in Number mode the literals
+/// beyond the largest double are both `Infinity`.
+fn runtime_ints_equal(a: &BigInt, b: &BigInt) -> bool {
+    if gleam_core::javascript::is_bigint_enabled() {
+        a == b
+    } else {
+        int_to_number(a) == int_to_number(b)
+    }
+}
+
+fn int_to_number(value: &BigInt) -> f64 {
+    // The decimal representation is parsed with correct rounding.
+    value
+        .to_string()
+        .parse()
+        .expect("an integer is a valid float")
+}
+
+fn number_to_int(value: f64) -> Result<BigInt, SubstitutionError> {
+    // The runtime gives `Infinity` on overflow, which has no Int literal.
+    if value.is_infinite() {
+        return Err(SubstitutionError::IntOverflow {
+            negative: value < 0.0,
+        });
+    }
+    // A finite double with no fractional part is printed with all its digits.
+    Ok(format!("{value:.0}")
+        .parse()
+        .expect("a finite double is a valid integer"))
+}
+
 fn float_arith(
     left: PrimVal,
     right: PrimVal,
@@ -561,37 +1057,6 @@ fn float_arith(
     }
 }
 
-fn reduce_case(expr: &UntypedExpr) -> (UntypedExpr, &'static str) {
-    let UntypedExpr::Case {
-        subjects, clauses, ..
-    } = expr
-    else {
-        unreachable!();
-    };
-
-    let clauses = clauses.as_ref().expect("No clauses in case");
-    let first = clauses.first().expect("Empty clauses in case");
-
-    if let Some(env) = match_clause(first, subjects) {
-        let result = substitute_expr(&first.then, &env);
-        return (result, "select case branch");
-    }
-
-    let remaining = clauses[1..].to_vec();
-    if remaining.is_empty() {
-        panic!("Non-exhaustive patterns caught by validator");
-    }
-
-    (
-        UntypedExpr::Case {
-            location: S,
-            subjects: subjects.clone(),
-            clauses: Some(remaining),
-        },
-        "remove first case branch",
-    )
-}
-
 fn match_clause(
     clause: &Clause<UntypedExpr, (), ()>,
     subjects: &[UntypedExpr],
@@ -850,50 +1315,69 @@ fn is_bool_lit(expr: &UntypedExpr, expected: bool) -> bool {
     matches!(expr, UntypedExpr::Var { name, .. } if name.as_ref() == if expected { "True" } else { "False" })
 }
 
-pub fn substitute_expr(expr: &UntypedExpr, env: &HashMap<EcoString, UntypedExpr>) -> UntypedExpr {
+pub fn substitute_expr(
+    expr: &UntypedExpr,
+    env: &HashMap<EcoString, UntypedExpr>,
+) -> Result<UntypedExpr, SubstitutionError> {
     let mut res = expr.clone();
     match &mut res {
         UntypedExpr::Var { name, .. } => {
             if let Some(replacement) = env.get(name) {
-                return replacement.clone();
+                return Ok(replacement.clone());
             }
         }
         UntypedExpr::Call { fun, arguments, .. } => {
-            **fun = substitute_expr(fun, env);
+            **fun = substitute_expr(fun, env)?;
             for arg in arguments {
-                arg.value = substitute_expr(&arg.value, env);
+                arg.value = substitute_expr(&arg.value, env)?;
             }
         }
         UntypedExpr::BinOp { left, right, .. } => {
-            **left = substitute_expr(left, env);
-            **right = substitute_expr(right, env);
+            **left = substitute_expr(left, env)?;
+            **right = substitute_expr(right, env)?;
         }
         UntypedExpr::NegateBool { value, .. } | UntypedExpr::NegateInt { value, .. } => {
-            **value = substitute_expr(value, env);
+            **value = substitute_expr(value, env)?;
         }
         UntypedExpr::List { elements, tail, .. } => {
             for e in elements {
-                *e = substitute_expr(e, env);
+                *e = substitute_expr(e, env)?;
             }
             if let Some(t) = tail {
-                **t = substitute_expr(t, env);
+                **t = substitute_expr(t, env)?;
             }
         }
         UntypedExpr::Tuple { elements, .. } => {
             for e in elements {
-                *e = substitute_expr(e, env);
+                *e = substitute_expr(e, env)?;
+            }
+        }
+        UntypedExpr::Block { statements, .. } => substitute_statements(statements.iter_mut(), env)?,
+        UntypedExpr::Fn {
+            arguments, body, ..
+        } => {
+            let mut inner = env.clone();
+            for argument in arguments.iter() {
+                if let Some(name) = argument.names.get_variable_name() {
+                    inner.remove(name);
+                }
             }
+            substitute_statements(body.iter_mut(), &inner)?;
         }
-        UntypedExpr::Block { statements, .. } => {
-            for s in statements {
-                *s = substitute_statement(s, env);
+        UntypedExpr::Echo {
+            expression,
+            message,
+            ..
+        } => {
+            for child in [expression, message].into_iter().flatten() {
+                **child = substitute_expr(child, env)?;
             }
         }
         UntypedExpr::Case {
             subjects, clauses, ..
         } => {
             for s in subjects {
-                *s = substitute_expr(s, env);
+                *s = substitute_expr(s, env)?;
             }
             if let Some(clauses) = clauses {
                 for clause in clauses {
@@ -901,28 +1385,63 @@ pub fn substitute_expr(expr: &UntypedExpr, env: &HashMap<EcoString, UntypedExpr>
                     for pat in &clause.pattern {
                         remove_pattern_names(pat, &mut inner);
                     }
-                    clause.then = substitute_expr(&clause.then, &inner);
+                    if let Some(guard) = &mut clause.guard {
+                        *guard = substitute_guard(guard, &inner)?;
+                    }
+                    clause.then = substitute_expr(&clause.then, &inner)?;
                 }
             }
         }
         _ => {}
     }
-    res
+    Ok(res)
+}
+
+/// Substitutes in a sequence of statements, where the names bound by each
+/// statement shadow `env` in the following ones.
+fn substitute_statements<'a>(
+    statements: impl Iterator<Item = &'a mut UntypedStatement>,
+    env: &HashMap<EcoString, UntypedExpr>,
+) -> Result<(), SubstitutionError> {
+    let mut env = env.clone();
+    for statement in statements {
+        *statement = substitute_statement(statement, &env)?;
+        match statement {
+            Statement::Assignment(assignment) => {
+                remove_pattern_names(&assignment.pattern, &mut env)
+            }
+            Statement::Use(use_) => {
+                for assignment in &use_.assignments {
+                    remove_pattern_names(&assignment.pattern, &mut env);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
 }
 
 fn substitute_statement(
     stmt: &UntypedStatement,
     env: &HashMap<EcoString, UntypedExpr>,
-) -> UntypedStatement {
+) -> Result<UntypedStatement, SubstitutionError> {
     let mut res = stmt.clone();
     match &mut res {
-        Statement::Expression(expr) => *expr = substitute_expr(expr, env),
+        Statement::Expression(expr) => *expr = substitute_expr(expr, env)?,
         Statement::Assignment(assignment) => {
-            assignment.value = substitute_expr(&assignment.value, env);
+            assignment.value = substitute_expr(&assignment.value, env)?;
+            if let AssignmentKind::Assert {
+                message: Some(message),
+                ..
+            } = &mut assignment.kind
+            {
+                *message = substitute_expr(message, env)?;
+            }
         }
+        Statement::Use(use_) => *use_.call = substitute_expr(&use_.call, env)?,
         _ => {}
     }
-    res
+    Ok(res)
 }
 
 fn remove_pattern_names(pattern: &Pattern<()>, env: &mut HashMap<EcoString, UntypedExpr>) {
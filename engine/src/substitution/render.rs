@@ -1,7 +1,12 @@
-use gleam_core::ast::UntypedExpr;
+use std::ops::Range;
 
-use super::SubstitutionError;
-use crate::format::format_source;
+use gleam_core::ast::{Statement, UntypedExpr};
+
+use super::{SubstitutionError, guard::guard_to_expr};
+use crate::{
+    format::format_source,
+    parser::{ReplItem, parse_repl},
+};
 
 /// Renders an `UntypedExpr` to formatted Gleam source text.
 ///
@@ -30,6 +35,169 @@ pub fn render_function(
         .map_err(|_| SubstitutionError::FormattingError)
 }
 
+/// Returns the byte range in `formatted` (the rendering of `expr`) of the
+/// subexpression of `expr` at `path`.
+///
+/// The formatter may reflow the code, so the span is found by parsing
+/// `formatted` and following `path` in the parsed expression.
+pub fn span_at(formatted: &str, expr: &UntypedExpr, path: &[usize]) -> Option<Range<usize>> {
+    let items = parse_repl(formatted).ok()?;
+    let [ReplItem::ReplStatement(Statement::Expression(parsed))] = items.as_slice() else {
+        return None;
+    };
+    let mut original = transparent(expr);
+    let mut parsed = transparent(parsed);
+    for &index in path {
+        original = transparent(child(original, index)?);
+        parsed = transparent(child(parsed, index)?);
+    }
+    let location = parsed.location();
+    Some(location.start as usize..location.end as usize)
+}
+
+/// Returns the child `index` of `expr`: the function and then the arguments
+/// of a call, the operands of an operator, the subjects of a case, the
+/// elements and then the tail of a list, the value and then the message of an
+/// `echo`, and the expressions or assigned values of the statements of a
+/// block.
+pub fn child(expr: &UntypedExpr, index: usize) -> Option<&UntypedExpr> {
+    match expr {
+        UntypedExpr::Call { fun, arguments, .. } => match index {
+            0 => Some(fun),
+            _ => arguments.get(index - 1).map(|arg| &arg.value),
+        },
+        UntypedExpr::BinOp { left, right, .. } => match index {
+            0 => Some(left),
+            1 => Some(right),
+            _ => None,
+        },
+        UntypedExpr::NegateInt { value, .. } | UntypedExpr::NegateBool { value, .. } => {
+            (index == 0).then_some(value.as_ref())
+        }
+        UntypedExpr::FieldAccess { container, .. } => (index == 0).then_some(container.as_ref()),
+        UntypedExpr::Case { subjects, .. } => subjects.get(index),
+        UntypedExpr::List { elements, tail, .. } => elements
+            .get(index)
+            .or_else(|| tail.as_deref().filter(|_| index == elements.len())),
+        UntypedExpr::Tuple { elements, .. } => elements.get(index),
+        UntypedExpr::Echo {
+            expression,
+            message,
+            ..
+        } => match index {
+            0 => expression.as_deref(),
+            1 => message.as_deref(),
+            _ => None,
+        },
+        UntypedExpr::Block { statements, .. } => match statements.get(index)? {
+            Statement::Expression(expr) => Some(expr),
+            Statement::Assignment(assignment) => Some(&assignment.value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Single expression blocks are rendered without braces (or added as
+/// parentheses by the renderer), so they are not counted in paths.
+fn transparent(expr: &UntypedExpr) -> &UntypedExpr {
+    match expr {
+        UntypedExpr::Block { statements, .. } if statements.len() == 1 => {
+            match statements.first() {
+                Statement::Expression(inner) => transparent(inner),
+                _ => expr,
+            }
+        }
+        _ => expr,
+    }
+}
+
+/// Returns the subexpression of `expr` at `path`.
+pub fn subexpr_at<'a>(expr: &'a UntypedExpr, path: &[usize]) -> Option<&'a UntypedExpr> {
+    let mut expr = transparent(expr);
+    for &index in path {
+        expr = transparent(child(expr, index)?);
+    }
+    Some(expr)
+}
+
+/// Like `subexpr_at`, but mutable.
+pub fn subexpr_at_mut<'a>(
+    expr: &'a mut UntypedExpr,
+    path: &[usize],
+) -> Option<&'a mut UntypedExpr> {
+    let mut expr = transparent_mut(expr);
+    for &index in path {
+        expr = transparent_mut(child_mut(expr, index)?);
+    }
+    Some(expr)
+}
+
+fn child_mut(expr: &mut UntypedExpr, index: usize) -> Option<&mut UntypedExpr> {
+    match expr {
+        UntypedExpr::Call { fun, arguments, .. } => match index {
+            0 => Some(fun),
+            _ => arguments.get_mut(index - 1).map(|arg| &mut arg.value),
+        },
+        UntypedExpr::BinOp { left, right, .. } => match index {
+            0 => Some(left),
+            1 => Some(right),
+            _ => None,
+        },
+        UntypedExpr::NegateInt { value, .. } | UntypedExpr::NegateBool { value, .. } => {
+            (index == 0).then_some(value.as_mut())
+        }
+        UntypedExpr::FieldAccess { container, .. } => (index == 0).then_some(container.as_mut()),
+        UntypedExpr::Case { subjects, .. } => subjects.get_mut(index),
+        UntypedExpr::List { elements, tail, .. } => {
+            if index < elements.len() {
+                elements.get_mut(index)
+            } else {
+                tail.as_deref_mut().filter(|_| index == elements.len())
+            }
+        }
+        UntypedExpr::Tuple { elements, .. } => elements.get_mut(index),
+        UntypedExpr::Echo {
+            expression,
+            message,
+            ..
+        } => match index {
+            0 => expression.as_deref_mut(),
+            1 => message.as_deref_mut(),
+            _ => None,
+        },
+        UntypedExpr::Block { statements, .. } => match statements.get_mut(index)? {
+            Statement::Expression(expr) => Some(expr),
+            Statement::Assignment(assignment) => Some(&mut assignment.value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn transparent_mut(expr: &mut UntypedExpr) -> &mut UntypedExpr {
+    let single = matches!(
+        expr,
+        UntypedExpr::Block { statements, .. }
+            if statements.len() == 1 && matches!(statements.first(), Statement::Expression(_))
+    );
+    if !single {
+        return expr;
+    }
+    let UntypedExpr::Block { statements, .. } = expr else {
+        unreachable!();
+    };
+    let Statement::Expression(inner) = statements.first_mut() else {
+        unreachable!();
+    };
+    transparent_mut(inner)
+}
+
+/// Renders a pattern as Gleam source text (without formatting).
+pub fn render_pattern(pattern: &gleam_core::ast::UntypedPattern) -> String {
+    raw_render_pattern(pattern)
+}
+
 fn format_expr_source(source: &str) -> Result<String, SubstitutionError> {
     let wrapped = format!("pub fn main() {{\n{source}\n}}\n");
     let formatted = format_source(&wrapped)?;
@@ -53,6 +221,15 @@ fn raw_render_expr(expr: &UntypedExpr) -> String {
         UntypedExpr::Float { value, .. } => value.to_string(),
         UntypedExpr::String { value, .. } => format!("\"{}\"", escape_string(value)),
         UntypedExpr::Var { name, .. } => name.to_string(),
+        // Library function references keep the module path in the container.
+        UntypedExpr::FieldAccess {
+            label, container, ..
+        } => match container.as_ref() {
+            UntypedExpr::Var { name: module, .. } => {
+                super::qualified_display_name(module, label).to_string()
+            }
+            container => format!("{}.{label}", raw_render_child(container, Prec::Atomic)),
+        },
 
         UntypedExpr::NegateInt { value, .. } => {
             let inner = raw_render_child(value, Prec::Prefix);
@@ -128,27 +305,97 @@ fn raw_render_expr(expr: &UntypedExpr) -> String {
             }
         }
 
+        UntypedExpr::Fn {
+            arguments, body, ..
+        } => {
+            let arguments: Vec<String> = arguments.iter().map(raw_render_arg).collect();
+            let body: Vec<String> = body.iter().map(raw_render_statement).collect();
+            format!("fn({}) {{\n{}\n}}", arguments.join(", "), body.join("\n"))
+        }
+
+        UntypedExpr::Echo {
+            expression,
+            message,
+            ..
+        } => {
+            let mut rendered = String::from("echo");
+            if let Some(expression) = expression {
+                rendered = format!("{rendered} {}", raw_render_expr(expression));
+            }
+            if let Some(message) = message {
+                rendered = format!("{rendered} as {}", raw_render_child(message, Prec::Atomic));
+            }
+            rendered
+        }
+
+        UntypedExpr::Panic { message, .. } => match message {
+            Some(message) => format!("panic as {}", raw_render_expr(message)),
+            None => "panic".to_string(),
+        },
+
         _ => "todo /* unsupported expression */".to_string(),
     }
 }
 
 fn raw_render_statement(stmt: &gleam_core::ast::UntypedStatement) -> String {
-    use gleam_core::ast::Statement;
     match stmt {
         Statement::Expression(expr) => raw_render_expr(expr),
         Statement::Assignment(assignment) => {
+            use gleam_core::ast::AssignmentKind;
             let pattern = raw_render_pattern(&assignment.pattern);
             let value = raw_render_expr(&assignment.value);
-            format!("let {pattern} = {value}")
+            match &assignment.kind {
+                AssignmentKind::Assert {
+                    message: Some(message),
+                    ..
+                } => format!(
+                    "let assert {pattern} = {value} as {}",
+                    raw_render_expr(message)
+                ),
+                AssignmentKind::Assert { message: None, .. } => {
+                    format!("let assert {pattern} = {value}")
+                }
+                _ => format!("let {pattern} = {value}"),
+            }
+        }
+        Statement::Use(use_) => {
+            let patterns: Vec<String> = use_
+                .assignments
+                .iter()
+                .map(|assignment| raw_render_pattern(&assignment.pattern))
+                .collect();
+            let call = raw_render_expr(&use_.call);
+            if patterns.is_empty() {
+                format!("use <- {call}")
+            } else {
+                format!("use {} <- {call}", patterns.join(", "))
+            }
         }
         _ => "todo /* unsupported statement */".to_string(),
     }
 }
 
+fn raw_render_arg(arg: &gleam_core::ast::Arg<()>) -> String {
+    use gleam_core::ast::ArgNames;
+    match &arg.names {
+        ArgNames::Discard { name, .. }
+        | ArgNames::LabelledDiscard { name, .. }
+        | ArgNames::Named { name, .. }
+        | ArgNames::NamedLabelled { name, .. } => name.to_string(),
+    }
+}
+
 fn raw_render_clause(clause: &gleam_core::ast::UntypedClause) -> String {
     let patterns: Vec<String> = clause.pattern.iter().map(raw_render_pattern).collect();
     let then = raw_render_expr(&clause.then);
-    format!("  {} -> {then}", patterns.join(", "))
+    match &clause.guard {
+        Some(guard) => format!(
+            "  {} if {} -> {then}",
+            patterns.join(", "),
+            raw_render_expr(&guard_to_expr(guard))
+        ),
+        None => format!("  {} -> {then}", patterns.join(", ")),
+    }
 }
 
 fn raw_render_pattern(pattern: &gleam_core::ast::UntypedPattern) -> String {
@@ -224,8 +471,10 @@ fn expr_prec(expr: &UntypedExpr) -> Prec {
         | UntypedExpr::Float { .. }
         | UntypedExpr::String { .. }
         | UntypedExpr::Var { .. }
+        | UntypedExpr::FieldAccess { .. }
         | UntypedExpr::List { .. }
-        | UntypedExpr::Tuple { .. } => Prec::Atomic,
+        | UntypedExpr::Tuple { .. }
+        | UntypedExpr::Fn { .. } => Prec::Atomic,
         UntypedExpr::Call { .. } => Prec::Call,
         UntypedExpr::NegateInt { .. } | UntypedExpr::NegateBool { .. } => Prec::Prefix,
         UntypedExpr::BinOp { name, .. } => bin_op_prec(*name),
@@ -1,6 +1,6 @@
 //! Scope-aware visitor for finding variable references in a typed expression.
-//! Used by `:stepper` to detect references to REPL runtime variables that the
-//! substitution evaluator cannot resolve.
+//! Used by `:stepper` to find the REPL runtime variables whose values must be
+//! fetched from the engine before the expression is evaluated.
 
 use std::collections::HashSet;
 
@@ -10,18 +10,23 @@ use gleam_core::ast::{
     visit::{Visit, visit_typed_expr},
 };
 
-/// Returns the name of the first variable referenced by `expr` that is in
-/// `target_names`, respecting local scoping (`let` bindings, function
-/// arguments, and case clause patterns shadow names from `target_names`).
-pub fn find_runtime_var_ref(expr: &TypedExpr, target_names: &HashSet<String>) -> Option<String> {
+/// Returns the names of the variables referenced by `expr` that are in
+/// `target_names`, in order of first reference, respecting local scoping
+/// (`let` bindings, function arguments, and case clause patterns shadow names
+/// from `target_names`).
+pub fn find_runtime_var_refs(expr: &TypedExpr, target_names: &HashSet<String>) -> Vec<String> {
     let mut visitor = ReferencedVarVisitor::new(target_names);
     visitor.visit_typed_expr(expr);
-    visitor.found.map(|name| name.to_string())
+    visitor
+        .found
+        .into_iter()
+        .map(|name| name.to_string())
+        .collect()
 }
 
 struct ReferencedVarVisitor {
     targets: HashSet<EcoString>,
-    found: Option<EcoString>,
+    found: Vec<EcoString>,
 }
 
 impl ReferencedVarVisitor {
@@ -32,7 +37,7 @@ impl ReferencedVarVisitor {
             .collect();
         Self {
             targets,
-            found: None,
+            found: vec![],
         }
     }
 
@@ -45,9 +50,6 @@ impl ReferencedVarVisitor {
     fn visit_statements(&mut self, statements: &[TypedStatement]) {
         for statement in statements {
             self.visit_typed_statement(statement);
-            if self.found.is_some() {
-                return;
-            }
         }
     }
 
@@ -107,14 +109,10 @@ impl ReferencedVarVisitor {
 
 impl<'ast> Visit<'ast> for ReferencedVarVisitor {
     fn visit_typed_expr(&mut self, expr: &'ast TypedExpr) {
-        if self.found.is_some() {
-            return;
-        }
-
         match expr {
             TypedExpr::Var { name, .. } => {
-                if self.targets.contains(name) {
-                    self.found = Some(name.clone());
+                if self.targets.contains(name) && !self.found.contains(name) {
+                    self.found.push(name.clone());
                 }
             }
             TypedExpr::Block { statements, .. } => {
@@ -137,9 +135,6 @@ impl<'ast> Visit<'ast> for ReferencedVarVisitor {
             } => {
                 for subject in subjects {
                     self.visit_typed_expr(subject);
-                    if self.found.is_some() {
-                        return;
-                    }
                 }
 
                 for clause in clauses {
@@ -154,9 +149,6 @@ impl<'ast> Visit<'ast> for ReferencedVarVisitor {
                         }
                         this.visit_typed_expr(&clause.then);
                     });
-                    if self.found.is_some() {
-                        return;
-                    }
                 }
             }
             _ => visit_typed_expr(self, expr),
@@ -164,10 +156,6 @@ impl<'ast> Visit<'ast> for ReferencedVarVisitor {
     }
 
     fn visit_typed_statement(&mut self, statement: &'ast TypedStatement) {
-        if self.found.is_some() {
-            return;
-        }
-
         match statement {
             TypedStatement::Expression(expr) => self.visit_typed_expr(expr),
             TypedStatement::Assignment(assignment) => {
@@ -1,11 +1,15 @@
 use camino::Utf8PathBuf;
 use ecow::EcoString;
-use gleam_core::ast::{
-    TypedExpr, TypedStatement,
-    visit::{Visit, visit_typed_expr, visit_typed_statement},
+use gleam_core::{
+    ast::{
+        AssignmentKind, FunctionLiteralKind, SrcSpan, Statement, TypedClause, TypedExpr,
+        TypedStatement,
+        visit::{Visit, visit_typed_expr, visit_typed_statement},
+    },
+    type_::ModuleValueConstructor,
 };
 
-use super::SubstitutionError;
+use super::{SubstitutionError, guard::unsupported_guard};
 
 pub struct Validator {
     pub src: EcoString,
@@ -38,7 +42,24 @@ impl Validator {
         Ok(())
     }
 
-    fn unsupported(&mut self, kind: &str, location: gleam_core::ast::SrcSpan) {
+    /// The span of a clause guard, starting at its `if` keyword.
+    fn guard_location(&self, clause: &TypedClause, guard: SrcSpan) -> SrcSpan {
+        let pattern_end = clause
+            .pattern
+            .last()
+            .map(|p| p.location().end)
+            .unwrap_or(clause.location.start);
+
+        let snippet = &self.src[pattern_end as usize..guard.end as usize];
+        let if_offset = snippet.find("if").unwrap_or(0);
+
+        SrcSpan {
+            start: pattern_end + if_offset as u32,
+            end: guard.end,
+        }
+    }
+
+    fn unsupported(&mut self, kind: &str, location: SrcSpan) {
         self.error = Some(SubstitutionError::UnsupportedFeature {
             kind: kind.into(),
             location,
@@ -61,6 +82,17 @@ impl<'a> Visit<'a> for Validator {
             TypedExpr::RecordUpdate { location, .. } => {
                 self.unsupported("record updates", *location);
             }
+            TypedExpr::Fn {
+                kind: FunctionLiteralKind::Use { .. },
+                body,
+                ..
+            } => {
+                // The assignments generated for the patterns of a `use` are
+                // created again when it is desugared.
+                for statement in body.iter().skip_while(|s| is_generated_assignment(s)) {
+                    self.visit_typed_statement(statement);
+                }
+            }
             TypedExpr::Fn { location, .. } => {
                 self.unsupported("anonymous functions", *location);
             }
@@ -70,8 +102,12 @@ impl<'a> Visit<'a> for Validator {
             TypedExpr::TupleIndex { location, .. } => {
                 self.unsupported("tuple indexing", *location);
             }
-            TypedExpr::Echo { location, .. } => {
-                self.unsupported("echo statements", *location);
+            TypedExpr::Echo {
+                location,
+                expression: None,
+                ..
+            } => {
+                self.unsupported("echo in pipelines", *location);
             }
             TypedExpr::Pipeline { .. } => {
                 self.unsupported("pipelines", expr.location());
@@ -82,27 +118,20 @@ impl<'a> Visit<'a> for Validator {
             TypedExpr::Panic { location, .. } => {
                 self.unsupported("panic", *location);
             }
-            TypedExpr::ModuleSelect { location, .. } => {
-                self.unsupported("module access", *location);
+            TypedExpr::ModuleSelect {
+                location,
+                constructor: ModuleValueConstructor::Constant { .. },
+                ..
+            } => {
+                self.unsupported("module constants", *location);
             }
             TypedExpr::Case { clauses, .. } => {
                 for clause in clauses {
-                    if let Some(guard) = &clause.guard {
-                        let loc = guard.location();
-                        let pattern_end = clause
-                            .pattern
-                            .last()
-                            .map(|p| p.location().end)
-                            .unwrap_or(clause.location.start);
-
-                        let snippet = &self.src[pattern_end as usize..loc.end as usize];
-                        let if_offset = snippet.find("if").unwrap_or(0);
-
-                        let location = gleam_core::ast::SrcSpan {
-                            start: pattern_end + if_offset as u32,
-                            end: loc.end,
-                        };
-                        self.unsupported("case guards", location);
+                    if let Some(guard) = &clause.guard
+                        && let Some(kind) = unsupported_guard(guard)
+                    {
+                        let location = self.guard_location(clause, guard.location());
+                        self.unsupported(kind, location);
                         return;
                     }
                 }
@@ -118,17 +147,14 @@ impl<'a> Visit<'a> for Validator {
         }
 
         match stmt {
-            TypedStatement::Use(u) => {
-                self.unsupported("use statements", u.location);
-            }
-            TypedStatement::Assignment(assignment) => {
-                if matches!(assignment.kind, gleam_core::ast::AssignmentKind::Generated) {
-                    self.unsupported("generated assignments", assignment.location);
-                    return;
-                }
-                visit_typed_statement(self, stmt);
+            Statement::Assignment(assignment) if is_generated_assignment(stmt) => {
+                self.unsupported("generated assignments", assignment.location);
             }
             _ => visit_typed_statement(self, stmt),
         }
     }
 }
+
+fn is_generated_assignment(stmt: &TypedStatement) -> bool {
+    matches!(stmt, Statement::Assignment(a) if matches!(a.kind, AssignmentKind::Generated))
+}
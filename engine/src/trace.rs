@@ -0,0 +1,209 @@
+use gleam_core::io::{FileSystemReader, FileSystemWriter, memory::InMemoryFileSystem};
+
+use crate::{
+    gleam::{Project, is_library_module},
+    swriteln,
+};
+
+use std::fmt::Write as _;
+
+// Imported under a name that cannot clash with the Gleam generated code.
+const TRACE_IMPORT: &str = "trace_call as trace$call";
+const UNTRACED_SUFFIX: &str = "$untraced";
+
+/// Rewrites the JavaScript of `module` (in the build directory) so calls to
+/// its top-level functions accepted by `filter` go through `trace_call`,
+/// which prints them when they are traced and counts them when profiling.
+/// The calls are identified by `label`, the module name of the functions.
+/// Returns the names of the instrumented functions. Modules already
+/// instrumented are not changed.
+pub fn instrument_module(
+    fs: &mut InMemoryFileSystem,
+    module: &str,
+    label: &str,
+    filter: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let path = Project::out().join(format!("{module}.mjs"));
+    let Ok(js) = fs.read(&path) else {
+        return vec![];
+    };
+    if js.contains(TRACE_IMPORT) {
+        return vec![];
+    }
+    let (js, names) = instrument_source(module, label, &js, filter);
+    if !names.is_empty() {
+        fs.write(&path, &js).expect("Write a file in memory");
+    }
+    names
+}
+
+/// Instruments the user modules of the build directory, that is, the ones
+/// that are not part of the library. Returns the names of the instrumented
+/// functions.
+pub fn instrument_user_modules(
+    fs: &mut InMemoryFileSystem,
+    filter: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let mut names = vec![];
+    for module in user_modules(fs) {
+        names.extend(instrument_module(fs, &module, &module, &filter));
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Returns the user modules of the build directory, the ones compiled from
+/// a Gleam source that is not part of the library.
+pub fn user_modules(fs: &InMemoryFileSystem) -> Vec<String> {
+    fs.files()
+        .into_iter()
+        .filter_map(|path| {
+            let module = path
+                .strip_prefix(Project::out())
+                .ok()?
+                .as_str()
+                .strip_suffix(".mjs")?
+                .to_string();
+            let source = Project::source().join(format!("{module}.gleam"));
+            (!is_library_module(&module) && fs.is_file(&source)).then_some(module)
+        })
+        .collect()
+}
+
+/// Writes `js`, the JavaScript of `module` as generated by the compiler, to
+/// the build directory with the calls to the functions accepted by `filter`
+/// instrumented as in `instrument_module`.
+pub fn write_instrumented(
+    fs: &mut InMemoryFileSystem,
+    module: &str,
+    js: &str,
+    filter: impl Fn(&str) -> bool,
+) {
+    let (js, _) = instrument_source(module, module, js, filter);
+    let path = Project::out().join(format!("{module}.mjs"));
+    fs.write(&path, &js).expect("Write a file in memory");
+}
+
+/// Returns the Gleam names of the top-level functions of `js`.
+pub fn function_names(js: &str) -> Vec<String> {
+    instrument_source("", "", js, |_| true).1
+}
+
+fn instrument_source(
+    module: &str,
+    label: &str,
+    js: &str,
+    filter: impl Fn(&str) -> bool,
+) -> (String, Vec<String>) {
+    let mut names = vec![];
+    let mut wrappers = String::new();
+    let mut out = String::new();
+    for line in js.split_inclusive('\n') {
+        let (export, rest) = match line.strip_prefix("export ") {
+            Some(rest) => ("export ", rest),
+            None => ("", line),
+        };
+        let function = rest
+            .strip_prefix("function ")
+            .and_then(|rest| rest.split_once('('))
+            .filter(|(name, _)| is_identifier(name));
+        match function {
+            Some((js_name, params)) if filter(gleam_name(js_name)) => {
+                let name = gleam_name(js_name);
+                out.push_str(&format!("function {js_name}{UNTRACED_SUFFIX}({params}"));
+                swriteln!(
+                    wrappers,
+                    r#"{export}function {js_name}(...args) {{ return trace$call("{label}", "{name}", {js_name}{UNTRACED_SUFFIX}, args); }}"#
+                );
+                names.push(name.to_string());
+            }
+            _ => out.push_str(line),
+        }
+    }
+    if names.is_empty() {
+        return (js.into(), names);
+    }
+
+    // Paths are relative to the module, which is in a subdirectory of the
+    // build directory for each `/` in its name. The import is hoisted, so it
+    // is added at the end to keep the lines of the source map.
+    let ffi = "../".repeat(module.matches('/').count());
+    let ffi = if ffi.is_empty() { "./".into() } else { ffi };
+    let mut src = out;
+    if !src.ends_with('\n') {
+        src.push('\n');
+    }
+    src.push_str(&wrappers);
+    swriteln!(
+        src,
+        "import {{ {TRACE_IMPORT} }} from \"{ffi}sgleam/sgleam_ffi.mjs\";"
+    );
+    (src, names)
+}
+
+/// Returns the Gleam name of a generated function, removing the suffix of
+/// the instrumented functions and the `$` added by the code generator to
+/// names that are reserved in JavaScript.
+pub fn gleam_name(js_name: &str) -> &str {
+    let js_name = js_name.strip_suffix(UNTRACED_SUFFIX).unwrap_or(js_name);
+    js_name.strip_suffix('$').unwrap_or(js_name)
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Returns the JavaScript array with `names`, used to set the traced
+/// functions with `call_function`.
+pub fn names_array(names: &[&str]) -> String {
+    let names: Vec<_> = names.iter().map(|name| format!("\"{name}\"")).collect();
+    format!("[{}]", names.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instrument_wraps_the_filtered_functions() {
+        let js = "import * as $int from \"../gleam/int.mjs\";\n\
+                  \n\
+                  export function fact(n) {\n  return n * fact(n - 1);\n}\n\
+                  \n\
+                  function helper(class$) {\n  return class$;\n}\n";
+        let (src, names) =
+            instrument_source("tests/fact", "tests/fact", js, |name| name != "helper");
+        assert_eq!(names, vec!["fact"]);
+        assert_eq!(
+            src,
+            "import * as $int from \"../gleam/int.mjs\";\n\
+             \n\
+             function fact$untraced(n) {\n  return n * fact(n - 1);\n}\n\
+             \n\
+             function helper(class$) {\n  return class$;\n}\n\
+             export function fact(...args) { return trace$call(\"tests/fact\", \"fact\", fact$untraced, args); }\n\
+             import { trace_call as trace$call } from \"../sgleam/sgleam_ffi.mjs\";\n"
+        );
+    }
+
+    #[test]
+    fn instrument_uses_the_gleam_names() {
+        let (src, names) = instrument_source("m", "", "function then$(x) {\n}\n", |_| true);
+        assert_eq!(names, vec!["then"]);
+        assert!(src.contains("import { trace_call as trace$call } from \"./sgleam/"));
+        assert!(src.contains("trace$call(\"\", \"then\", then$$untraced, args)"));
+    }
+
+    #[test]
+    fn instrument_without_functions_keeps_the_source() {
+        let js = "export const x = 1;\n";
+        assert_eq!(
+            instrument_source("m", "m", js, |_| true),
+            (js.into(), vec![])
+        );
+    }
+}
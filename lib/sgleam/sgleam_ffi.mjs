@@ -2,6 +2,12 @@ import { isEqual, List } from "../gleam.mjs";
 import { inspect } from "../gleam/string.mjs";
 import { to_svg } from "../sgleam/image.mjs";
 
+// The whole stack is needed to summarize the recursion of a stack overflow.
+// An error captures only the frames on the stack when it is created, so the
+// limit matters only for errors of deep recursions. The engine stack runs out
+// at less than half of it, as measured by the `stack_overflow_summary` test.
+Error.stackTraceLimit = 1000000;
+
 export function try_main(main, input_kind, show_output) {
     try {
         let r;
@@ -78,6 +84,15 @@ export function repl_load(index) {
     return globalThis.repl_vars[index];
 }
 
+// Moves the last saved value to `index`.
+export function repl_replace(index) {
+    globalThis.repl_vars[index] = globalThis.repl_vars.pop();
+}
+
+export function repl_reset() {
+    globalThis.repl_vars = [];
+}
+
 const IMAGE_TYPE_NAMES = new Set([
     "Path",
     "Combination",
@@ -99,6 +114,99 @@ export function repl_print(value) {
     return value;
 }
 
+const traced_functions = new Set();
+let trace_depth = 0;
+
+export function trace_functions(names) {
+    traced_functions.clear();
+    for (const name of names) {
+        traced_functions.add(name);
+    }
+}
+
+// The profiling is done here, and not in a function called by it, so a call
+// to an instrumented function adds a single frame to the stack.
+export function trace_call(module, name, fun, args) {
+    if (profile === null) {
+        return traced_call(name, fun, args);
+    }
+    const key = `${module}:${name}`;
+    let f = profile.functions.get(key);
+    if (f === undefined) {
+        f = { module, name, calls: 0, total: 0, self: 0, depth: 0, max_depth: 0 };
+        profile.functions.set(key, f);
+    }
+    f.calls += 1;
+    f.depth += 1;
+    f.max_depth = Math.max(f.max_depth, f.depth);
+    const frame = { children: 0 };
+    profile.stack.push(frame);
+    const start = sgleam.clock();
+    try {
+        return traced_functions.has(name) ? traced_call(name, fun, args) : fun(...args);
+    } finally {
+        const elapsed = sgleam.clock() - start;
+        profile.stack.pop();
+        f.depth -= 1;
+        // The time of recursive calls is already in the outermost one.
+        if (f.depth === 0) {
+            f.total += elapsed;
+        }
+        f.self += elapsed - frame.children;
+        const parent = profile.stack[profile.stack.length - 1];
+        if (parent !== undefined) {
+            parent.children += elapsed;
+        }
+    }
+}
+
+function traced_call(name, fun, args) {
+    if (!traced_functions.has(name)) {
+        return fun(...args);
+    }
+    const indent = "| ".repeat(trace_depth);
+    console.log(`${indent}> ${name}(${args.map(inspect).join(", ")})`);
+    trace_depth += 1;
+    let result;
+    try {
+        result = fun(...args);
+    } finally {
+        trace_depth -= 1;
+    }
+    console.log(`${indent}< ${inspect(result)}`);
+    return result;
+}
+
+// The calls of each function and the stack of active calls while profiling.
+let profile = null;
+
+export function profile_start() {
+    profile = { functions: new Map(), stack: [] };
+}
+
+export function profile_stop() {
+    const functions = profile?.functions ?? new Map();
+    profile = null;
+    return [...functions.values()]
+        .map((f) =>
+            [f.module, f.name, f.calls, f.total, f.self, f.max_depth].join(":")
+        )
+        .join(";");
+}
+
+// The number of executions of each probe of the code instrumented for
+// coverage.
+const coverage = [];
+
+export function coverage_hit(probe) {
+    const index = Number(probe);
+    coverage[index] = (coverage[index] ?? 0) + 1;
+}
+
+export function coverage_counts() {
+    return Array.from(coverage, (count) => count ?? 0).join(",");
+}
+
 export function show_svg(svg) {
     if (sgleam.draw_svg) {
         sgleam.draw_svg(svg);
@@ -120,7 +228,7 @@ export function check_equal(a, b, path, function_name, line_number) {
             return false;
         }
     } catch (err) {
-        show_check_error(err, path, function_name, line_number);
+        show_error(err);
         globalThis.errors += 1;
         return false;
     }
@@ -162,7 +270,7 @@ export function check_approx(
             return false;
         }
     } catch (err) {
-        show_check_error(err, path, function_name, line_number);
+        show_error(err);
         globalThis.errors += 1;
         return false;
     }
@@ -180,16 +288,6 @@ function show_check_failure(a, b, tolerance, path, function_name, line_number) {
     }
 }
 
-function show_check_error(err, path, function_name, line_number) {
-    if (!err.gleam_error) {
-        err.gleam_error = true;
-        err.file = path;
-        err.fn = function_name;
-        err.line = line_number;
-    }
-    show_error(err);
-}
-
 function location(file, fname, line_number) {
     if (fname !== "") {
         return `${file} (${fname}:${line_number})`;
@@ -197,39 +295,28 @@ function location(file, fname, line_number) {
     return `${file}`;
 }
 
+// Errors are shown by the Rust side as diagnostics in the Gleam sources.
 function show_error(err) {
-    if (err.gleam_error) {
-        // remove src/
-        const file = err.file.slice(4);
-        console.log(`Error at ${location(file, err.fn, err.line)}`);
-        console.log(`  ${err.message}`);
-        for (const k in err) {
-            if (
-                ![
-                    "message",
-                    "gleam_error",
-                    "module",
-                    "file",
-                    "line",
-                    "function",
-                    "fn",
-                ].includes(k)
-            ) {
-                console.log(`  ${k}: ${err[k]}`);
-            }
-        }
-    } else if (err.message == "stack overflow") {
-        const stack = err.stack.split("\n").slice(-20, -3).reverse();
-        stack.push("    ...");
-        console.log("Stack overflow");
-        for (const f of stack) {
-            console.log(`${f.slice(2)}`);
-        }
+    if (err?.gleam_error) {
+        const value = "value" in err ? inspect(err.value) : null;
+        sgleam.show_gleam_error(err, value, err.stack ?? "");
+    } else if (is_stack_overflow(err)) {
+        // Summarized with the recursive calls collapsed.
+        sgleam.show_stack_overflow(err.stack ?? "");
     } else {
-        console.log(`${err}`);
+        // Shown in the Gleam sources of the stack frames.
+        sgleam.show_runtime_error(`${err}`, err?.stack ?? "");
     }
 }
 
+// QuickJS-ng throws a `RangeError`, older versions an `InternalError`.
+function is_stack_overflow(err) {
+    return (
+        err?.message === "Maximum call stack size exceeded" ||
+        err?.message === "stack overflow"
+    );
+}
+
 export function cos_deg(angle) {
     return Math.cos(angle * Math.PI / 180.0);
 }
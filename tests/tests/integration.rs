@@ -26,7 +26,7 @@ fn insta_settings() -> Settings {
 fn run_file_captured(path: &str) -> (String, String) {
     let path = Utf8PathBuf::from(path);
     capture_output(|| {
-        if let Err(err) = run_main(&[path.clone()]) {
+        if let Err(err) = run_main(&[path.clone()], &[], false) {
             show_error(&err);
         }
     })
@@ -35,7 +35,7 @@ fn run_file_captured(path: &str) -> (String, String) {
 fn run_tests_captured(path: &str) -> (String, String) {
     let path = Utf8PathBuf::from(path);
     capture_output(|| {
-        if let Err(err) = run_test(&[path.clone()], &[path.clone()]) {
+        if let Err(err) = run_test(&[path.clone()], &[path.clone()], false, None) {
             show_error(&err);
         }
     })
@@ -6,7 +6,8 @@ use engine::{
     gleam::{Project, get_module},
     quickjs::QuickJsEngine,
     repl::{Repl, ReplOutput},
-    substitution::{SubstitutionModule, SubstitutionStep},
+    stepper::build_stepper_source,
+    substitution::{StepperOptions, SubstitutionModule, SubstitutionStep, export::trace_json},
 };
 use gleam_core::build::Module;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -74,6 +75,10 @@ const REPL_QUIT: u32 = 2;
 
 #[cfg(target_arch = "wasm32")]
 mod stepper_ui {
+    use std::ops::Range;
+
+    use engine::substitution::{called_functions, lines_with_span, next_call};
+
     use super::SubstitutionStep;
 
     mod ffi {
@@ -94,6 +99,10 @@ mod stepper_ui {
     enum StepperKey {
         Next,
         Prev,
+        First,
+        Last,
+        NextCall,
+        NextFunction,
         Quit,
         None,
     }
@@ -104,11 +113,27 @@ mod stepper_ui {
             return;
         }
 
+        let functions = called_functions(steps);
+        let mut chosen = 0usize;
         let mut current = 0usize;
         loop {
-            render_stepper_ansi(steps, current);
+            render_stepper_ansi(steps, current, functions.get(chosen).copied());
 
             match read_key() {
+                StepperKey::First => current = 0,
+                StepperKey::Last => current = steps.len() - 1,
+                StepperKey::NextFunction => {
+                    if !functions.is_empty() {
+                        chosen = (chosen + 1) % functions.len();
+                    }
+                }
+                StepperKey::NextCall => {
+                    if let Some(function) = functions.get(chosen)
+                        && let Some(next) = next_call(steps, current, function)
+                    {
+                        current = next;
+                    }
+                }
                 StepperKey::Next => {
                     if current < steps.len() {
                         current += 1;
@@ -150,12 +175,16 @@ mod stepper_ui {
         match key.as_str() {
             "ArrowRight" | "ArrowDown" | "l" | "j" => StepperKey::Next,
             "ArrowLeft" | "ArrowUp" | "h" | "k" => StepperKey::Prev,
+            "Home" | "g" => StepperKey::First,
+            "End" | "e" | "G" => StepperKey::Last,
+            "n" => StepperKey::NextCall,
+            "f" => StepperKey::NextFunction,
             "q" | "Escape" => StepperKey::Quit,
             _ => StepperKey::None,
         }
     }
 
-    fn render_stepper_ansi(steps: &[SubstitutionStep], current: usize) {
+    fn render_stepper_ansi(steps: &[SubstitutionStep], current: usize, chosen: Option<&str>) {
         const FRAME_COLS: usize = 80;
         const BASE_CONTENT_ROWS: usize = 18;
 
@@ -176,7 +205,12 @@ mod stepper_ui {
         } else {
             " Stepper - Finished ".to_string()
         };
-        let help_str = " q: quit, arrows/hjkl: navigate ";
+        let help_str = match chosen {
+            Some(function) => format!(
+                " q: quit, arrows/hjkl: navigate, e: end, n: next {function}, f: next function "
+            ),
+            None => " q: quit, arrows/hjkl: navigate, e: end ".to_string(),
+        };
 
         let available_width = FRAME_COLS.saturating_sub(7);
         let col_left_w = available_width / 2;
@@ -204,11 +238,15 @@ mod stepper_ui {
             String::new()
         };
 
-        let left_lines: Vec<String> = left_step
-            .map(|s| s.formatted.lines().map(ToString::to_string).collect())
+        // The left side shows the redex of the transition to the right side,
+        // and the right side its result.
+        let redex = right_step.and_then(|s| s.redex.as_ref());
+        let left_lines: Vec<(&str, Option<Range<usize>>)> = left_step
+            .map(|s| lines_with_span(&s.formatted, redex))
             .unwrap_or_default();
-        let right_lines: Vec<String> = right_step
-            .map(|s| s.formatted.lines().map(ToString::to_string).collect())
+        let result = right_step.and_then(|s| s.result.as_ref());
+        let right_lines: Vec<(&str, Option<Range<usize>>)> = right_step
+            .map(|s| lines_with_span(&s.formatted, result))
             .unwrap_or_default();
         let context_lines: Vec<String> = right_step
             .and_then(|s| s.context.as_deref())
@@ -226,7 +264,7 @@ mod stepper_ui {
         }
 
         let title_trunc = truncate_with_ellipsis(&title_str, left_fill);
-        let help_trunc = truncate_with_ellipsis(help_str, right_fill);
+        let help_trunc = truncate_with_ellipsis(&help_str, right_fill);
         let left_pad = left_fill.saturating_sub(title_trunc.chars().count());
         let right_pad = right_fill.saturating_sub(help_trunc.chars().count());
 
@@ -275,14 +313,14 @@ mod stepper_ui {
         // Conteúdo Principal
         for i in 0..content_rows {
             out.push_str(&format!("{cyan}│ {reset}"));
-            if let Some(line) = left_lines.get(i) {
-                out.push_str(&highlight_gleam_padded(line, col_left_w));
+            if let Some((line, span)) = left_lines.get(i) {
+                out.push_str(&highlight_gleam_padded(line, span, REDEX_BG, col_left_w));
             } else {
                 out.push_str(&" ".repeat(col_left_w));
             }
             out.push_str(&format!("{cyan} │ {reset}"));
-            if let Some(line) = right_lines.get(i) {
-                out.push_str(&highlight_gleam_padded(line, col_right_w));
+            if let Some((line, span)) = right_lines.get(i) {
+                out.push_str(&highlight_gleam_padded(line, span, RESULT_BG, col_right_w));
             } else {
                 out.push_str(&" ".repeat(col_right_w));
             }
@@ -300,7 +338,7 @@ mod stepper_ui {
             for i in 0..context_rows {
                 out.push_str(&format!("{cyan}│ {reset}"));
                 if let Some(line) = context_lines.get(i) {
-                    out.push_str(&highlight_gleam_padded(line, full_width));
+                    out.push_str(&highlight_gleam_padded(line, &None, "", full_width));
                 } else {
                     out.push_str(&" ".repeat(full_width));
                 }
@@ -336,12 +374,31 @@ mod stepper_ui {
         out
     }
 
-    fn highlight_gleam_padded(input: &str, width: usize) -> String {
-        let chunks = highlight_gleam_chunks(input);
+    const REDEX_BG: &str = "\x1b[48;5;58m";
+    const RESULT_BG: &str = "\x1b[48;5;22m";
+
+    /// Highlights `input`, with the bytes in `span` on the background `bg`.
+    fn highlight_gleam_padded(
+        input: &str,
+        span: &Option<Range<usize>>,
+        bg: &str,
+        width: usize,
+    ) -> String {
+        let span = span.clone().unwrap_or(input.len()..input.len());
+        let parts = [
+            (&input[..span.start], ""),
+            (&input[span.clone()], bg),
+            (&input[span.end..], ""),
+        ];
+        let chunks = parts.into_iter().flat_map(|(text, bg)| {
+            highlight_gleam_chunks(text)
+                .into_iter()
+                .map(move |chunk| (chunk, bg))
+        });
         let mut out = String::new();
         let mut visible = 0usize;
 
-        for chunk in chunks {
+        for (chunk, bg) in chunks {
             if visible >= width {
                 break;
             }
@@ -367,7 +424,7 @@ mod stepper_ui {
                 kept
             };
 
-            out.push_str(&format!("{color}{text_to_print}\x1b[0m"));
+            out.push_str(&format!("{color}{bg}{text_to_print}\x1b[0m"));
         }
 
         if visible < width {
@@ -551,16 +608,13 @@ pub unsafe extern "C" fn repl_new(
     }
     let substitution_module = {
         let mut result = SubstitutionModule::default();
-        for module in modules
-            .iter()
-            .filter(|m| !m.name.starts_with("gleam/") && !m.name.starts_with("sgleam/"))
-        {
+        for module in &modules {
             result.merge(SubstitutionModule::from_module(module));
         }
-        Some(result)
+        result
     };
     let mut repl = Repl::new(project, module).expect("A repl");
-    repl.set_substitution_module(substitution_module);
+    repl.merge_substitution_module(substitution_module);
     Box::leak(Box::new(repl))
 }
 
@@ -609,6 +663,19 @@ pub unsafe extern "C" fn repl_run(repl: *mut Repl<QuickJsEngine>, ptr: *mut u8,
     ret
 }
 
+/// Steps through an expression in the REPL, like `:stepper`, and returns the
+/// steps as JSON for the front end to render.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn repl_stepper(
+    repl: *mut Repl<QuickJsEngine>,
+    ptr: *mut u8,
+    len: usize,
+) -> *mut std::ffi::c_char {
+    assert!(!repl.is_null());
+    let repl = unsafe { &mut *repl };
+    to_cstr(repl.stepper_json(&new_string(ptr, len)))
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn repl_destroy(repl: *mut Repl<QuickJsEngine>) {
     unsafe {
@@ -616,6 +683,37 @@ pub unsafe extern "C" fn repl_destroy(repl: *mut Repl<QuickJsEngine>) {
     };
 }
 
+// --- Stepper ---
+
+/// Steps through `main` of the module `code`, or through `expr` in the scope
+/// of the module if it is not empty, and returns the steps as JSON.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stepper(
+    code_ptr: *mut u8,
+    code_len: usize,
+    expr_ptr: *mut u8,
+    expr_len: usize,
+    config_ptr: *mut u8,
+    config_len: usize,
+) -> *mut std::ffi::c_char {
+    init();
+
+    let source = new_string(code_ptr, code_len);
+    let expr = new_string(expr_ptr, expr_len);
+    let config = new_string(config_ptr, config_len);
+
+    if parse_config_bigint(&config) {
+        gleam_core::javascript::set_bigint_enabled(true);
+    }
+
+    let expr = Some(expr.trim()).filter(|expr| !expr.is_empty());
+    let json = match build_stepper_source(&source, expr, StepperOptions::default()) {
+        Ok(steps) => trace_json(&steps, None),
+        Err(err) => trace_json(&[], Some(&error::error_to_string(&err))),
+    };
+    to_cstr(json)
+}
+
 // --- Completion ---
 
 fn is_break_char(c: char) -> bool {
@@ -13,6 +13,15 @@ interface WasmExports {
   ): number;
   repl_run(repl: number, ptr: number, len: number): number;
   repl_destroy(repl: number): void;
+  repl_stepper(repl: number, ptr: number, len: number): number;
+  stepper(
+    code_ptr: number,
+    code_len: number,
+    expr_ptr: number,
+    expr_len: number,
+    config_ptr: number,
+    config_len: number,
+  ): number;
   repl_complete?(
     repl: number,
     ptr: number,
@@ -223,6 +232,15 @@ function run(
   };
 }
 
+function stepperJson(ctx: ReplContext, input: string) {
+  const [ptr, len] = encodeString(ctx.exports, input);
+  const out = ctx.exports.repl_stepper(ctx.repl, ptr, len);
+  ctx.exports.string_deallocate(ptr, len);
+  const json = readCstr(ctx.exports, out);
+  ctx.exports.cstr_deallocate(out);
+  return JSON.parse(json);
+}
+
 function destroy(ctx: ReplContext): void {
   ctx.exports.repl_destroy(ctx.repl);
 }
@@ -315,6 +333,53 @@ Deno.test("stepper renders UI in stdout", async () => {
   destroy(ctx);
 });
 
+Deno.test("repl_stepper returns the steps as json", async () => {
+  const ctx = await newRepl();
+  run(ctx, "let x = 2");
+  const trace = stepperJson(ctx, "x * 3 + 1");
+  assertEquals(
+    trace.steps.map((step: { code: string }) => step.code),
+    ["2 * 3 + 1", "6 + 1", "7"],
+  );
+  assertEquals(trace.steps[0].redex, { start: 0, end: 5 });
+  assertEquals(trace.steps[1].result, { start: 0, end: 1 });
+  assertEquals(trace.error, undefined);
+  assertEquals(ctx.stdout.join(""), "", "expected no ANSI output");
+  destroy(ctx);
+});
+
+Deno.test("repl_stepper reports errors in json", async () => {
+  const ctx = await newRepl();
+  const trace = stepperJson(ctx, "1 + True");
+  assertEquals(trace.steps, []);
+  assertMatch(trace.error, /Type mismatch/);
+  destroy(ctx);
+});
+
+Deno.test("stepper steps main of a file", async () => {
+  const ctx = await loadWasm();
+  const source = "pub fn double(x) { x * 2 }\npub fn main() { double(4) }\n";
+  const [codePtr, codeLen] = encodeString(ctx.exports, source);
+  const [exprPtr, exprLen] = encodeString(ctx.exports, "");
+  const [cfgPtr, cfgLen] = encodeString(ctx.exports, "bigint=true");
+  const out = ctx.exports.stepper(
+    codePtr,
+    codeLen,
+    exprPtr,
+    exprLen,
+    cfgPtr,
+    cfgLen,
+  );
+  ctx.exports.string_deallocate(codePtr, codeLen);
+  ctx.exports.string_deallocate(exprPtr, exprLen);
+  ctx.exports.string_deallocate(cfgPtr, cfgLen);
+  const trace = JSON.parse(readCstr(ctx.exports, out));
+  ctx.exports.cstr_deallocate(out);
+  assertEquals(trace.steps[0].code, "double(4)");
+  assertEquals(trace.steps[1].note, "substitute call to double");
+  assertEquals(trace.steps.at(-1).code, "8");
+});
+
 Deno.test(":quit returns quit status", async () => {
   const ctx = await newRepl();
   const r = run(ctx, ":quit");